@@ -0,0 +1,862 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crossterm::event::KeyCode;
+use crossterm::{cursor, style, terminal, QueueableCommand};
+
+use btc_lib::net::{Fault, Subnet, DEFAULT_BAN_TIME};
+use btc_lib::prelude::*;
+use btc_lib::rpc;
+
+use super::{
+    bottom_rows, parse_hex, scroll_log, serve_rest, serve_rpc, stats_report, Advertised,
+    ClientCommand, PeerSort, Picker, Ui,
+};
+
+// A command typed at the prompt. Handlers are only called with between
+// min_args and max_args arguments; anything else prints the usage.
+pub(super) struct CommandSpec {
+    pub(super) name: &'static str,
+    pub(super) args: &'static str,
+    pub(super) min_args: usize,
+    pub(super) max_args: usize,
+    pub(super) description: &'static str,
+    pub(super) handler: fn(&mut Ui, &[&str]) -> io::Result<()>,
+}
+
+impl CommandSpec {
+    fn usage(&self) -> String {
+        match self.args {
+            "" => self.name.to_string(),
+            args => format!("{} {args}", self.name),
+        }
+    }
+}
+
+// Commands that draw on the terminal, which a daemon doesn't have.
+const TUI_COMMANDS: &[&str] = &["statusbar", "copy"];
+
+pub(super) const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "connect",
+        args: "<host>[:port] [norelay] [network]",
+        min_args: 1,
+        max_args: 3,
+        description: "Connect to a peer by IP address or host name, trying each address a \
+                      name resolves to. norelay asks it not to announce transactions, the \
+                      network picks the magic and the default port.",
+        handler: connect,
+    },
+    CommandSpec {
+        name: "disconnect",
+        args: "",
+        min_args: 0,
+        max_args: 0,
+        description: "Disconnect from the peer.",
+        handler: disconnect,
+    },
+    CommandSpec {
+        name: "peers",
+        args: "[--sort quality]",
+        min_args: 0,
+        max_args: 2,
+        description: "Show connected peers, best first with --sort quality.",
+        handler: peers,
+    },
+    CommandSpec {
+        name: "setban",
+        args: "<ip>[/mask] add|remove [seconds]",
+        min_args: 2,
+        max_args: 3,
+        description: "Ban an address or subnet for a time, a day by default, or lift a ban. \
+                      Banned peers are disconnected and not connected to again. Bans are \
+                      kept across restarts with --datadir.",
+        handler: setban,
+    },
+    CommandSpec {
+        name: "listbanned",
+        args: "",
+        min_args: 0,
+        max_args: 0,
+        description: "Show banned addresses and subnets.",
+        handler: listbanned,
+    },
+    CommandSpec {
+        name: "clearbanned",
+        args: "",
+        min_args: 0,
+        max_args: 0,
+        description: "Lift all bans.",
+        handler: clearbanned,
+    },
+    CommandSpec {
+        name: "exportbans",
+        args: "<path>",
+        min_args: 1,
+        max_args: 1,
+        description: "Write the bans to a file, in the layout of Core's banlist.json if it \
+                      ends in .json and as CSV otherwise.",
+        handler: exportbans,
+    },
+    CommandSpec {
+        name: "importbans",
+        args: "<path>",
+        min_args: 1,
+        max_args: 1,
+        description: "Add the bans in a file written by exportbans, or Core's banlist.json. \
+                      Subnets banned already keep their ban.",
+        handler: importbans,
+    },
+    CommandSpec {
+        name: "ping",
+        args: "[nonce]",
+        min_args: 0,
+        max_args: 1,
+        description: "Ping the peer, with a random nonce if none is given.",
+        handler: ping,
+    },
+    CommandSpec {
+        name: "getaddr",
+        args: "",
+        min_args: 0,
+        max_args: 0,
+        description: "Ask the peer for addresses.",
+        handler: getaddr,
+    },
+    CommandSpec {
+        name: "sendraw",
+        args: "<command> [hex payload]",
+        min_args: 1,
+        max_args: 2,
+        description: "Send a message with any command and payload.",
+        handler: sendraw,
+    },
+    CommandSpec {
+        name: "fault",
+        args: "<command> checksum|truncate <bytes>|delay <ms>|reorder|clear",
+        min_args: 2,
+        max_args: 3,
+        description: "Mangle outbound messages with a command, or stop doing so.",
+        handler: fault,
+    },
+    CommandSpec {
+        name: "advertise",
+        args: "<addr>|external|off",
+        min_args: 1,
+        max_args: 1,
+        description: "Advertise an address of ours to peers, or the one they see us at.",
+        handler: advertise,
+    },
+    CommandSpec {
+        name: "export",
+        args: "<path>",
+        min_args: 1,
+        max_args: 1,
+        description: "Write known addresses to a file, as JSON if it ends in .json and CSV \
+                      otherwise.",
+        handler: export,
+    },
+    CommandSpec {
+        name: "import",
+        args: "<path>",
+        min_args: 1,
+        max_args: 1,
+        description: "Add addresses from a file with one address per line.",
+        handler: import,
+    },
+    CommandSpec {
+        name: "record",
+        args: "start <path>|stop",
+        min_args: 1,
+        max_args: 2,
+        description: "Append announced inventory, addresses and blocks to a CSV file.",
+        handler: record,
+    },
+    CommandSpec {
+        name: "dump",
+        args: "<dir>|off",
+        min_args: 1,
+        max_args: 1,
+        description: "Write messages that fail to decode to a directory, with the peer, time \
+                      and error, for looking into later.",
+        handler: dump,
+    },
+    CommandSpec {
+        name: "notify",
+        args: "<addr>",
+        min_args: 1,
+        max_args: 1,
+        description: "Publish notifications of new blocks and transactions on an address.",
+        handler: notify,
+    },
+    CommandSpec {
+        name: "rpc",
+        args: "<addr> [user:password]",
+        min_args: 1,
+        max_args: 2,
+        description: "Serve JSON-RPC on an address.",
+        handler: rpc,
+    },
+    CommandSpec {
+        name: "rest",
+        args: "<addr>",
+        min_args: 1,
+        max_args: 1,
+        description: "Serve status and metrics over HTTP on an address.",
+        handler: rest,
+    },
+    CommandSpec {
+        name: "stats",
+        args: "[every <secs>|every off]",
+        min_args: 0,
+        max_args: 2,
+        description: "Show traffic statistics, or summarize bandwidth periodically.",
+        handler: stats,
+    },
+    CommandSpec {
+        name: "statusbar",
+        args: "",
+        min_args: 0,
+        max_args: 0,
+        description: "Toggle the status bar.",
+        handler: statusbar,
+    },
+    CommandSpec {
+        name: "session",
+        args: "save|load <path>",
+        min_args: 2,
+        max_args: 2,
+        description: "Save the peer, known addresses and settings to a file, or run the \
+                      commands in a saved one to pick up where it left off.",
+        handler: session,
+    },
+    CommandSpec {
+        name: "copy",
+        args: "",
+        min_args: 0,
+        max_args: 0,
+        description: "Pick a hash or address from the recent log with the up and down keys \
+                      and copy it to the clipboard with enter, or cancel with esc. The \
+                      terminal does the copying, which most support, also over SSH.",
+        handler: copy,
+    },
+    CommandSpec {
+        name: "expand",
+        args: "",
+        min_args: 0,
+        max_args: 0,
+        description: "Log the lines folded away from the last long inv or addr.",
+        handler: expand,
+    },
+    CommandSpec {
+        name: "alias",
+        args: "[<name> [commands]]",
+        min_args: 0,
+        max_args: usize::MAX,
+        description: "Define an alias for commands separated by ;, e.g. alias hs \"connect \
+                      203.0.113.1; getaddr\". Arguments given to an alias go to its last \
+                      command. Without commands shows aliases, which --datadir keeps across \
+                      restarts.",
+        handler: alias,
+    },
+    CommandSpec {
+        name: "unalias",
+        args: "<name>",
+        min_args: 1,
+        max_args: 1,
+        description: "Remove an alias.",
+        handler: unalias,
+    },
+    CommandSpec {
+        name: "help",
+        args: "[command]",
+        min_args: 0,
+        max_args: 1,
+        description: "List commands, or describe one.",
+        handler: help,
+    },
+];
+
+// Runs a line typed at the prompt, which can hold several commands
+// separated by semicolons. Only alias takes the whole line, so that it
+// can define ones with several commands.
+pub(super) fn run(ui: &mut Ui, line: &str) -> io::Result<()> {
+    if line.split_whitespace().next() == Some("alias") {
+        return run_one(ui, line, true);
+    }
+
+    let commands: Vec<&str> = line.split(';').collect();
+    for command in &commands {
+        if commands.len() > 1 && command.trim().is_empty() {
+            continue;
+        }
+        run_one(ui, command, true)?;
+    }
+    Ok(())
+}
+
+fn run_one(ui: &mut Ui, line: &str, expand_aliases: bool) -> io::Result<()> {
+    let mut words = line.split_whitespace();
+    let Some(name) = words.next() else {
+        ui.err("A command must be provided");
+        return Ok(());
+    };
+    let args: Vec<&str> = words.collect();
+
+    // Aliases are expanded once, so one that refers to another alias or
+    // itself can't loop.
+    if let Some(commands) = ui.aliases.get(name).filter(|_| expand_aliases) {
+        let mut commands: Vec<String> = commands.split(';').map(str::to_string).collect();
+        if let Some(last) = commands.last_mut() {
+            for arg in &args {
+                last.push(' ');
+                last.push_str(arg);
+            }
+        }
+        for command in commands.iter().filter(|c| !c.trim().is_empty()) {
+            run_one(ui, command, false)?;
+        }
+        return Ok(());
+    }
+
+    if ui.headless && TUI_COMMANDS.contains(&name) {
+        ui.err(format!("{name} needs the TUI"));
+        return Ok(());
+    }
+
+    match COMMANDS.iter().find(|spec| spec.name == name) {
+        Some(spec) if (spec.min_args..=spec.max_args).contains(&args.len()) => {
+            (spec.handler)(ui, &args)
+        }
+        Some(spec) => {
+            ui.err(format!("usage: {}", spec.usage()));
+            Ok(())
+        }
+        None => {
+            ui.err(format!("Unknown command \"{name}\", see help"));
+            Ok(())
+        }
+    }
+}
+
+fn usage(ui: &mut Ui, name: &str) {
+    if let Some(spec) = COMMANDS.iter().find(|spec| spec.name == name) {
+        ui.err(format!("usage: {}", spec.usage()));
+    }
+}
+
+fn parse_addr(ui: &mut Ui, addr: &str) -> Option<SocketAddr> {
+    match SocketAddr::from_str(addr) {
+        Ok(addr) => Some(addr),
+        Err(e) => {
+            ui.err(format!("Could not parse address \"{addr}\": {e}"));
+            None
+        }
+    }
+}
+
+fn connect(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    let mut relay = true;
+    let mut network = ui.default_network;
+    for option in &args[1..] {
+        match *option {
+            "norelay" => relay = false,
+            _ => match option.parse() {
+                Ok(n) => network = n,
+                Err(()) => {
+                    ui.err(format!("Unknown option \"{option}\""));
+                    return Ok(());
+                }
+            },
+        }
+    }
+
+    ui.send(ClientCommand::Connect {
+        addr: args[0].to_string(),
+        relay,
+        network,
+    });
+    Ok(())
+}
+
+fn disconnect(ui: &mut Ui, _: &[&str]) -> io::Result<()> {
+    ui.send(ClientCommand::Disconnect);
+    Ok(())
+}
+
+fn peers(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    match args {
+        [] => ui.send(ClientCommand::Peers(PeerSort::Connection)),
+        ["--sort", "quality"] => ui.send(ClientCommand::Peers(PeerSort::Quality)),
+        _ => usage(ui, "peers"),
+    }
+    Ok(())
+}
+
+fn setban(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    let Ok(subnet) = args[0].parse::<Subnet>() else {
+        ui.err(format!("Invalid IP/subnet \"{}\"", args[0]));
+        return Ok(());
+    };
+    match args[1..] {
+        ["add"] => ui.send(ClientCommand::Ban {
+            subnet,
+            secs: DEFAULT_BAN_TIME,
+        }),
+        ["add", secs] => match secs.parse::<u64>() {
+            // As in Core, 0 is the default time.
+            Ok(0) => ui.send(ClientCommand::Ban {
+                subnet,
+                secs: DEFAULT_BAN_TIME,
+            }),
+            Ok(secs) => ui.send(ClientCommand::Ban { subnet, secs }),
+            Err(_) => usage(ui, "setban"),
+        },
+        ["remove"] => ui.send(ClientCommand::Unban(subnet)),
+        _ => usage(ui, "setban"),
+    }
+    Ok(())
+}
+
+fn listbanned(ui: &mut Ui, _args: &[&str]) -> io::Result<()> {
+    ui.send(ClientCommand::ListBanned);
+    Ok(())
+}
+
+fn clearbanned(ui: &mut Ui, _args: &[&str]) -> io::Result<()> {
+    ui.send(ClientCommand::ClearBanned);
+    Ok(())
+}
+
+fn exportbans(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    ui.send(ClientCommand::ExportBans(PathBuf::from(args[0])));
+    Ok(())
+}
+
+fn importbans(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    ui.send(ClientCommand::ImportBans(PathBuf::from(args[0])));
+    Ok(())
+}
+
+fn ping(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    match args.first() {
+        Some(value) => match value.parse() {
+            Ok(value) => ui.send(ClientCommand::SendBtcMsg(BitcoinMsg::ping(value))),
+            Err(e) => ui.err(format!("Could not parse value \"{value}\": {e}")),
+        },
+        None => ui.send(ClientCommand::Ping),
+    }
+    Ok(())
+}
+
+fn getaddr(ui: &mut Ui, _: &[&str]) -> io::Result<()> {
+    ui.send(ClientCommand::SendBtcMsg(BitcoinMsg::getaddr()));
+    Ok(())
+}
+
+fn sendraw(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    let hex = args.get(1).copied().unwrap_or("");
+    match parse_hex(hex) {
+        Some(payload) => ui.send(ClientCommand::SendRaw {
+            command: args[0].to_string(),
+            payload,
+        }),
+        None => ui.err(format!("Could not parse hex \"{hex}\"")),
+    }
+    Ok(())
+}
+
+fn fault(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    let command = Command::from(args[0]);
+    let arg = args.get(2).map(|arg| arg.parse::<u64>());
+    let fault = match (args[1], arg) {
+        ("checksum", None) => Some(Fault::CorruptChecksum),
+        ("truncate", Some(Ok(len))) => Some(Fault::Truncate(len as usize)),
+        ("delay", Some(Ok(ms))) => Some(Fault::Delay(Duration::from_millis(ms))),
+        ("reorder", None) => Some(Fault::Reorder),
+        ("clear", None) => None,
+        _ => {
+            usage(ui, "fault");
+            return Ok(());
+        }
+    };
+    ui.send(ClientCommand::Fault { command, fault });
+    Ok(())
+}
+
+fn advertise(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    match args[0] {
+        "off" => ui.send(ClientCommand::Advertise(Advertised::Off)),
+        "external" => ui.send(ClientCommand::Advertise(Advertised::External)),
+        addr => {
+            if let Some(addr) = parse_addr(ui, addr) {
+                ui.send(ClientCommand::Advertise(Advertised::Addr(addr)));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn export(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    ui.send(ClientCommand::ExportAddrs(PathBuf::from(args[0])));
+    Ok(())
+}
+
+fn import(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    ui.send(ClientCommand::ImportAddrs(PathBuf::from(args[0])));
+    Ok(())
+}
+
+fn record(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    match args {
+        ["start", path] => ui.send(ClientCommand::Record(Some(PathBuf::from(path)))),
+        ["stop"] => ui.send(ClientCommand::Record(None)),
+        _ => usage(ui, "record"),
+    }
+    Ok(())
+}
+
+fn dump(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    match args {
+        ["off"] => ui.send(ClientCommand::Dump(None)),
+        [dir] => ui.send(ClientCommand::Dump(Some(PathBuf::from(dir)))),
+        _ => usage(ui, "dump"),
+    }
+    Ok(())
+}
+
+fn notify(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    let Some(addr) = parse_addr(ui, args[0]) else {
+        return Ok(());
+    };
+    match ui.notifier.serve(addr) {
+        Ok(_) => ui.info(format!("Publishing notifications on {addr}")),
+        Err(e) => ui.err(format!("Could not listen on {addr}: {e}")),
+    }
+    Ok(())
+}
+
+fn rpc(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    let Some(addr) = parse_addr(ui, args[0]) else {
+        return Ok(());
+    };
+    let auth = args.get(1).and_then(|s| s.split_once(':'));
+    match serve_rpc(addr, auth, ui.tx.clone()) {
+        Ok(_) => ui.info(format!("Serving JSON-RPC on {addr}")),
+        Err(e) => ui.err(format!("Could not listen on {addr}: {e}")),
+    }
+    Ok(())
+}
+
+fn rest(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    let Some(addr) = parse_addr(ui, args[0]) else {
+        return Ok(());
+    };
+    match serve_rest(
+        addr,
+        ui.tx.clone(),
+        ui.metrics.clone(),
+        ui.recent_log.clone(),
+        ui.start,
+    ) {
+        Ok(_) => ui.info(format!("Serving status on http://{addr}/")),
+        Err(e) => ui.err(format!("Could not listen on {addr}: {e}")),
+    }
+    Ok(())
+}
+
+fn stats(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    match args {
+        [] => {
+            for line in stats_report(&ui.metrics.snapshot(), ui.start.elapsed()) {
+                ui.info(line);
+            }
+            ui.send(ClientCommand::Stats);
+        }
+        ["every", "off"] => {
+            ui.stats_every = None;
+            ui.info("Stopped periodic bandwidth summaries");
+        }
+        ["every", secs] => match secs.parse::<u64>() {
+            Ok(secs) if secs > 0 => {
+                let interval = Duration::from_secs(secs);
+                ui.stats_every = Some((interval, Instant::now(), ui.metrics.snapshot()));
+                ui.info(format!("Summarizing bandwidth every {secs}s"));
+            }
+            _ => ui.err(format!("Could not parse interval \"{secs}\"")),
+        },
+        _ => usage(ui, "stats"),
+    }
+    Ok(())
+}
+
+fn statusbar(ui: &mut Ui, _: &[&str]) -> io::Result<()> {
+    ui.status_bar = !ui.status_bar;
+
+    ui.stdout
+        .queue(cursor::SavePosition)?
+        .queue(cursor::MoveTo(0, ui.rows - 2))?;
+    if ui.status_bar {
+        // The status bar takes over the last log row, so make room if the
+        // next log line would land there.
+        if ui.log_cursor_position.1 > ui.rows - bottom_rows(true) {
+            scroll_log(&mut ui.stdout, ui.rows, 1, &ui.command, false)?;
+            ui.log_cursor_position.1 -= 1;
+        }
+        ui.last_sample = (Instant::now(), ui.metrics.snapshot());
+        ui.status = String::from("collecting statistics...");
+    } else {
+        ui.stdout
+            .queue(terminal::Clear(terminal::ClearType::CurrentLine))?;
+    }
+    ui.stdout.queue(cursor::RestorePosition)?;
+    Ok(())
+}
+
+fn help(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    match args.first() {
+        Some(name) => match COMMANDS.iter().find(|spec| spec.name == *name) {
+            Some(spec) => ui.info(format!("{}\n  {}", spec.usage(), spec.description)),
+            None => ui.err(format!("Unknown command \"{name}\", see help")),
+        },
+        None => {
+            let mut lines = vec![String::from("Commands, see help <command> for more:")];
+            lines.extend(COMMANDS.iter().map(|spec| format!("  {}", spec.usage())));
+            ui.info(lines.join("\n"));
+        }
+    }
+    Ok(())
+}
+
+// Aliases are kept in the config file as the alias commands that define
+// them, one per line:
+//
+//     alias hs connect 203.0.113.1; getaddr
+//
+// Blank lines and lines starting with # are skipped. A missing file
+// means no aliases.
+pub(super) fn load_aliases(path: &Path) -> io::Result<BTreeMap<String, String>> {
+    let config = match fs::read_to_string(path) {
+        Ok(config) => config,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut aliases = BTreeMap::new();
+    for (i, line) in config.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let definition = line.strip_prefix("alias ").and_then(|alias| {
+            let (name, commands) = alias.trim().split_once(char::is_whitespace)?;
+            Some((name.to_string(), commands.trim().to_string()))
+        });
+        match definition {
+            Some((name, commands)) => {
+                aliases.insert(name, commands);
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {} is not an alias definition", i + 1),
+                ))
+            }
+        }
+    }
+    Ok(aliases)
+}
+
+fn save_aliases(path: &Path, aliases: &BTreeMap<String, String>) -> io::Result<()> {
+    let mut config = String::new();
+    for (name, commands) in aliases {
+        config += &format!("alias {name} {commands}\n");
+    }
+
+    let tmp = path.with_extension("conf.tmp");
+    fs::write(&tmp, config)?;
+    fs::rename(tmp, path)
+}
+
+fn store_aliases(ui: &mut Ui) {
+    let Some(path) = &ui.config_path else {
+        return;
+    };
+    if let Err(e) = save_aliases(path, &ui.aliases) {
+        ui.err(format!("Could not save aliases to {}: {e}", path.display()));
+    }
+}
+
+fn alias(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    match args {
+        [] if ui.aliases.is_empty() => ui.info("No aliases defined"),
+        [] => {
+            for (name, commands) in &ui.aliases {
+                ui.info(format!("alias {name} {commands}"));
+            }
+        }
+        [name] => match ui.aliases.get(*name) {
+            Some(commands) => ui.info(format!("alias {name} {commands}")),
+            None => ui.err(format!("No alias \"{name}\"")),
+        },
+        [name, ..] if COMMANDS.iter().any(|spec| spec.name == *name) => {
+            ui.err(format!("\"{name}\" is a command and can't be an alias"));
+        }
+        [name, commands @ ..] => {
+            let commands = commands.join(" ");
+            // Quotes are optional, as in alias hs "connect 203.0.113.1; getaddr".
+            let commands = commands
+                .strip_prefix('"')
+                .and_then(|c| c.strip_suffix('"'))
+                .unwrap_or(&commands)
+                .trim()
+                .to_string();
+            ui.info(format!("alias {name} {commands}"));
+            ui.aliases.insert(name.to_string(), commands);
+            store_aliases(ui);
+        }
+    }
+    Ok(())
+}
+
+fn unalias(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    if ui.aliases.remove(args[0]).is_none() {
+        ui.err(format!("No alias \"{}\"", args[0]));
+        return Ok(());
+    }
+    store_aliases(ui);
+    Ok(())
+}
+
+// A session file is a list of commands, one per line, that recreate
+// the state it was saved in.
+fn session(ui: &mut Ui, args: &[&str]) -> io::Result<()> {
+    let path = PathBuf::from(args[1]);
+    match args[0] {
+        "save" => {
+            let mut lines = vec![];
+            if ui.status_bar {
+                lines.push("statusbar".to_string());
+            }
+            if let Some((interval, ..)) = ui.stats_every {
+                lines.push(format!("stats every {}", interval.as_secs()));
+            }
+            ui.send(ClientCommand::SaveSession { path, lines });
+        }
+        "load" => {
+            let session = match fs::read_to_string(&path) {
+                Ok(session) => session,
+                Err(e) => {
+                    ui.err(format!("Could not read {}: {e}", path.display()));
+                    return Ok(());
+                }
+            };
+            // Run as typed, except that a line is a single command.
+            for line in session.lines().map(str::trim) {
+                if !line.is_empty() && !line.starts_with('#') {
+                    run_one(ui, line, false)?;
+                }
+            }
+        }
+        _ => usage(ui, "session"),
+    }
+    Ok(())
+}
+
+// Hashes and addresses in a log message, the things worth copying.
+fn copyable(msg: &str) -> impl Iterator<Item = &str> {
+    msg.split(|c: char| c.is_whitespace() || ",;()[]\"".contains(c))
+        .filter(|word| {
+            (word.len() == 64 && word.bytes().all(|b| b.is_ascii_hexdigit()))
+                || word.parse::<SocketAddr>().is_ok()
+                || word.parse::<IpAddr>().is_ok()
+        })
+}
+
+fn copy(ui: &mut Ui, _: &[&str]) -> io::Result<()> {
+    let mut items: Vec<String> = vec![];
+    for entry in ui.recent_log.lock().unwrap().iter().rev() {
+        let msg = entry["msg"].as_str().unwrap_or_default();
+        for item in copyable(msg) {
+            if !items.iter().any(|known| known == item) {
+                items.push(item.to_string());
+            }
+        }
+    }
+
+    if items.is_empty() {
+        ui.err("No hashes or addresses in the recent log");
+    } else {
+        ui.picker = Some(Picker { items, selected: 0 });
+    }
+    Ok(())
+}
+
+fn expand(ui: &mut Ui, _: &[&str]) -> io::Result<()> {
+    if ui.folded.is_empty() {
+        ui.err("Nothing is folded");
+    }
+    for row in mem::take(&mut ui.folded) {
+        ui.log_tx.send(row).unwrap();
+    }
+    Ok(())
+}
+
+// Replaces the prompt while picking what to copy, newest first.
+pub(super) fn draw_picker(ui: &mut Ui) -> io::Result<()> {
+    let Some(picker) = &ui.picker else {
+        return Ok(());
+    };
+    let mut line = format!(
+        "copy> {} ({}/{})",
+        picker.items[picker.selected],
+        picker.selected + 1,
+        picker.items.len()
+    );
+    // Wrapping on the last row would scroll the screen.
+    line.truncate(ui.cols.saturating_sub(1) as usize);
+
+    ui.stdout
+        .queue(cursor::MoveTo(0, ui.rows - 1))?
+        .queue(terminal::Clear(terminal::ClearType::CurrentLine))?
+        .queue(style::Print(line))?;
+    Ok(())
+}
+
+pub(super) fn pick(ui: &mut Ui, key: KeyCode) -> io::Result<()> {
+    let Some(picker) = &mut ui.picker else {
+        return Ok(());
+    };
+
+    match key {
+        KeyCode::Up => picker.selected = (picker.selected + 1).min(picker.items.len() - 1),
+        KeyCode::Down => picker.selected = picker.selected.saturating_sub(1),
+        KeyCode::Enter | KeyCode::Esc => {
+            let picked = (key == KeyCode::Enter).then(|| picker.items[picker.selected].clone());
+            ui.picker = None;
+            ui.stdout
+                .queue(cursor::MoveTo(0, ui.rows - 1))?
+                .queue(terminal::Clear(terminal::ClearType::CurrentLine))?
+                .queue(style::Print("> "))?
+                .queue(style::Print(&ui.command))?;
+
+            if let Some(item) = picked {
+                // OSC 52, which has the terminal set the clipboard.
+                let osc52 = format!("\x1b]52;c;{}\x07", rpc::base64(item.as_bytes()));
+                ui.stdout.queue(style::Print(osc52))?;
+                ui.info(format!("Copied {item}"));
+            }
+            return Ok(());
+        }
+        _ => return Ok(()),
+    }
+
+    draw_picker(ui)
+}
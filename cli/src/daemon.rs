@@ -0,0 +1,160 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use btc_lib::net;
+use btc_lib::rpc::{json, Value};
+
+use super::{clock_time, commands, strip_control, LogMsg, Ui};
+
+// How long `btc ctl` waits for more of the log after the last line.
+// Whatever a command asks of the peer is only logged once it answers.
+const CTL_IDLE: Duration = Duration::from_millis(500);
+
+// A control connection that doesn't read its log is dropped rather
+// than allowed to hold up the daemon.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Subscribers = Arc<Mutex<Vec<UnixStream>>>;
+
+// Runs without the TUI, for running under a service manager. The log
+// goes to stdout, and commands come in on the Unix socket at `path`,
+// one JSON object per line, such as {"command": "connect 203.0.113.5"}.
+// From then on the connection gets every log line as a JSON object with
+// the fields of the recent log.
+pub(super) fn run(mut ui: Ui, rx: Receiver<LogMsg>, path: &Path) -> io::Result<()> {
+    let listener = bind(path)?;
+    let subscribers = Subscribers::default();
+    let (cmd_tx, cmd_rx) = mpsc::channel();
+    {
+        let subscribers = subscribers.clone();
+        thread::spawn(move || accept(listener, cmd_tx, subscribers));
+    }
+    ui.info(format!("Listening for commands on {}", path.display()));
+
+    let mut stdout = io::stdout();
+    loop {
+        match cmd_rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(line) => commands::run(&mut ui, &line)?,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::other("control socket closed"));
+            }
+        }
+
+        for mut msg in rx.try_iter() {
+            strip_control(&mut msg);
+            let time = net::unix_time();
+            ui.remember(time, &mut msg);
+
+            let clock = clock_time(time, ui.utc);
+            let level = msg.kind.as_str();
+            for line in msg.msg.split('\n').filter(|s| !s.is_empty()) {
+                writeln!(stdout, "{clock} {}: {line}", level.to_uppercase())?;
+            }
+
+            let entry = json!({ "time": time, "level": level, "msg": msg.msg });
+            subscribers
+                .lock()
+                .unwrap()
+                .retain_mut(|stream| writeln!(stream, "{entry}").is_ok());
+        }
+        stdout.flush()?;
+
+        ui.summarize_bandwidth();
+    }
+}
+
+// A socket file left behind by a daemon that died is in the way, while
+// one that still answers belongs to a daemon that is running.
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another daemon is listening on {}", path.display()),
+        ));
+    }
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    UnixListener::bind(path)
+}
+
+fn accept(listener: UnixListener, cmd_tx: Sender<String>, subscribers: Subscribers) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let (Ok(mut reply), Ok(log)) = (stream.try_clone(), stream.try_clone()) else {
+            continue;
+        };
+        if log.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() {
+            continue;
+        }
+        subscribers.lock().unwrap().push(log);
+
+        let cmd_tx = cmd_tx.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                let request = line.parse::<Value>().unwrap_or_default();
+                let Some(command) = request["command"].as_str() else {
+                    let error = json!({
+                        "time": net::unix_time(),
+                        "level": "error",
+                        "msg": "expected {\"command\": \"...\"}",
+                    });
+                    if writeln!(reply, "{error}").is_err() {
+                        break;
+                    }
+                    continue;
+                };
+                if cmd_tx.send(command.to_string()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+// Runs `command` on the daemon listening on `path` and prints what it
+// logs until it goes quiet, errors to stderr. Exits with 1 if anything
+// was an error and 2 if the daemon couldn't be reached.
+pub(super) fn ctl(path: &Path, command: &str) -> ! {
+    let stream = UnixStream::connect(path).and_then(|mut stream| {
+        writeln!(stream, "{}", json!({ "command": command }))?;
+        stream.set_read_timeout(Some(CTL_IDLE))?;
+        Ok(stream)
+    });
+    let stream = match stream {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Could not reach the daemon at {}: {e}", path.display());
+            std::process::exit(2);
+        }
+    };
+
+    let mut status = 0;
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let entry = line.parse::<Value>().unwrap_or_default();
+        let msg = entry["msg"].as_str().unwrap_or(&line);
+        if entry["level"] == "error" {
+            eprintln!("{msg}");
+            status = 1;
+        } else {
+            println!("{msg}");
+        }
+    }
+    std::process::exit(status);
+}
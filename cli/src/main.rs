@@ -1,25 +1,44 @@
-use std::fmt::Write as _;
-use std::io::{self, Read, Write};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::mem;
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
 use std::result;
-use std::str::FromStr;
-use std::sync::mpsc::Sender;
 use std::sync::mpsc::{self, Receiver};
-use std::time::{Duration, SystemTime};
+use std::sync::mpsc::{SendError, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use crossterm::terminal;
 use crossterm::ExecutableCommand;
 use crossterm::{cursor, style, QueueableCommand};
-
-use btc_lib::*;
+use mio::{Events, Interest, Poll, Registry, Token, Waker};
+
+use btc_lib::crypto;
+use btc_lib::metrics::{Counters, Direction, Metrics};
+use btc_lib::net::{
+    self, AddrBook, AdjustedTime, BanList, DisconnectReason, Enricher, EventRecorder, ExternalAddr,
+    Fault, NetGroups, PeerAddr, Subnet, DEFAULT_BAN_TIME,
+};
+use btc_lib::netgroup::{AddressType, NetGroup};
+use btc_lib::notify::Notifier;
+use btc_lib::p2p::builder::PROTOCOL_VERSION;
+use btc_lib::prelude::*;
+use btc_lib::rpc::{self, json, RpcError, Value};
+
+mod commands;
+#[cfg(unix)]
+mod daemon;
 
 #[derive(Debug)]
 enum ErrorKind {
     IoErr(io::Error),
     NotConnected,
     ProtocolErr,
+    Timeout,
 }
 
 #[derive(Debug)]
@@ -47,6 +66,18 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<net::Error> for Error {
+    fn from(e: net::Error) -> Self {
+        let kind = match e.kind {
+            net::ErrorKind::IoErr(e) => ErrorKind::IoErr(e),
+            net::ErrorKind::ProtocolErr => ErrorKind::ProtocolErr,
+            net::ErrorKind::Timeout => ErrorKind::Timeout,
+        };
+
+        Error { kind, msg: e.msg }
+    }
+}
+
 type Result<T> = result::Result<T, Error>;
 
 enum LogMsgKind {
@@ -55,9 +86,65 @@ enum LogMsgKind {
     Error,
 }
 
+impl LogMsgKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LogMsgKind::Info => "info",
+            LogMsgKind::Warn => "warn",
+            LogMsgKind::Error => "error",
+        }
+    }
+}
+
+// Objects that come in bulk, logged one per row with aligned columns so
+// long runs of them can be scanned: kind, hash or address, then for
+// addresses their network, age and services.
+enum LogEvent {
+    Inv(InventoryElement),
+    Addr(AddrElement),
+}
+
+impl LogEvent {
+    fn kind(&self) -> String {
+        match self {
+            LogEvent::Inv(inv) => inv.kind.to_string(),
+            LogEvent::Addr(_) => "addr".to_string(),
+        }
+    }
+
+    fn color(&self) -> style::Color {
+        match self {
+            LogEvent::Inv(inv) if inv.kind.is_tx() => style::Color::Green,
+            LogEvent::Inv(_) => style::Color::Magenta,
+            LogEvent::Addr(_) => style::Color::Cyan,
+        }
+    }
+
+    // Everything after the kind column.
+    fn columns(&self) -> String {
+        match self {
+            LogEvent::Inv(inv) => inv.hash.to_string(),
+            LogEvent::Addr(addr) => {
+                let age = net::format_age(addr.timestamp as i64, net::unix_time());
+
+                format!(
+                    "{:<47} {:<10} {:>10}  {}",
+                    addr.addr.addr,
+                    AddressType::of(&addr.addr.addr.ip()),
+                    age,
+                    addr.addr.services
+                )
+            }
+        }
+    }
+}
+
 struct LogMsg {
     kind: LogMsgKind,
     msg: String,
+    event: Option<LogEvent>,
+    // Lines logged only on request.
+    folded: Vec<LogMsg>,
 }
 
 impl LogMsg {
@@ -65,6 +152,8 @@ impl LogMsg {
         LogMsg {
             kind: LogMsgKind::Info,
             msg: msg.to_string(),
+            event: None,
+            folded: vec![],
         }
     }
 
@@ -72,6 +161,8 @@ impl LogMsg {
         LogMsg {
             kind: LogMsgKind::Warn,
             msg: msg.to_string(),
+            event: None,
+            folded: vec![],
         }
     }
 
@@ -79,26 +170,169 @@ impl LogMsg {
         LogMsg {
             kind: LogMsgKind::Error,
             msg: msg.to_string(),
+            event: None,
+            folded: vec![],
+        }
+    }
+
+    // The text is what goes to the recent log, the columns without the
+    // colors.
+    fn event(event: LogEvent) -> LogMsg {
+        LogMsg {
+            kind: LogMsgKind::Info,
+            msg: format!("{:<LOG_KIND_WIDTH$} {}", event.kind(), event.columns()),
+            event: Some(event),
+            folded: vec![],
+        }
+    }
+
+    // `summary` alone, with `rows` kept out of the log until `expand`.
+    fn folded(summary: impl ToString, rows: Vec<LogMsg>) -> LogMsg {
+        LogMsg {
+            kind: LogMsgKind::Info,
+            msg: format!(
+                "{} ({} lines folded, expand shows them)",
+                summary.to_string(),
+                rows.len()
+            ),
+            event: None,
+            folded: rows,
         }
     }
 }
 
+// Wide enough for every inventory type short of "filtered witness block".
+const LOG_KIND_WIDTH: usize = 13;
+
+// Messages with more inventory or addresses than this are logged as a
+// summary, so that one 1000 entry addr can't hold up the UI.
+const FOLD_ROWS: usize = 20;
+
 enum ClientCommand {
     SendBtcMsg(BitcoinMsg),
-    Connect(SocketAddr),
+    Connect {
+        // An IP address or a host name, with an optional port.
+        addr: String,
+        relay: bool,
+        network: Network,
+    },
+    ConnectAnchors,
     Disconnect,
+    Peers(PeerSort),
+    Ban {
+        subnet: Subnet,
+        secs: u64,
+    },
+    Unban(Subnet),
+    ListBanned,
+    ClearBanned,
+    ExportBans(PathBuf),
+    ImportBans(PathBuf),
+    // A ping with a random nonce.
+    Ping,
+    Advertise(Advertised),
+    Stats,
+    ExportAddrs(PathBuf),
+    ImportAddrs(PathBuf),
+    // Writes the commands that bring back the connection and client
+    // settings to a session file, after `lines` from the UI.
+    SaveSession {
+        path: PathBuf,
+        lines: Vec<String>,
+    },
+    // None stops recording.
+    Record(Option<PathBuf>),
+    // None stops dumping.
+    Dump(Option<PathBuf>),
+    SendRaw {
+        command: String,
+        payload: Vec<u8>,
+    },
+    // None clears the faults for the command.
+    Fault {
+        command: Command,
+        fault: Option<Fault>,
+    },
+    Rpc {
+        method: String,
+        params: Vec<Value>,
+        reply: Sender<result::Result<Value, RpcError>>,
+    },
+}
+
+// Wakes the client thread when a command is sent, since it otherwise
+// sleeps until its connection has something to read.
+#[derive(Clone)]
+struct CommandSender {
+    tx: Sender<ClientCommand>,
+    waker: Arc<Waker>,
+}
+
+impl CommandSender {
+    // Fails once the client thread is gone. The command is dropped rather
+    // than handed back, as no caller has a use for it.
+    fn send(&self, cmd: ClientCommand) -> result::Result<(), SendError<()>> {
+        self.tx.send(cmd).map_err(|_| SendError(()))?;
+        // Only fails if the poll is gone, and then so is the receiver.
+        let _ = self.waker.wake();
+        Ok(())
+    }
+}
+
+const CONN_TOKEN: Token = Token(0);
+const WAKER_TOKEN: Token = Token(1);
+// Connections have timers of their own, such as for advertising our
+// address, which only run when they are asked for messages.
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(1);
+// How long connecting waits for the TCP connection, and then for the
+// handshake. The UI doesn't take input meanwhile, so it is kept short.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy)]
+enum PeerSort {
+    Connection,
+    Quality,
+}
+
+// What to advertise to peers as our own address.
+#[derive(Clone, Copy)]
+enum Advertised {
+    Off,
+    Addr(SocketAddr),
+    // The address peers report seeing us at, on the network's default
+    // port, once enough of them agree.
+    External,
 }
 
 struct Client {
-    stream: Option<TcpStream>,
+    conn: Option<Connection>,
+    // A handle on the connection's socket, registered for readiness.
+    conn_source: Option<mio::net::TcpStream>,
+    registry: Registry,
+    events: Option<Receiver<net::Event>>,
     log_tx: Sender<LogMsg>,
+    metrics: Arc<Metrics>,
+    notifier: Arc<Notifier>,
+    adjusted_time: AdjustedTime,
+    external_addr: ExternalAddr,
+    advertised: Advertised,
+    addr_book: AddrBook,
+    recorder: Option<EventRecorder>,
+    // Where connections write frames they can't decode.
+    dump_dir: Option<PathBuf>,
+    // Anchors are only kept for the default network.
+    network: Network,
+    anchors_path: Option<PathBuf>,
+    anchors: Vec<NetAddr>,
+    bans: BanList,
+    // Bans are only kept across restarts with --datadir.
+    bans_path: Option<PathBuf>,
 }
 
 impl Client {
     fn send_msg(&mut self, msg: BitcoinMsg) -> Result<()> {
-        if let Some(stream) = &mut self.stream {
-            let blob = msg.to_blob();
-            stream.write_all(&blob)?;
+        if let Some(conn) = &mut self.conn {
+            conn.send_msg(&msg)?;
             Ok(())
         } else {
             Err(Error::with_msg(
@@ -108,17 +342,9 @@ impl Client {
         }
     }
 
-    fn read_msg(&mut self) -> Result<BitcoinMsg> {
-        if let Some(stream) = &mut self.stream {
-            let mut header = vec![0; 24];
-            stream.peek(&mut header)?;
-            let header = BitcoinHeader::from_blob(&mut Scanner::new(header));
-
-            let mut msg = vec![0; 24 + header.size as usize];
-            stream.read_exact(&mut msg)?;
-
-            let msg = BitcoinMsg::from_blob(&mut Scanner::new(msg));
-            Ok(msg)
+    fn process_next_msg(&mut self) -> Result<()> {
+        if let Some(conn) = &mut self.conn {
+            Ok(conn.process_next_msg()?)
         } else {
             Err(Error::with_msg(
                 ErrorKind::NotConnected,
@@ -130,8 +356,62 @@ impl Client {
     fn handle_cmds(&mut self, cmd: ClientCommand) -> Result<()> {
         match cmd {
             ClientCommand::SendBtcMsg(btc_msg) => self.send_msg_cmd(btc_msg)?,
-            ClientCommand::Connect(addr) => self.connect(addr)?,
+            ClientCommand::Connect {
+                addr,
+                relay,
+                network,
+            } => self.connect_host(&addr, relay, network)?,
+            ClientCommand::ConnectAnchors => self.connect_anchors()?,
             ClientCommand::Disconnect => self.disconnect()?,
+            ClientCommand::Peers(sort) => self.peers(sort)?,
+            ClientCommand::Ban { subnet, secs } => {
+                if self.ban(subnet, secs)? {
+                    self.log_tx
+                        .send(LogMsg::info(format!(
+                            "Banned {subnet} for {}",
+                            net::format_duration(secs)
+                        )))
+                        .unwrap();
+                } else {
+                    self.log_tx
+                        .send(LogMsg::err(format!("{subnet} is already banned")))
+                        .unwrap();
+                }
+            }
+            ClientCommand::Unban(subnet) => {
+                let msg = if self.unban(&subnet) {
+                    LogMsg::info(format!("Unbanned {subnet}"))
+                } else {
+                    LogMsg::err(format!("{subnet} was not banned"))
+                };
+                self.log_tx.send(msg).unwrap();
+            }
+            ClientCommand::ListBanned => self.list_banned(),
+            ClientCommand::ClearBanned => {
+                self.bans.clear();
+                self.save_bans();
+                self.log_tx.send(LogMsg::info("Cleared all bans")).unwrap();
+            }
+            ClientCommand::ExportBans(path) => self.export_bans(&path)?,
+            ClientCommand::ImportBans(path) => self.import_bans(&path)?,
+            ClientCommand::Ping => self.ping()?,
+            ClientCommand::Advertise(advertised) => self.advertise(advertised),
+            ClientCommand::Stats => self.stats(),
+            ClientCommand::ExportAddrs(path) => self.export_addrs(&path)?,
+            ClientCommand::ImportAddrs(path) => self.import_addrs(&path)?,
+            ClientCommand::SaveSession { path, lines } => self.save_session(&path, lines)?,
+            ClientCommand::Record(path) => self.record(path)?,
+            ClientCommand::Dump(dir) => self.dump(dir),
+            ClientCommand::SendRaw { command, payload } => self.send_raw(&command, &payload)?,
+            ClientCommand::Fault { command, fault } => self.fault(command, fault)?,
+            ClientCommand::Rpc {
+                method,
+                params,
+                reply,
+            } => {
+                // The caller may have given up waiting already.
+                let _ = reply.send(self.rpc(&method, &params));
+            }
         }
 
         Ok(())
@@ -163,329 +443,1672 @@ impl Client {
         Ok(())
     }
 
-    fn connect(&mut self, addr: SocketAddr) -> Result<()> {
-        self.stream = TcpStream::connect(addr).ok();
+    fn ping(&mut self) -> Result<()> {
+        let Some(conn) = &mut self.conn else {
+            return Err(Error::with_msg(
+                ErrorKind::NotConnected,
+                "Could not send ping, client not connected",
+            ));
+        };
 
-        let msg = BitcoinMsg::version(
-            NetAddr {
-                services: Default::default(),
-                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8333),
-            },
-            NetAddr {
-                services: Default::default(),
-                addr,
-            },
-            "my bitcoin client".to_string(),
-            69,
-            0,
-            true,
-        );
+        let nonce = conn.ping()?;
+        self.log_tx
+            .send(LogMsg::info(format!("Sent ping with value {nonce}")))
+            .unwrap();
+        Ok(())
+    }
 
-        self.send_msg(msg)?;
+    fn send_raw(&mut self, command: &str, payload: &[u8]) -> Result<()> {
+        let Some(conn) = &mut self.conn else {
+            return Err(Error::with_msg(
+                ErrorKind::NotConnected,
+                "Could not send message, client not connected",
+            ));
+        };
 
-        if let BitcoinPayload::Version(_) = self.read_msg()?.payload {
-        } else {
-            return Err(Error::new(ErrorKind::ProtocolErr));
+        conn.send_raw(command, payload)?;
+        self.log_tx
+            .send(LogMsg::warn(format!(
+                "Sent raw {command} message with {} bytes of payload",
+                payload.len()
+            )))
+            .unwrap();
+
+        Ok(())
+    }
+
+    fn advertise(&mut self, advertised: Advertised) {
+        self.advertised = advertised;
+        let addr = self
+            .conn
+            .as_ref()
+            .and_then(|conn| self.advertised_addr(conn));
+        if let Some(conn) = &mut self.conn {
+            conn.set_advertised_addr(addr);
         }
 
-        if let BitcoinPayload::VerAck = self.read_msg()?.payload {
+        let msg = match advertised {
+            Advertised::Off => String::from("Not advertising an address"),
+            Advertised::Addr(addr) => format!("Advertising {addr} to peers"),
+            Advertised::External => String::from("Advertising our external address to peers"),
+        };
+        self.log_tx.send(LogMsg::info(msg)).unwrap();
+    }
+
+    fn advertised_addr(&self, conn: &Connection) -> Option<NetAddr> {
+        let addr = match self.advertised {
+            Advertised::Off => None,
+            Advertised::Addr(addr) => Some(addr),
+            Advertised::External => {
+                let network = Network::from_magic(conn.magic()).unwrap_or(self.network);
+                self.external_addr
+                    .ip()
+                    .map(|ip| SocketAddr::new(ip, network.default_port()))
+            }
+        };
+
+        addr.map(|addr| NetAddr {
+            services: Default::default(),
+            addr,
+        })
+    }
+
+    fn stats(&mut self) {
+        let msg = match self.external_addr.best() {
+            Some((ip, votes)) => format!(
+                "external address: {} ({ip} reported by {votes} of {} peers)",
+                match self.external_addr.ip() {
+                    Some(ip) => ip.to_string(),
+                    None => String::from("undecided"),
+                },
+                self.external_addr.sample_count(),
+            ),
+            None => String::from("external address: unknown"),
+        };
+        self.log_tx.send(LogMsg::info(msg)).unwrap();
+        self.log_tx
+            .send(LogMsg::info(format!(
+                "known addresses: {}",
+                self.addr_book.len()
+            )))
+            .unwrap();
+    }
+
+    // JSON if the file name ends in .json, CSV otherwise, with the type
+    // and netgroup of each address.
+    fn export_addrs(&mut self, path: &Path) -> Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let enrichers: [&dyn Enricher; 1] = [&NetGroups];
+        if path.extension().is_some_and(|ext| ext == "json") {
+            self.addr_book.write_json_enriched(file, &enrichers)?;
         } else {
-            return Err(Error::new(ErrorKind::ProtocolErr));
+            self.addr_book.write_csv_enriched(file, &enrichers)?;
+        }
+
+        self.log_tx
+            .send(LogMsg::info(format!(
+                "Exported {} addresses to {}",
+                self.addr_book.len(),
+                path.display()
+            )))
+            .unwrap();
+        Ok(())
+    }
+
+    fn import_addrs(&mut self, path: &Path) -> Result<()> {
+        let file = BufReader::new(File::open(path)?);
+        let new = self.addr_book.import(file, self.network.default_port())?;
+
+        self.log_tx
+            .send(LogMsg::info(format!(
+                "Imported {new} new addresses from {}, {} known",
+                path.display(),
+                self.addr_book.len()
+            )))
+            .unwrap();
+        Ok(())
+    }
+
+    fn record(&mut self, path: Option<PathBuf>) -> Result<()> {
+        if let Some(mut recorder) = self.recorder.take() {
+            recorder.flush()?;
+        }
+
+        let msg = match path {
+            Some(path) => {
+                self.recorder = Some(EventRecorder::append(&path)?);
+                format!("Recording events to {}", path.display())
+            }
+            None => String::from("Stopped recording events"),
+        };
+        self.log_tx.send(LogMsg::info(msg)).unwrap();
+        Ok(())
+    }
+
+    fn dump(&mut self, dir: Option<PathBuf>) {
+        let msg = match &dir {
+            Some(dir) => format!("Dumping undecodable messages to {}", dir.display()),
+            None => String::from("Stopped dumping undecodable messages"),
+        };
+        if let Some(conn) = &mut self.conn {
+            conn.set_dump_dir(dir.clone());
+        }
+        self.dump_dir = dir;
+        self.log_tx.send(LogMsg::info(msg)).unwrap();
+    }
+
+    fn fault(&mut self, command: Command, fault: Option<Fault>) -> Result<()> {
+        let Some(conn) = &mut self.conn else {
+            return Err(Error::with_msg(
+                ErrorKind::NotConnected,
+                "Could not set up fault, client not connected",
+            ));
+        };
+
+        match fault {
+            Some(fault) => {
+                self.log_tx
+                    .send(LogMsg::warn(format!(
+                        "Injecting {fault:?} into outbound {command} messages"
+                    )))
+                    .unwrap();
+                conn.inject_fault(command, fault);
+            }
+            None => {
+                self.log_tx
+                    .send(LogMsg::info(format!(
+                        "Cleared faults for {command} messages"
+                    )))
+                    .unwrap();
+                conn.clear_faults(&command);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Known addresses go next to the session file, as <path>.addrs, and
+    // are imported before anything connects.
+    fn save_session(&mut self, path: &Path, mut lines: Vec<String>) -> Result<()> {
+        if !self.addr_book.is_empty() {
+            let mut addrs_path = path.as_os_str().to_owned();
+            addrs_path.push(".addrs");
+            let addrs_path = PathBuf::from(addrs_path);
+            self.addr_book
+                .write_csv(BufWriter::new(File::create(&addrs_path)?))?;
+            lines.insert(0, format!("import {}", addrs_path.display()));
+        }
+
+        match self.advertised {
+            Advertised::Off => {}
+            Advertised::Addr(addr) => lines.push(format!("advertise {addr}")),
+            Advertised::External => lines.push("advertise external".to_string()),
+        }
+        if let Some(dir) = &self.dump_dir {
+            lines.push(format!("dump {}", dir.display()));
+        }
+        if let Some(conn) = &self.conn {
+            let mut line = format!("connect {}", conn.peer_addr()?);
+            if !conn.relay() {
+                line += " norelay";
+            }
+            if let Some(network) = Network::from_magic(conn.magic()) {
+                line += &format!(" {network}");
+            }
+            lines.push(line);
         }
 
-        self.send_msg(BitcoinMsg::verack())?;
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "# btc session, restore with: session load <path>")?;
+        for line in &lines {
+            writeln!(file, "{line}")?;
+        }
+        file.flush()?;
+
+        self.log_tx
+            .send(LogMsg::info(format!("Saved session to {}", path.display())))
+            .unwrap();
+        Ok(())
+    }
+
+    // Disconnects the peer if it is in the subnet, as Core does. Returns
+    // false if the subnet is banned already.
+    fn ban(&mut self, subnet: Subnet, secs: u64) -> Result<bool> {
+        if !self.bans.ban(subnet, secs, net::unix_time()) {
+            return Ok(false);
+        }
+        self.save_bans();
+
+        let banned_peer = self
+            .conn
+            .as_ref()
+            .and_then(|conn| conn.peer_addr().ok())
+            .is_some_and(|addr| subnet.contains(addr.ip()));
+        if banned_peer {
+            self.disconnect()?;
+        }
+        Ok(true)
+    }
 
-        if let Some(stream) = &self.stream {
-            stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+    // Only what was banned can be unbanned, not an address in a banned
+    // subnet.
+    fn unban(&mut self, subnet: &Subnet) -> bool {
+        if !self.bans.unban(subnet) {
+            return false;
+        }
+        self.save_bans();
+        true
+    }
 
+    fn list_banned(&mut self) {
+        let now = net::unix_time();
+        self.bans.sweep(now);
+        if self.bans.is_empty() {
+            self.log_tx.send(LogMsg::info("No bans")).unwrap();
+        }
+        for (subnet, ban) in self.bans.iter() {
             self.log_tx
                 .send(LogMsg::info(format!(
-                    "Connected to address {}",
-                    stream.peer_addr().unwrap()
+                    "{subnet}: banned {} ago, {} left",
+                    net::format_duration(now.saturating_sub(ban.created).max(0) as u64),
+                    net::format_duration((ban.until - now) as u64)
                 )))
                 .unwrap();
+        }
+    }
+
+    // In the layout of Core's banlist.json if the file name ends in .json,
+    // CSV otherwise.
+    fn export_bans(&mut self, path: &Path) -> Result<()> {
+        self.bans.sweep(net::unix_time());
+        let mut file = BufWriter::new(File::create(path)?);
+        if path.extension().is_some_and(|ext| ext == "json") {
+            self.bans.write_json(&mut file)?;
         } else {
-            unreachable!()
+            self.bans.write_csv(&mut file)?;
         }
+        file.flush()?;
+
+        self.log_tx
+            .send(LogMsg::info(format!(
+                "Exported {} bans to {}",
+                self.bans.len(),
+                path.display()
+            )))
+            .unwrap();
+        Ok(())
+    }
 
+    // The peer is disconnected if one of the new bans covers it.
+    fn import_bans(&mut self, path: &Path) -> Result<()> {
+        let file = BufReader::new(File::open(path)?);
+        let bans = if path.extension().is_some_and(|ext| ext == "json") {
+            BanList::read_json(file)?
+        } else {
+            BanList::read_csv(file)?
+        };
+        let new = self.bans.merge(bans);
+        self.save_bans();
+
+        self.log_tx
+            .send(LogMsg::info(format!(
+                "Imported {new} new bans from {}, {} in place",
+                path.display(),
+                self.bans.len()
+            )))
+            .unwrap();
+
+        let banned_peer = self
+            .conn
+            .as_ref()
+            .and_then(|conn| conn.peer_addr().ok())
+            .is_some_and(|addr| self.bans.is_banned(addr.ip(), net::unix_time()));
+        if banned_peer {
+            self.disconnect()?;
+        }
         Ok(())
     }
 
-    fn disconnect(&mut self) -> Result<()> {
-        let addr = self.stream.as_ref().map(|s| s.peer_addr());
-        self.stream = None;
+    // Bans that ran out are dropped on the way.
+    fn save_bans(&mut self) {
+        self.bans.sweep(net::unix_time());
+        let Some(path) = &self.bans_path else {
+            return;
+        };
+        if let Err(e) = self.bans.save(path) {
+            self.log_tx
+                .send(LogMsg::warn(format!("Could not save bans: {e}")))
+                .unwrap();
+        }
+    }
 
-        if let Some(addr) = addr {
-            self.log_tx.send(LogMsg::info(format!("Disconnecting from {}", addr?))).unwrap();
-        } else {
-            self.log_tx.send(LogMsg::info("Already Disconnected")).unwrap();
+    fn connect(&mut self, addr: SocketAddr, relay: bool, network: Network) -> Result<()> {
+        if self.bans.is_banned(addr.ip(), net::unix_time()) {
+            return Err(Error::with_msg(
+                ErrorKind::IoErr(io::ErrorKind::PermissionDenied.into()),
+                format!("{addr} is banned"),
+            ));
+        }
+        let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        let mut source = mio::net::TcpStream::from_std(stream.try_clone()?);
+        let mut conn = Connection::new(stream);
+        conn.set_handshake_timeout(CONNECT_TIMEOUT);
+        let events = conn.subscribe();
+        conn.set_metrics(self.metrics.clone());
+        conn.set_relay(relay);
+        conn.set_magic(network.magic());
+        conn.set_dump_dir(self.dump_dir.clone());
+
+        conn.handshake()?;
+        // From here on messages the socket can't take right away are
+        // queued by the connection and written out as it becomes writable.
+        conn.transport().set_nonblocking(true)?;
+        self.drop_conn();
+        self.registry.register(
+            &mut source,
+            CONN_TOKEN,
+            Interest::READABLE | Interest::WRITABLE,
+        )?;
+        self.conn_source = Some(source);
+
+        if let Some(offset) = conn.time_offset() {
+            self.adjusted_time.add_sample(addr.ip(), offset);
+        }
+        if let Some(version) = conn.peer_version() {
+            self.external_addr
+                .add_sample(addr.ip(), version.remote.addr);
+        }
+        conn.set_advertised_addr(self.advertised_addr(&conn));
+        if network == self.network {
+            let services = conn.peer_version().map(|version| version.services.clone());
+            self.add_anchor(NetAddr {
+                services: services.unwrap_or_default(),
+                addr,
+            });
         }
 
+        self.conn = Some(conn);
+        self.events = Some(events);
+
         Ok(())
     }
-}
 
-fn bitcoin_handling(mut client: Client, rx: Receiver<ClientCommand>) -> Result<()> {
-    loop {
-        for cmd in rx.try_iter() {
-            if let Err(e) = client.handle_cmds(cmd) {
-                if let ErrorKind::IoErr(_) = e.kind {
-                    return Err(e);
-                } else if let Some(msg) = e.msg {
-                        client.log_tx.send(LogMsg::err(msg)).unwrap();
+    // Connects to the first of the addresses `addr` resolves to that
+    // completes a handshake.
+    fn connect_host(&mut self, addr: &str, relay: bool, network: Network) -> Result<()> {
+        let peer = PeerAddr::parse(addr, network.default_port())?;
+        let candidates = peer.resolve().map_err(|e| {
+            let msg = format!("Could not resolve \"{peer}\": {e}");
+            Error::with_msg(ErrorKind::IoErr(e), msg)
+        })?;
+
+        let mut ret = Ok(());
+        for (i, candidate) in candidates.iter().enumerate() {
+            if i > 0 {
+                self.log_tx
+                    .send(LogMsg::info(format!("Trying {candidate} for {peer}")))
+                    .unwrap();
+            }
+            ret = self.connect(*candidate, relay, network);
+            match &ret {
+                Ok(()) if matches!(peer, PeerAddr::Host { .. }) => {
+                    self.log_tx
+                        .send(LogMsg::info(format!("Reached {peer} at {candidate}")))
+                        .unwrap();
+                    break;
                 }
+                Ok(()) => break,
+                Err(e) if i + 1 < candidates.len() => self
+                    .log_tx
+                    .send(LogMsg::warn(format!(
+                        "Could not connect to {candidate}: {}",
+                        e.msg.clone().unwrap_or_else(|| format!("{:?}", e.kind))
+                    )))
+                    .unwrap(),
+                Err(_) => {}
             }
         }
+        ret
+    }
 
-        let msg = client.read_msg();
+    // The most recent successful peers come first.
+    fn add_anchor(&mut self, anchor: NetAddr) {
+        let Some(path) = &self.anchors_path else {
+            return;
+        };
 
-        if let Err(Error {
-            kind: ErrorKind::NotConnected,
-            ..
-        }) = msg
-        {
-            continue;
+        self.anchors.retain(|a| a.addr != anchor.addr);
+        self.anchors.insert(0, anchor);
+        self.anchors.truncate(net::MAX_ANCHORS);
+        if let Err(e) = net::write_anchors(path, self.network.magic(), &self.anchors) {
+            self.log_tx
+                .send(LogMsg::warn(format!("Could not save anchors: {e}")))
+                .unwrap();
         }
+    }
 
-        if let Err(e) = msg {
-            match e.kind {
-                ErrorKind::IoErr(e) if io::ErrorKind::TimedOut == e.kind() => (),
-                ErrorKind::IoErr(e) if io::ErrorKind::WouldBlock == e.kind() => (),
-                _ => client
+    fn connect_anchors(&mut self) -> Result<()> {
+        let Some(path) = &self.anchors_path else {
+            return Ok(());
+        };
+
+        let anchors = match net::read_anchors(path, self.network.magic()) {
+            Ok(anchors) => anchors,
+            Err(e) => {
+                self.log_tx
+                    .send(LogMsg::warn(format!("Ignoring anchors: {e}")))
+                    .unwrap();
+                return Ok(());
+            }
+        };
+
+        for anchor in anchors {
+            self.log_tx
+                .send(LogMsg::info(format!(
+                    "Connecting to anchor {}",
+                    anchor.addr
+                )))
+                .unwrap();
+            match self.connect(anchor.addr, true, self.network) {
+                Ok(()) => return Ok(()),
+                Err(e) => self
                     .log_tx
-                    .send(LogMsg::err(format!("Failed to read Message: {e:?}")))
+                    .send(LogMsg::warn(format!(
+                        "Could not connect to anchor {}: {}",
+                        anchor.addr,
+                        e.msg.unwrap_or_else(|| format!("{:?}", e.kind))
+                    )))
                     .unwrap(),
             }
+        }
 
-            continue;
+        Ok(())
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        if let Some(conn) = self.conn.take() {
+            self.log_tx
+                .send(LogMsg::info(format!(
+                    "Disconnecting from {}",
+                    conn.peer_addr()?
+                )))
+                .unwrap();
+            conn.disconnect();
+            self.drop_conn();
+        } else {
+            self.log_tx
+                .send(LogMsg::info("Already Disconnected"))
+                .unwrap();
         }
 
-        let msg = msg.unwrap();
+        Ok(())
+    }
 
-        match msg.payload {
-            BitcoinPayload::Inv(p) => {
-                client
-                    .log_tx
+    fn peers(&mut self, sort: PeerSort) -> Result<()> {
+        let conns = match sort {
+            PeerSort::Connection => self.conn.iter().collect(),
+            PeerSort::Quality => net::rank_by_quality(&self.conn),
+        };
+        if conns.is_empty() {
+            self.log_tx
+                .send(LogMsg::info("No connected peers"))
+                .unwrap();
+        }
+
+        for conn in conns {
+            let addr = conn.peer_addr()?;
+            let mut line = format!(
+                "peer {addr} [{}, group {}]",
+                AddressType::of(&addr.ip()),
+                NetGroup::of(&addr.ip())
+            );
+            if let Some(network) = Network::from_magic(conn.magic()) {
+                line += &format!(" ({network})");
+            }
+            if let Some(version) = conn.peer_version() {
+                line += &format!(" {} proto {}", version.user_agent, version.proto_ver);
+            }
+            if let Some(fingerprint) = conn.fingerprint() {
+                line += &format!(" looks like {fingerprint}");
+            }
+            if let Some(offset) = conn.time_offset() {
+                line += &format!(" clock offset {offset:+}s");
+            }
+            if let Some(latency) = conn.latency() {
+                line += &format!(" latency {}ms", latency.as_millis());
+            }
+            line += &format!(
+                " relay: us {}, peer {}",
+                if conn.relay() { "on" } else { "off" },
+                if conn.peer_relay() { "on" } else { "off" }
+            );
+            line += &format!(" quality {:.2}", conn.quality_score());
+            if conn.queued_bytes() > 0 {
+                line += &format!(" {} bytes queued", conn.queued_bytes());
+            }
+            self.log_tx.send(LogMsg::info(line)).unwrap();
+
+            let latencies = conn.latency_histogram();
+            if let (Some(min), Some(mean), Some(max)) =
+                (latencies.min(), latencies.mean(), latencies.max())
+            {
+                let buckets: Vec<String> = latencies
+                    .buckets()
+                    .map(|(bound, count)| match bound {
+                        Some(bound) => format!("<={bound}ms: {count}"),
+                        None => format!("slower: {count}"),
+                    })
+                    .collect();
+                self.log_tx
                     .send(LogMsg::info(format!(
-                        "Got {} new objects",
-                        p.inventory.len()
+                        "  latency over {} samples: min {}ms, mean {}ms, max {}ms; {}",
+                        latencies.count(),
+                        min.as_millis(),
+                        mean.as_millis(),
+                        max.as_millis(),
+                        buckets.join(", ")
                     )))
                     .unwrap();
+            }
+        }
+
+        let fingerprints: Vec<_> = self.conn.iter().filter_map(|c| c.fingerprint()).collect();
+        if !fingerprints.is_empty() {
+            let counts: Vec<String> = net::implementation_counts(&fingerprints)
+                .iter()
+                .map(|(implementation, count)| format!("{implementation}: {count}"))
+                .collect();
+            self.log_tx
+                .send(LogMsg::info(format!(
+                    "implementations: {}",
+                    counts.join(", ")
+                )))
+                .unwrap();
+        }
+
+        let median = match self.adjusted_time.median_offset() {
+            Some(median) => format!("{median:+}s"),
+            None => String::from("n/a"),
+        };
+        self.log_tx
+            .send(LogMsg::info(format!(
+                "network-adjusted time: {} (offset {:+}s, median {median} over {} samples)",
+                self.adjusted_time.now(),
+                self.adjusted_time.offset(),
+                self.adjusted_time.sample_count(),
+            )))
+            .unwrap();
+
+        Ok(())
+    }
 
-                for inv in p.inventory.iter() {
-                    let mut send_str = String::new();
-                    write!(send_str, "{:?}: ", inv.kind).unwrap();
-                    for x in inv.hash.iter().rev() {
-                        write!(send_str, "{x:02x}").unwrap();
+    fn rpc(&mut self, method: &str, params: &[Value]) -> result::Result<Value, RpcError> {
+        match method {
+            "getpeerinfo" => Ok(json!(self
+                .conn
+                .iter()
+                .map(|conn| rpc::peer_info(0, conn))
+                .collect::<Vec<_>>())),
+            "getnetworkinfo" => Ok(json!({
+                "version": 0,
+                "subversion": net::USER_AGENT,
+                "protocolversion": PROTOCOL_VERSION,
+                "localrelay": self.conn.as_ref().is_none_or(|conn| conn.relay()),
+                "timeoffset": self.adjusted_time.offset(),
+                "networkactive": true,
+                "connections": self.conn.iter().count(),
+                "networks": [],
+                "localaddresses": self.external_addr.ip().map(|ip| json!({
+                    "address": ip.to_string(),
+                    "port": self.network.default_port(),
+                    "score": self.external_addr.best().map_or(0, |(_, votes)| votes),
+                })).into_iter().collect::<Vec<_>>(),
+                "warnings": "",
+            })),
+            "sendrawtransaction" => {
+                let Some(hex) = params.first().and_then(Value::as_str) else {
+                    return Err(RpcError::invalid_params("Expected a hex string"));
+                };
+                let payload = parse_hex(hex);
+                let Some(txid) = payload.as_deref().and_then(crypto::txid) else {
+                    return Err(RpcError::new(
+                        rpc::RPC_DESERIALIZATION_ERROR,
+                        "TX decode failed",
+                    ));
+                };
+                let Some(conn) = &mut self.conn else {
+                    return Err(RpcError::new(
+                        rpc::RPC_CLIENT_NOT_CONNECTED,
+                        "Client not connected",
+                    ));
+                };
+
+                let msg = BitcoinMsg {
+                    payload: BitcoinPayload::Unknown {
+                        command: Command::from("tx"),
+                        payload: payload.unwrap(),
+                    },
+                };
+                conn.send_msg(&msg).map_err(|e| {
+                    let msg = e.msg.unwrap_or_else(|| format!("{:?}", e.kind));
+                    RpcError::new(rpc::RPC_MISC_ERROR, msg)
+                })?;
+
+                Ok(json!(txid.to_string()))
+            }
+            "setban" => {
+                let subnet = params.first().and_then(Value::as_str);
+                let Some(subnet) = subnet.and_then(|subnet| subnet.parse::<Subnet>().ok()) else {
+                    return Err(RpcError::new(
+                        rpc::RPC_CLIENT_INVALID_IP_OR_SUBNET,
+                        "Error: Invalid IP/Subnet",
+                    ));
+                };
+                match params.get(1).and_then(Value::as_str) {
+                    Some("add") => {
+                        // Like Core, 0 is the default time, and with
+                        // absolute the time is when the ban ends.
+                        let bantime = params.get(2).and_then(Value::as_i64).unwrap_or(0);
+                        let absolute = params.get(3).and_then(Value::as_bool).unwrap_or(false);
+                        let secs = match (bantime, absolute) {
+                            (until, true) => until.saturating_sub(net::unix_time()).max(0) as u64,
+                            (..=0, false) => DEFAULT_BAN_TIME,
+                            (secs, false) => secs as u64,
+                        };
+                        match self.ban(subnet, secs) {
+                            Ok(true) => Ok(Value::Null),
+                            Ok(false) => Err(RpcError::new(
+                                rpc::RPC_CLIENT_NODE_ALREADY_ADDED,
+                                "Error: IP/Subnet already banned",
+                            )),
+                            Err(e) => Err(RpcError::new(
+                                rpc::RPC_MISC_ERROR,
+                                e.msg.unwrap_or_else(|| format!("{:?}", e.kind)),
+                            )),
+                        }
                     }
-                    client.log_tx.send(LogMsg::info(send_str)).unwrap();
+                    Some("remove") if self.unban(&subnet) => Ok(Value::Null),
+                    Some("remove") => Err(RpcError::new(
+                        rpc::RPC_CLIENT_INVALID_IP_OR_SUBNET,
+                        "Error: Unban failed. Requested address/subnet was not previously \
+                         manually banned.",
+                    )),
+                    _ => Err(RpcError::invalid_params("Expected add or remove")),
                 }
             }
-            BitcoinPayload::Ping(x) => {
-                client.send_msg(BitcoinMsg::pong(x))?;
+            "listbanned" => {
+                let now = net::unix_time();
+                self.bans.sweep(now);
+                Ok(json!(self
+                    .bans
+                    .iter()
+                    .map(|(subnet, ban)| json!({
+                        "address": subnet.to_string(),
+                        "ban_created": ban.created,
+                        "banned_until": ban.until,
+                        "ban_duration": ban.until - ban.created,
+                        "time_remaining": ban.until - now,
+                    }))
+                    .collect::<Vec<_>>()))
             }
-            BitcoinPayload::Pong(x) => {
-                client
-                    .log_tx
-                    .send(LogMsg::info(format!("Received pong with value {x}")))
-                    .unwrap();
+            "clearbanned" => {
+                self.bans.clear();
+                self.save_bans();
+                Ok(Value::Null)
             }
-            BitcoinPayload::Addr(addrs) => {
-                client
-                    .log_tx
-                    .send(LogMsg::info(format!(
-                        "Found {:#?} nodes",
-                        addrs.addr_list.len()
-                    )))
-                    .unwrap();
-                for addr in addrs.addr_list {
-                    let time_since = SystemTime::now()
-                        .duration_since(
-                            SystemTime::UNIX_EPOCH + Duration::from_secs(addr.timestamp as u64),
-                        )
-                        .unwrap()
-                        .as_secs();
-                    client
-                        .log_tx
-                        .send(LogMsg::info(format!(
-                            "addr: {}, timestamp: {}h{}m{}s",
-                            addr.addr.addr,
-                            time_since / 3600,
-                            (time_since % 3600) / 60,
-                            time_since % 60,
-                        )))
+            // These need a chain and a mempool, which the client doesn't keep.
+            "getblockheader" | "getrawmempool" => Err(RpcError::new(
+                rpc::RPC_MISC_ERROR,
+                format!("{method} is not supported by this client"),
+            )),
+            _ => Err(RpcError::method_not_found(method)),
+        }
+    }
+
+    fn handle_events(&mut self) {
+        let Some(events) = &self.events else {
+            return;
+        };
+
+        let mut disconnected = false;
+        let peer = self.conn.as_ref().and_then(|conn| conn.peer_addr().ok());
+
+        for event in events.try_iter() {
+            self.notifier.publish_event(&event);
+            if let (Some(recorder), Some(peer)) = (&mut self.recorder, peer) {
+                if let Err(e) = recorder.record(peer, &event).and_then(|_| recorder.flush()) {
+                    self.log_tx
+                        .send(LogMsg::err(format!("Stopped recording events: {e}")))
                         .unwrap();
+                    self.recorder = None;
                 }
             }
-            _ => client
-                .log_tx
-                .send(LogMsg::warn(format!("Could not handle message {msg:?}")))
-                .unwrap(),
-        };
+
+            match event {
+                net::Event::PeerConnected { addr, .. } => {
+                    self.log_tx
+                        .send(LogMsg::info(format!("Connected to address {addr}")))
+                        .unwrap();
+                }
+                net::Event::InvReceived(p) => {
+                    let summary = format!("Got {} new objects", p.inventory.len());
+                    let events = p.inventory.into_iter().map(LogEvent::Inv).collect();
+                    self.log_events(summary, events);
+                }
+                net::Event::PingReceived(_) => {}
+                net::Event::PongReceived(x) => {
+                    self.log_tx
+                        .send(LogMsg::info(format!("Received pong with value {x}")))
+                        .unwrap();
+                }
+                net::Event::AddrReceived(addrs) => {
+                    let summary = format!("Found {} nodes", addrs.addr_list.len());
+                    for addr in &addrs.addr_list {
+                        self.addr_book.add(addr.clone());
+                    }
+                    let events = addrs.addr_list.into_iter().map(LogEvent::Addr).collect();
+                    self.log_events(summary, events);
+                }
+                net::Event::MessageReceived(msg) => self
+                    .log_tx
+                    .send(LogMsg::warn(format!("Could not handle message {msg:?}")))
+                    .unwrap(),
+                net::Event::Disconnected(reason) => {
+                    match reason {
+                        DisconnectReason::Local => {}
+                        DisconnectReason::ClosedByPeer => self
+                            .log_tx
+                            .send(LogMsg::warn("Peer closed the connection"))
+                            .unwrap(),
+                        DisconnectReason::Error(e) => self
+                            .log_tx
+                            .send(LogMsg::err(format!("Connection lost: {e}")))
+                            .unwrap(),
+                    }
+                    disconnected = true;
+                }
+            }
+        }
+
+        if disconnected {
+            self.drop_conn();
+        }
+    }
+
+    // The summary and a row for each event, or the summary alone if
+    // there are too many rows.
+    fn log_events(&self, summary: String, events: Vec<LogEvent>) {
+        let rows: Vec<LogMsg> = events.into_iter().map(LogMsg::event).collect();
+        if rows.len() > FOLD_ROWS {
+            self.log_tx.send(LogMsg::folded(summary, rows)).unwrap();
+            return;
+        }
+
+        self.log_tx.send(LogMsg::info(summary)).unwrap();
+        for row in rows {
+            self.log_tx.send(row).unwrap();
+        }
+    }
+
+    fn drop_conn(&mut self) {
+        if let Some(mut source) = self.conn_source.take() {
+            // The socket may be closed already, taking its registration
+            // with it.
+            let _ = self.registry.deregister(&mut source);
+        }
+        self.conn = None;
+        self.events = None;
+    }
+}
+
+// Sleeps until a command comes in or the connection has data, instead of
+// polling the socket with a read timeout.
+fn bitcoin_handling(mut client: Client, mut poll: Poll, rx: Receiver<ClientCommand>) -> Result<()> {
+    let mut events = Events::with_capacity(16);
+
+    loop {
+        let timeout = client.conn.is_some().then_some(HOUSEKEEPING_INTERVAL);
+        if let Err(e) = poll.poll(&mut events, timeout) {
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e.into());
+        }
+
+        for cmd in rx.try_iter() {
+            if let Err(e) = client.handle_cmds(cmd) {
+                let msg = match e {
+                    Error { msg: Some(msg), .. } => msg,
+                    Error {
+                        kind: ErrorKind::IoErr(e),
+                        ..
+                    } => e.to_string(),
+                    Error { kind, .. } => format!("{kind:?}"),
+                };
+                client.log_tx.send(LogMsg::err(msg)).unwrap();
+            }
+        }
+
+        // Readiness is edge triggered, so read until the socket runs dry.
+        // This also runs on every wakeup so the connection's timers fire
+        // and its outbound queue is written out.
+        loop {
+            let Err(e) = client.process_next_msg() else {
+                continue;
+            };
+            match e.kind {
+                ErrorKind::NotConnected => (),
+                // Running dry is expected, and lost connections are reported
+                // through net::Event::Disconnected.
+                ErrorKind::IoErr(_) => (),
+                _ => client
+                    .log_tx
+                    .send(LogMsg::err(format!("Failed to read Message: {e:?}")))
+                    .unwrap(),
+            }
+            break;
+        }
+
+        client.handle_events();
     }
 }
 
+const RPC_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Calls are answered by the client thread, which owns the connection.
+fn client_call(
+    tx: &CommandSender,
+    method: &str,
+    params: &[Value],
+) -> result::Result<Value, RpcError> {
+    let (reply, response) = mpsc::channel();
+    tx.send(ClientCommand::Rpc {
+        method: method.to_string(),
+        params: params.to_vec(),
+        reply,
+    })
+    .map_err(|_| RpcError::new(rpc::RPC_MISC_ERROR, "Client stopped"))?;
+
+    response
+        .recv_timeout(RPC_TIMEOUT)
+        .map_err(|_| RpcError::new(rpc::RPC_MISC_ERROR, "Client did not respond"))?
+}
+
+fn serve_rpc(
+    addr: SocketAddr,
+    auth: Option<(&str, &str)>,
+    tx: CommandSender,
+) -> io::Result<thread::JoinHandle<()>> {
+    rpc::serve(addr, auth, move |method: &str, params: &[Value]| {
+        client_call(&tx, method, params)
+    })
+}
+
+const RECENT_LOG_SIZE: usize = 100;
+
+type RecentLog = Arc<Mutex<VecDeque<Value>>>;
+
+fn serve_rest(
+    addr: SocketAddr,
+    tx: CommandSender,
+    metrics: Arc<Metrics>,
+    recent_log: RecentLog,
+    start: Instant,
+) -> io::Result<thread::JoinHandle<()>> {
+    rpc::serve_rest(addr, move |path: &str| {
+        let peers = || client_call(&tx, "getpeerinfo", &[]).unwrap_or_else(|e| json!(e.message));
+        let log = || json!(*recent_log.lock().unwrap());
+        let stats = || {
+            let counters = metrics.snapshot();
+            let bytes = |direction| counters.bytes.get(&direction).copied().unwrap_or(0);
+            json!({
+                "uptime": start.elapsed().as_secs(),
+                "peers": counters.peers,
+                "bytesrecv": bytes(Direction::Received),
+                "bytessent": bytes(Direction::Sent),
+                "handshakefailures": counters.handshake_failures,
+                "decodeerrors": counters.decode_errors,
+            })
+        };
+
+        match path {
+            "/" => Some(json!({ "stats": stats(), "peers": peers(), "log": log() })),
+            "/stats" => Some(stats()),
+            "/peers" => Some(peers()),
+            "/log" => Some(log()),
+            _ => None,
+        }
+    })
+}
+
 const COMMAND_AREA_ROWS: u16 = 2;
+const STATUS_BAR_ROWS: u16 = 1;
+
+// One line of `msg`, without moving to the next. `repeats` is how many
+// times in a row the message came.
+fn print_log_line(
+    stdout: &mut io::Stdout,
+    clock: &str,
+    msg: &LogMsg,
+    line: &str,
+    repeats: usize,
+) -> io::Result<()> {
+    stdout
+        .queue(style::SetForegroundColor(style::Color::DarkGrey))?
+        .queue(style::Print(clock))?
+        .queue(style::ResetColor)?
+        .queue(style::Print(" "))?;
+    if let Some(event) = &msg.event {
+        // The kind is padded to LOG_KIND_WIDTH, or longer.
+        let kind_width = event.kind().len().max(LOG_KIND_WIDTH);
+        let (kind, columns) = line.split_at(kind_width);
+        stdout
+            .queue(style::SetForegroundColor(event.color()))?
+            .queue(style::Print(kind))?
+            .queue(style::ResetColor)?
+            .queue(style::Print(columns))?;
+    } else {
+        match msg.kind {
+            LogMsgKind::Info => stdout
+                .queue(style::SetForegroundColor(style::Color::Blue))?
+                .queue(style::Print("INFO: "))?,
+            LogMsgKind::Warn => stdout
+                .queue(style::SetForegroundColor(style::Color::Yellow))?
+                .queue(style::Print("WARN: "))?,
+            LogMsgKind::Error => stdout
+                .queue(style::SetForegroundColor(style::Color::Red))?
+                .queue(style::Print("ERROR: "))?,
+        }
+        .queue(style::Print(line))?
+        .queue(style::ResetColor)?;
+    }
+
+    if repeats > 1 {
+        stdout
+            .queue(style::SetForegroundColor(style::Color::DarkGrey))?
+            .queue(style::Print(format!(" x{repeats}")))?
+            .queue(style::ResetColor)?;
+    }
+    Ok(())
+}
+
+// Some of what is logged comes from peers, and the escape sequences a
+// terminal would run can't be allowed through. Newlines split lines.
+fn strip_control(msg: &mut LogMsg) {
+    if msg.msg.chars().any(|c| c.is_control() && c != '\n') {
+        msg.msg = msg
+            .msg
+            .chars()
+            .filter(|&c| !c.is_control() || c == '\n')
+            .collect();
+    }
+}
+
+fn bottom_rows(status_bar: bool) -> u16 {
+    if status_bar {
+        COMMAND_AREA_ROWS + STATUS_BAR_ROWS
+    } else {
+        COMMAND_AREA_ROWS
+    }
+}
+
+fn scroll_log(
+    stdout: &mut io::Stdout,
+    rows: u16,
+    dist: u16,
+    command: &str,
+    status_bar: bool,
+) -> io::Result<()> {
+    // Whatever is on the status bar row would scroll into the log area.
+    if status_bar {
+        stdout
+            .queue(cursor::MoveTo(0, rows - 2))?
+            .queue(terminal::Clear(terminal::ClearType::CurrentLine))?;
+    }
+
+    stdout
+        .queue(cursor::MoveTo(0, rows - 1))?
+        .queue(terminal::Clear(terminal::ClearType::CurrentLine))?
+        .queue(terminal::ScrollUp(dist))?
+        .queue(cursor::MoveTo(0, rows - 1))?
+        .queue(style::Print("> "))?
+        .queue(style::Print(command))?;
+
+    Ok(())
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// The time of day log lines are marked with, as HH:MM:SS in the local
+// time zone or in UTC. Always digits and colons, whatever the locale.
+fn clock_time(unix: i64, utc: bool) -> String {
+    let offset = if utc { 0 } else { local_utc_offset(unix) };
+    let secs = (unix + offset).rem_euclid(24 * 3600);
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
+
+#[cfg(unix)]
+fn local_utc_offset(unix: i64) -> i64 {
+    let time = unix as libc::time_t;
+    // SAFETY: tm is plain data, and localtime_r only writes to it.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+// Without a portable way to get the time zone, log in UTC.
+#[cfg(not(unix))]
+fn local_utc_offset(_unix: i64) -> i64 {
+    0
+}
+
+fn format_bytes(bytes: f64) -> String {
+    if bytes >= 1024.0 * 1024.0 {
+        format!("{:.1}MiB", bytes / (1024.0 * 1024.0))
+    } else if bytes >= 1024.0 {
+        format!("{:.1}KiB", bytes / 1024.0)
+    } else {
+        format!("{bytes:.0}B")
+    }
+}
+
+fn received(counters: &Counters, command: &str) -> u64 {
+    counters
+        .messages
+        .get(&(Direction::Received, command.to_string()))
+        .copied()
+        .unwrap_or(0)
+}
+
+fn stats_report(counters: &Counters, uptime: Duration) -> Vec<String> {
+    let secs = uptime.as_secs_f64().max(1.0);
+    let bytes = |direction| counters.bytes.get(&direction).copied().unwrap_or(0);
+
+    let mut ret = vec![
+        format!("uptime: {}", net::format_duration(uptime.as_secs())),
+        format!("connected peers: {}", counters.peers),
+        format!(
+            "bandwidth: in {} ({}/s), out {} ({}/s)",
+            format_bytes(bytes(Direction::Received) as f64),
+            format_bytes(bytes(Direction::Received) as f64 / secs),
+            format_bytes(bytes(Direction::Sent) as f64),
+            format_bytes(bytes(Direction::Sent) as f64 / secs),
+        ),
+        format!("inv rate: {:.2}/s", received(counters, "inv") as f64 / secs),
+    ];
+
+    for ((direction, command), count) in &counters.messages {
+        let key = (*direction, command.clone());
+        let command_bytes = counters.command_bytes.get(&key).copied().unwrap_or(0);
+        ret.push(format!(
+            "{} {command}: {count} ({:.2}/s), {} ({:.0}%)",
+            direction.as_str(),
+            *count as f64 / secs,
+            format_bytes(command_bytes as f64),
+            100.0 * command_bytes as f64 / bytes(*direction).max(1) as f64,
+        ));
+    }
+
+    ret
+}
+
+const BANDWIDTH_SUMMARY_COMMANDS: usize = 5;
+
+// The commands that took the most bandwidth between two samples, both
+// directions together.
+fn bandwidth_summary(prev: &Counters, cur: &Counters, elapsed: Duration) -> String {
+    let mut by_command: BTreeMap<&str, u64> = BTreeMap::new();
+    for ((direction, command), bytes) in &cur.command_bytes {
+        let before = prev
+            .command_bytes
+            .get(&(*direction, command.clone()))
+            .copied()
+            .unwrap_or(0);
+        *by_command.entry(command).or_default() += bytes - before;
+    }
+    let total: u64 = by_command.values().sum();
+
+    let mut top: Vec<(&str, u64)> = by_command
+        .into_iter()
+        .filter(|(_, bytes)| *bytes > 0)
+        .collect();
+    top.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+    top.truncate(BANDWIDTH_SUMMARY_COMMANDS);
+
+    let top: Vec<String> = top
+        .iter()
+        .map(|(command, bytes)| {
+            format!(
+                "{command} {} ({:.0}%)",
+                format_bytes(*bytes as f64),
+                100.0 * *bytes as f64 / total as f64
+            )
+        })
+        .collect();
+    format!(
+        "last {}s: {} total{}{}",
+        elapsed.as_secs(),
+        format_bytes(total as f64),
+        if top.is_empty() { "" } else { ", " },
+        top.join(", ")
+    )
+}
+
+fn status_line(prev: &Counters, cur: &Counters, elapsed: Duration, start: Instant) -> String {
+    let secs = elapsed.as_secs_f64();
+    let bytes =
+        |counters: &Counters, direction| counters.bytes.get(&direction).copied().unwrap_or(0);
+    let rate = |direction| (bytes(cur, direction) - bytes(prev, direction)) as f64 / secs;
+    let msgs: u64 = cur.messages.values().sum::<u64>() - prev.messages.values().sum::<u64>();
+
+    format!(
+        " up {} | peers {} | {:.1} msg/s | inv {:.1}/s | in {}/s | out {}/s ",
+        net::format_duration(start.elapsed().as_secs()),
+        cur.peers,
+        msgs as f64 / secs,
+        (received(cur, "inv") - received(prev, "inv")) as f64 / secs,
+        format_bytes(rate(Direction::Received)),
+        format_bytes(rate(Direction::Sent)),
+    )
+}
+
+// The terminal UI's state, which commands typed at the prompt act on.
+struct Ui {
+    stdout: io::Stdout,
+    rows: u16,
+    cols: u16,
+    tx: CommandSender,
+    log_tx: Sender<LogMsg>,
+    notifier: Arc<Notifier>,
+    metrics: Arc<Metrics>,
+    recent_log: RecentLog,
+    start: Instant,
+    default_network: Network,
+    // Where aliases are saved, None without --datadir.
+    config_path: Option<PathBuf>,
+    aliases: BTreeMap<String, String>,
+    // What is typed at the prompt so far.
+    command: String,
+    log_cursor_position: (u16, u16),
+    status_bar: bool,
+    status: String,
+    last_sample: (Instant, Counters),
+    // Interval, time and counters of the last periodic bandwidth summary.
+    stats_every: Option<(Duration, Instant, Counters)>,
+    // Set while picking something to copy from the log.
+    picker: Option<Picker>,
+    // Whether log lines are timestamped in UTC instead of local time.
+    utc: bool,
+    // The last line logged and how many times in a row it came, if it
+    // can be folded into a count.
+    last_line: Option<(String, usize)>,
+    // The rows of the last folded message, for `expand`.
+    folded: Vec<LogMsg>,
+    // Running as a daemon, with no terminal to draw on.
+    headless: bool,
+}
+
+struct Picker {
+    // Newest first.
+    items: Vec<String>,
+    selected: usize,
+}
+
+impl Ui {
+    fn send(&self, cmd: ClientCommand) {
+        self.tx.send(cmd).unwrap();
+    }
+
+    fn info(&self, msg: impl ToString) {
+        self.log_tx.send(LogMsg::info(msg)).unwrap();
+    }
+
+    fn err(&self, msg: impl ToString) {
+        self.log_tx.send(LogMsg::err(msg)).unwrap();
+    }
+
+    // Adds `msg` to the recent log, and keeps what it folds for `expand`.
+    fn remember(&mut self, time: i64, msg: &mut LogMsg) {
+        {
+            let mut recent_log = self.recent_log.lock().unwrap();
+            if recent_log.len() == RECENT_LOG_SIZE {
+                recent_log.pop_front();
+            }
+            recent_log.push_back(json!({
+                "time": time,
+                "level": msg.kind.as_str(),
+                "msg": msg.msg,
+            }));
+        }
+        if !msg.folded.is_empty() {
+            self.folded = mem::take(&mut msg.folded);
+        }
+    }
+
+    // Logs a bandwidth summary if one is due, see `stats every`.
+    fn summarize_bandwidth(&mut self) {
+        if let Some((interval, last, prev)) = &mut self.stats_every {
+            if last.elapsed() >= *interval {
+                let cur = self.metrics.snapshot();
+                let summary = bandwidth_summary(prev, &cur, last.elapsed());
+                self.log_tx.send(LogMsg::info(summary)).unwrap();
+                (*last, *prev) = (Instant::now(), cur);
+            }
+        }
+    }
+}
+
+// How long a probe waits for the peer at each step of the handshake.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Shakes hands with `addr` and prints one line of JSON: the peer as
+// getpeerinfo shows it, or the address and the error. Exits with 0 if
+// the handshake succeeded, 2 if not and 1 if `addr` is malformed.
+fn probe(addr: &str, network: Network) -> ! {
+    let handshake = |addr: SocketAddr| -> result::Result<Value, net::Error> {
+        let stream = TcpStream::connect_timeout(&addr, PROBE_TIMEOUT)?;
+        stream.set_write_timeout(Some(PROBE_TIMEOUT))?;
+        let mut conn = Connection::new(stream);
+        conn.set_read_timeout(Some(PROBE_TIMEOUT))?;
+        conn.set_magic(network.magic());
+        conn.handshake()?;
+
+        let info = rpc::peer_info(0, &conn);
+        conn.disconnect();
+        Ok(info)
+    };
+
+    let peer = match PeerAddr::parse(addr, network.default_port()) {
+        Ok(peer) => peer,
+        Err(e) => {
+            eprintln!("Invalid peer address: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // The addresses a host name resolves to are tried in order.
+    let mut ret = Err(String::new());
+    match peer.resolve() {
+        Ok(candidates) => {
+            for candidate in candidates {
+                ret = handshake(candidate).map_err(|e| match (e.kind, e.msg) {
+                    (_, Some(msg)) => msg,
+                    (net::ErrorKind::IoErr(e), None) => e.to_string(),
+                    (kind, None) => format!("{kind:?}"),
+                });
+                if ret.is_ok() {
+                    break;
+                }
+            }
+        }
+        Err(e) => ret = Err(format!("Could not resolve: {e}")),
+    }
+
+    let (info, status) = match ret {
+        Ok(info) => (info, 0),
+        Err(error) => (json!({ "addr": addr, "error": error }), 2),
+    };
+    println!("{info}");
+    std::process::exit(status);
+}
 
 fn main() -> std::io::Result<()> {
+    // --network sets the network `connect` uses when none is given, and
+    // --datadir is where anchors and bans for it are kept across
+    // restarts. A peer address connects to it right away instead of to
+    // the anchors, and --probe <addr> only checks whether the peer
+    // completes a handshake. --utc timestamps the log in UTC rather
+    // than local time. --daemon runs without the TUI, taking commands
+    // on the --control socket, and `ctl <command>` runs a command on
+    // such a daemon.
+    let mut default_network = Network::Mainnet;
+    let mut utc = false;
+    let mut daemon = false;
+    let mut control_path = None;
+    let mut ctl_command = None;
+    let mut datadir = None;
+    let mut peer = None;
+    let mut probe_peer = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--network" => match args.next().map(|network| network.parse()) {
+                Some(Ok(network)) => default_network = network,
+                _ => {
+                    let names: Vec<_> = Network::ALL.iter().map(Network::as_str).collect();
+                    eprintln!("--network needs one of {}", names.join(", "));
+                    std::process::exit(1);
+                }
+            },
+            "--datadir" => match args.next() {
+                Some(dir) => datadir = Some(PathBuf::from(dir)),
+                None => {
+                    eprintln!("--datadir needs a directory");
+                    std::process::exit(1);
+                }
+            },
+            "--probe" => match args.next() {
+                Some(addr) => probe_peer = Some(addr),
+                None => {
+                    eprintln!("--probe needs a peer address");
+                    std::process::exit(1);
+                }
+            },
+            "--utc" => utc = true,
+            "--daemon" if cfg!(unix) => daemon = true,
+            "--control" => match args.next() {
+                Some(path) => control_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("--control needs a socket path");
+                    std::process::exit(1);
+                }
+            },
+            "ctl" if cfg!(unix) && peer.is_none() => {
+                ctl_command = Some(args.by_ref().collect::<Vec<_>>().join(" "));
+            }
+            _ if !arg.starts_with('-') && peer.is_none() => peer = Some(arg),
+            _ => {
+                eprintln!("Unknown argument \"{arg}\"");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(addr) = probe_peer {
+        probe(&addr, default_network);
+    }
+
+    let control_path = control_path.unwrap_or_else(|| match &datadir {
+        Some(datadir) => datadir.join("btc.sock"),
+        None => PathBuf::from("btc.sock"),
+    });
+    #[cfg(unix)]
+    if let Some(command) = ctl_command {
+        daemon::ctl(&control_path, &command);
+    }
+
+    let anchors_path = match &datadir {
+        Some(datadir) => {
+            let dir = datadir.join(default_network.as_str());
+            fs::create_dir_all(&dir)?;
+            Some(dir.join("anchors.dat"))
+        }
+        None => None,
+    };
+    let bans_path = anchors_path
+        .as_ref()
+        .map(|path| path.with_file_name("banlist.csv"));
+    // Shared by all networks, unlike the anchors.
+    let config_path = datadir.map(|datadir| datadir.join("btc.conf"));
+
     let (log_tx, rx) = mpsc::channel();
 
+    let aliases = match config_path.as_deref().map(commands::load_aliases) {
+        Some(Ok(aliases)) => aliases,
+        Some(Err(e)) => {
+            log_tx
+                .send(LogMsg::warn(format!("Ignoring aliases in btc.conf: {e}")))
+                .unwrap();
+            BTreeMap::new()
+        }
+        None => BTreeMap::new(),
+    };
+    let bans = match bans_path.as_deref().map(BanList::load) {
+        Some(Ok(bans)) => bans,
+        Some(Err(e)) => {
+            log_tx
+                .send(LogMsg::warn(format!("Ignoring banlist.csv: {e}")))
+                .unwrap();
+            BanList::new()
+        }
+        None => BanList::new(),
+    };
+
     let (tx, cmd_rx) = mpsc::channel();
+    let poll = Poll::new()?;
+    let tx = CommandSender {
+        tx,
+        waker: Arc::new(Waker::new(poll.registry(), WAKER_TOKEN)?),
+    };
+    let registry = poll.registry().try_clone()?;
+
+    let metrics = Metrics::new();
 
     let log_tx_clone = log_tx.clone();
+    let metrics_clone = metrics.clone();
+    let notifier = Notifier::new();
+    let notifier_clone = notifier.clone();
     let _handle = thread::spawn(move || {
         bitcoin_handling(
             Client {
-                stream: None,
+                conn: None,
+                conn_source: None,
+                registry,
+                events: None,
                 log_tx: log_tx_clone,
+                metrics: metrics_clone,
+                notifier: notifier_clone,
+                adjusted_time: AdjustedTime::new(),
+                external_addr: ExternalAddr::new(),
+                advertised: Advertised::Off,
+                addr_book: AddrBook::new(),
+                recorder: None,
+                dump_dir: None,
+                network: default_network,
+                anchors_path,
+                anchors: vec![],
+                bans,
+                bans_path,
             },
+            poll,
             cmd_rx,
         )
     });
 
-    let mut stdout = io::stdout();
-    terminal::enable_raw_mode()?;
+    match peer {
+        Some(addr) => tx
+            .send(ClientCommand::Connect {
+                addr,
+                relay: true,
+                network: default_network,
+            })
+            .unwrap(),
+        None => tx.send(ClientCommand::ConnectAnchors).unwrap(),
+    }
 
-    let window_size = terminal::window_size()?;
-    stdout
+    let (rows, cols) = if daemon {
+        (0, 0)
+    } else {
+        // Not window_size(), which Windows doesn't implement.
+        let (cols, rows) = terminal::size()?;
+        (rows, cols)
+    };
+    let mut ui = Ui {
+        stdout: io::stdout(),
+        rows,
+        cols,
+        tx,
+        log_tx,
+        notifier,
+        metrics: metrics.clone(),
+        recent_log: RecentLog::default(),
+        start: Instant::now(),
+        default_network,
+        config_path,
+        aliases,
+        command: String::new(),
+        log_cursor_position: (0, 0),
+        status_bar: false,
+        status: String::new(),
+        last_sample: (Instant::now(), metrics.snapshot()),
+        stats_every: None,
+        picker: None,
+        utc,
+        last_line: None,
+        folded: vec![],
+        headless: daemon,
+    };
+
+    #[cfg(unix)]
+    if daemon {
+        return daemon::run(ui, rx, &control_path);
+    }
+
+    terminal::enable_raw_mode()?;
+    ui.stdout
         .execute(terminal::Clear(terminal::ClearType::All))?
-        .execute(cursor::MoveTo(0, window_size.rows - 1))?
+        .execute(cursor::MoveTo(0, ui.rows - 1))?
         .execute(style::Print("> "))?;
 
-    let mut command = String::new();
-    let mut command_cursor_position = (2, window_size.rows - 1);
-    let mut log_cursor_position = (0, 0);
+    let mut command_cursor_position = (2, ui.rows - 1);
 
     loop {
         if event::poll(Duration::from_secs(1))? {
-            if let Event::Key(event) = event::read()? {
+            // Windows reports releasing keys too, which would type
+            // everything twice.
+            if let Event::Key(
+                event @ KeyEvent {
+                    kind: KeyEventKind::Press,
+                    ..
+                },
+            ) = event::read()?
+            {
                 if event == KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL) {
                     break;
                 }
 
-                if let KeyCode::Char(c) = event.code {
-                    command.push(c);
-                    stdout.queue(style::Print(c))?;
-                }
-
-                if event.code == KeyCode::Backspace && !command.is_empty() {
-                    command.pop();
-                    stdout
-                        .queue(cursor::MoveLeft(1))?
-                        .queue(style::Print(" "))?
-                        .queue(cursor::MoveLeft(1))?;
-                }
-
-                if event.code == KeyCode::Enter {
-                    let mut command_parsed = command.split_whitespace();
-
-                    match &command_parsed.next() {
-                        Some("connect") => {
-                            if let Some(addr) = command_parsed.next() {
-                                match SocketAddr::from_str(addr) {
-                                    Ok(addr) => tx.send(ClientCommand::Connect(addr)).unwrap(),
-                                    Err(e) => log_tx
-                                        .send(LogMsg::err(format!(
-                                            "Could not parse address \"{addr}\": {e}",
-                                        )))
-                                        .unwrap(),
-                                }
-                            } else {
-                                log_tx.send(LogMsg::err("addr not provided!")).unwrap();
-                            };
+                if ui.picker.is_some() {
+                    commands::pick(&mut ui, event.code)?;
+                } else {
+                    match event.code {
+                        KeyCode::Char(c) => {
+                            ui.command.push(c);
+                            ui.stdout.queue(style::Print(c))?;
                         }
-                        Some("disconnect") => tx
-                            .send(ClientCommand::Disconnect)
-                            .unwrap(),
-                        Some("ping") => {
-                            if let Some(value) = command_parsed.next() {
-                                match value.parse() {
-                                    Ok(value) => tx
-                                        .send(ClientCommand::SendBtcMsg(BitcoinMsg::ping(value)))
-                                        .unwrap(),
-                                    Err(e) => log_tx
-                                        .send(LogMsg::err(format!(
-                                            "Could not parse value \"{value}\": {e}"
-                                        )))
-                                        .unwrap(),
-                                }
-                            } else {
-                                log_tx
-                                    .send(LogMsg::err("ping value not provided!"))
-                                    .unwrap();
-                            };
+                        KeyCode::Backspace if !ui.command.is_empty() => {
+                            ui.command.pop();
+                            ui.stdout
+                                .queue(cursor::MoveLeft(1))?
+                                .queue(style::Print(" "))?
+                                .queue(cursor::MoveLeft(1))?;
                         }
-                        Some("getaddr") => tx
-                            .send(ClientCommand::SendBtcMsg(BitcoinMsg::getaddr()))
-                            .unwrap(),
-                        Some(cmd) => log_tx
-                            .send(LogMsg::err(format!("No command \"{cmd}\" no found")))
-                            .unwrap(),
-                        None => log_tx
-                            .send(LogMsg::err("A command must be provided"))
-                            .unwrap(),
-                    }
+                        KeyCode::Enter => {
+                            let line = ui.command.clone();
+                            commands::run(&mut ui, &line)?;
 
-                    stdout
-                        .queue(cursor::MoveToColumn(2))?
-                        .queue(style::Print(" ".repeat(command.len())))?
-                        .queue(cursor::MoveToColumn(2))?;
+                            ui.stdout
+                                .queue(cursor::MoveToColumn(2))?
+                                .queue(style::Print(" ".repeat(line.len())))?
+                                .queue(cursor::MoveToColumn(2))?;
 
-                    command.clear();
+                            ui.command.clear();
+                            commands::draw_picker(&mut ui)?;
+                        }
+                        _ => {}
+                    }
                 }
 
                 command_cursor_position = cursor::position()?;
             }
         }
 
-        stdout
-            .queue(cursor::Hide)?
-            .queue(cursor::MoveTo(log_cursor_position.0, log_cursor_position.1))?;
+        ui.stdout.queue(cursor::Hide)?.queue(cursor::MoveTo(
+            ui.log_cursor_position.0,
+            ui.log_cursor_position.1,
+        ))?;
+
+        for mut msg in rx.try_iter() {
+            strip_control(&mut msg);
+            let time = net::unix_time();
+            let clock = clock_time(time, ui.utc);
+
+            // A single line message just like the last one only bumps the
+            // count after that one, so a chatty peer can't flood the log.
+            let key = format!("{}:{}", msg.kind.as_str(), msg.msg);
+            let fits = clock.len() + msg.msg.len() + 16 < ui.cols as usize;
+            let foldable = fits && !msg.msg.contains('\n') && msg.folded.is_empty();
+            match &mut ui.last_line {
+                Some((last, repeats)) if foldable && *last == key => {
+                    *repeats += 1;
+                    let repeats = *repeats;
+                    if let Some(entry) = ui.recent_log.lock().unwrap().back_mut() {
+                        entry["time"] = json!(time);
+                        entry["repeats"] = json!(repeats);
+                    }
+                    ui.stdout
+                        .queue(cursor::MoveToPreviousLine(1))?
+                        .queue(terminal::Clear(terminal::ClearType::CurrentLine))?;
+                    print_log_line(&mut ui.stdout, &clock, &msg, &msg.msg, repeats)?;
+                    ui.stdout.queue(cursor::MoveToNextLine(1))?;
+                    continue;
+                }
+                _ => ui.last_line = foldable.then_some((key, 1)),
+            }
+
+            ui.remember(time, &mut msg);
 
-        for msg in rx.try_iter() {
             for msg_part in msg.msg.split('\n').filter(|s| !s.is_empty()) {
-                match msg.kind {
-                    LogMsgKind::Info => stdout
-                        .queue(style::SetForegroundColor(style::Color::Blue))?
-                        .queue(style::Print("INFO: "))?,
-                    LogMsgKind::Warn => stdout
-                        .queue(style::SetForegroundColor(style::Color::Yellow))?
-                        .queue(style::Print("WARN: "))?,
-                    LogMsgKind::Error => stdout
-                        .queue(style::SetForegroundColor(style::Color::Red))?
-                        .queue(style::Print("ERROR: "))?,
-                }
-                .queue(style::Print(msg_part))?
-                .queue(style::ResetColor)?
-                .queue(cursor::MoveToNextLine(1))?;
-
-                if cursor::position()?.1 > window_size.rows - COMMAND_AREA_ROWS {
-                    let dist = cursor::position()?.1 - (window_size.rows - COMMAND_AREA_ROWS);
-
-                    stdout
-                        .queue(cursor::SavePosition)?
-                        .queue(cursor::MoveTo(0, window_size.rows - 1))?
-                        .queue(style::Print(" ".repeat(command_cursor_position.0 as usize)))?
-                        .queue(terminal::ScrollUp(dist))?
-                        .queue(cursor::MoveTo(0, window_size.rows - 1))?
-                        .queue(style::Print("> "))?
-                        .queue(style::Print(command.clone()))?
+                print_log_line(&mut ui.stdout, &clock, &msg, msg_part, 1)?;
+                ui.stdout.queue(cursor::MoveToNextLine(1))?;
+
+                let last_log_row = ui.rows - bottom_rows(ui.status_bar);
+                if cursor::position()?.1 > last_log_row {
+                    let dist = cursor::position()?.1 - last_log_row;
+
+                    ui.stdout.queue(cursor::SavePosition)?;
+                    scroll_log(&mut ui.stdout, ui.rows, dist, &ui.command, ui.status_bar)?;
+                    ui.stdout
                         .queue(cursor::RestorePosition)?
                         .queue(cursor::MoveToPreviousLine(dist))?;
                 }
             }
         }
 
-        log_cursor_position = cursor::position()?;
+        ui.summarize_bandwidth();
 
-        stdout
+        ui.log_cursor_position = cursor::position()?;
+        // Scrolling the log redraws the prompt over it.
+        commands::draw_picker(&mut ui)?;
+
+        if ui.status_bar {
+            if ui.last_sample.0.elapsed() >= Duration::from_secs(1) {
+                let sample = (Instant::now(), ui.metrics.snapshot());
+                let elapsed = ui.last_sample.0.elapsed();
+                ui.status = status_line(&ui.last_sample.1, &sample.1, elapsed, ui.start);
+                ui.last_sample = sample;
+            }
+
+            ui.stdout
+                .queue(cursor::MoveTo(0, ui.rows - 2))?
+                .queue(terminal::Clear(terminal::ClearType::CurrentLine))?
+                .queue(style::SetAttribute(style::Attribute::Reverse))?
+                .queue(style::Print(&ui.status))?
+                .queue(style::SetAttribute(style::Attribute::Reset))?;
+        }
+
+        ui.stdout
             .queue(cursor::MoveTo(
                 command_cursor_position.0,
                 command_cursor_position.1,
             ))?
             .queue(cursor::Show)?;
 
-        stdout.flush()?;
+        ui.stdout.flush()?;
     }
 
     terminal::disable_raw_mode()?;
@@ -0,0 +1,183 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use btc_lib::net::Connection;
+use btc_lib::types::Magic;
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const RPC_USER: &str = "btc";
+const RPC_PASSWORD: &str = "btc";
+
+static INSTANCES: AtomicUsize = AtomicUsize::new(0);
+
+// A throwaway `bitcoind -regtest` with its own data directory and ports,
+// killed and cleaned up on drop.
+pub struct Bitcoind {
+    process: Child,
+    datadir: PathBuf,
+    p2p_addr: SocketAddr,
+    rpc_addr: SocketAddr,
+}
+
+impl Bitcoind {
+    // The binary is taken from $BITCOIND, or looked up in $PATH. Returns
+    // None when it can't be found, so tests can skip instead of failing on
+    // machines without bitcoind.
+    pub fn spawn() -> Option<Bitcoind> {
+        let exe = env::var("BITCOIND").unwrap_or_else(|_| String::from("bitcoind"));
+        let found = Command::new(&exe)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if !found {
+            eprintln!("{exe} not found, skipping (set BITCOIND to run the conformance tests)");
+            return None;
+        }
+
+        let datadir = env::temp_dir().join(format!(
+            "btc-conformance-{}-{}",
+            std::process::id(),
+            INSTANCES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&datadir);
+        fs::create_dir_all(&datadir).unwrap();
+
+        let p2p_addr = free_local_addr();
+        let rpc_addr = free_local_addr();
+
+        let process = Command::new(&exe)
+            .arg("-regtest")
+            .arg(format!("-datadir={}", datadir.display()))
+            .arg(format!("-bind={p2p_addr}"))
+            .arg(format!("-rpcport={}", rpc_addr.port()))
+            .arg(format!("-rpcuser={RPC_USER}"))
+            .arg(format!("-rpcpassword={RPC_PASSWORD}"))
+            .arg("-listen=1")
+            .arg("-server=1")
+            .arg("-connect=0")
+            .arg("-dnsseed=0")
+            .arg("-fixedseeds=0")
+            .arg("-printtoconsole=0")
+            // A fresh regtest node is in initial block download and would
+            // otherwise ignore getheaders from us.
+            .arg("-whitelist=download@127.0.0.1")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let bitcoind = Bitcoind {
+            process,
+            datadir,
+            p2p_addr,
+            rpc_addr,
+        };
+        bitcoind.wait_ready();
+
+        Some(bitcoind)
+    }
+
+    pub fn p2p_addr(&self) -> SocketAddr {
+        self.p2p_addr
+    }
+
+    // Opens a connection with the regtest magic and completes the handshake.
+    pub fn connect(&self) -> Connection {
+        let mut conn = Connection::new(TcpStream::connect(self.p2p_addr).unwrap());
+        conn.set_magic(Magic::REGTEST);
+        conn.handshake().unwrap();
+        conn
+    }
+
+    // Minimal JSON-RPC call; `params` is spliced in as raw JSON and the raw
+    // response body is returned.
+    pub fn rpc(&self, method: &str, params: &str) -> io::Result<String> {
+        let body = format!(r#"{{"jsonrpc":"1.0","id":0,"method":"{method}","params":[{params}]}}"#);
+
+        let mut stream = TcpStream::connect(self.rpc_addr)?;
+        write!(
+            stream,
+            "POST / HTTP/1.0\r\n\
+             Authorization: Basic {}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            base64(format!("{RPC_USER}:{RPC_PASSWORD}").as_bytes()),
+            body.len()
+        )?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+
+        match response.split_once("\r\n\r\n") {
+            Some((_, body)) => Ok(body.to_string()),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed RPC response",
+            )),
+        }
+    }
+
+    // Both the P2P port and the RPC server have to be up; RPC answers with
+    // a "warming up" error until the node finished loading.
+    fn wait_ready(&self) {
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+
+        while Instant::now() < deadline {
+            let p2p_up = TcpStream::connect(self.p2p_addr).is_ok();
+            let rpc_up = self
+                .rpc("getblockcount", "")
+                .is_ok_and(|body| body.contains(r#""error":null"#));
+            if p2p_up && rpc_up {
+                return;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        panic!("bitcoind did not start within {STARTUP_TIMEOUT:?}");
+    }
+}
+
+impl Drop for Bitcoind {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = fs::remove_dir_all(&self.datadir);
+    }
+}
+
+fn free_local_addr() -> SocketAddr {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut ret = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+    ret
+}
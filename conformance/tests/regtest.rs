@@ -0,0 +1,164 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use btc_conformance::Bitcoind;
+use btc_lib::net::{self, Connection, Event};
+use btc_lib::prelude::*;
+
+const REGTEST_GENESIS: &str = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
+
+// Reads until a message satisfies `f`, dropping everything else. Only
+// for messages the peer sends unasked; answers are waited for with
+// Connection::request.
+fn wait_for<R>(
+    conn: &mut Connection,
+    timeout: Duration,
+    mut f: impl FnMut(&BitcoinMsg) -> Option<R>,
+) -> R {
+    let deadline = Instant::now() + timeout;
+    conn.set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+
+    while Instant::now() < deadline {
+        match conn.read_msg() {
+            Ok(msg) => {
+                if let Some(ret) = f(&msg) {
+                    return ret;
+                }
+            }
+            Err(net::Error {
+                kind: net::ErrorKind::IoErr(e),
+                ..
+            }) if matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ) => {}
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+
+    panic!("no matching message within {timeout:?}");
+}
+
+#[test]
+fn handshake() {
+    let Some(bitcoind) = Bitcoind::spawn() else {
+        return;
+    };
+
+    let conn = bitcoind.connect();
+    let version = conn.peer_version().unwrap();
+
+    assert!(version.proto_ver >= 70014);
+    assert!(version.user_agent.starts_with("/Satoshi:"));
+    assert_eq!(version.last_block, 0);
+    assert!(version.relay);
+    assert_eq!(conn.peer_addr().unwrap(), bitcoind.p2p_addr());
+}
+
+#[test]
+fn ping_pong() {
+    let Some(bitcoind) = Bitcoind::spawn() else {
+        return;
+    };
+
+    let mut conn = bitcoind.connect();
+    let reply = conn
+        .request(BitcoinMsg::ping(0xdead_beef), Duration::from_secs(10))
+        .unwrap();
+
+    assert!(matches!(reply.payload, BitcoinPayload::Pong(0xdead_beef)));
+}
+
+#[test]
+fn pongs_in_order() {
+    let Some(bitcoind) = Bitcoind::spawn() else {
+        return;
+    };
+
+    let mut conn = bitcoind.connect();
+    let events = conn.subscribe();
+    conn.send_msg(&BitcoinMsg::ping(1)).unwrap();
+    conn.request(BitcoinMsg::ping(2), Duration::from_secs(10))
+        .unwrap();
+
+    let pongs: Vec<u64> = events
+        .try_iter()
+        .filter_map(|event| match event {
+            Event::PongReceived(nonce) => Some(nonce),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(pongs, [1, 2]);
+}
+
+#[test]
+fn getaddr() {
+    let Some(bitcoind) = Bitcoind::spawn() else {
+        return;
+    };
+
+    // bitcoind only hands out 23% of its address manager per getaddr, so
+    // seed enough addresses for the answer to be non-empty.
+    for i in 1..=20 {
+        let body = bitcoind
+            .rpc("addpeeraddress", &format!(r#""1.2.3.{i}", 8333"#))
+            .unwrap();
+        assert!(body.contains(r#""error":null"#), "{body}");
+    }
+
+    let mut conn = bitcoind.connect();
+    // Address responses go out on bitcoind's address relay timer, which
+    // averages 30s for inbound peers.
+    let reply = conn
+        .request(BitcoinMsg::getaddr(), Duration::from_secs(120))
+        .unwrap();
+
+    let BitcoinPayload::Addr(addr) = reply.payload else {
+        panic!("expected addr, got {:?}", reply.payload);
+    };
+    assert!(!addr.addr_list.is_empty());
+    for element in addr.addr_list {
+        assert_eq!(element.addr.addr.port(), 8333);
+        assert!(element.addr.addr.to_string().starts_with("1.2.3."));
+    }
+}
+
+#[test]
+fn asks_for_headers() {
+    let Some(bitcoind) = Bitcoind::spawn() else {
+        return;
+    };
+
+    let mut conn = bitcoind.connect();
+    let locator = wait_for(&mut conn, Duration::from_secs(30), |msg| {
+        match &msg.payload {
+            BitcoinPayload::GetHeaders(getheaders) => Some(getheaders.locator.clone()),
+            _ => None,
+        }
+    });
+
+    assert_eq!(locator, vec![REGTEST_GENESIS.parse::<Hash>().unwrap()]);
+}
+
+#[test]
+fn getheaders() {
+    let Some(bitcoind) = Bitcoind::spawn() else {
+        return;
+    };
+
+    let mut conn = bitcoind.connect();
+    let genesis = REGTEST_GENESIS.parse::<Hash>().unwrap();
+    let reply = conn
+        .request(
+            BitcoinMsg::getheaders(vec![genesis], Hash::default()),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+
+    // Nothing was mined, so there is nothing past genesis to send.
+    let BitcoinPayload::Headers(headers) = reply.payload else {
+        panic!("expected headers, got {:?}", reply.payload);
+    };
+    assert!(headers.headers.is_empty());
+}
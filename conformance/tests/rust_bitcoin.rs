@@ -0,0 +1,269 @@
+use std::net::SocketAddr;
+
+use bitcoin::block::{Header, Version as BlockVersion};
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::Hash as _;
+use bitcoin::p2p::address::Address;
+use bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage};
+use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::p2p::message_compact_blocks::SendCmpct as TheirSendCmpct;
+use bitcoin::p2p::message_network::VersionMessage;
+use bitcoin::p2p::{Magic as TheirMagic, ServiceFlags};
+use bitcoin::{BlockHash, CompactTarget, TxMerkleNode, Txid, Wtxid};
+use btc_lib::p2p::messages::BlockHeader;
+use btc_lib::prelude::*;
+
+// The same messages built with this library and with rust-bitcoin, which
+// have to encode to the same bytes. Run with --features rust-bitcoin.
+
+// Every services bit we have a flag for, in a few combinations.
+const SERVICES: [u64; 6] = [0, 0x1, 0x409, 0x44d, 0x45f, 0xc09];
+
+const ADDRS: [&str; 4] = [
+    "0.0.0.0:0",
+    "198.51.100.1:8333",
+    "[2001:db8::1]:18333",
+    "[::1]:65535",
+];
+
+// Around where a compact size goes from one byte to three.
+const LIST_LENGTHS: [usize; 5] = [0, 1, 0xfc, 0xfd, 0x100];
+
+fn net_addr(bits: u64, addr: &str) -> (NetAddr, Address) {
+    let addr: SocketAddr = addr.parse().unwrap();
+    (
+        NetAddr {
+            services: Services::from_bits(bits),
+            addr,
+        },
+        Address::new(&addr, ServiceFlags::from(bits)),
+    )
+}
+
+fn hash(seed: usize) -> [u8; 32] {
+    let mut ret = [0; 32];
+    for (i, byte) in ret.iter_mut().enumerate() {
+        *byte = (seed * 31 + i * 7) as u8;
+    }
+    ret
+}
+
+// Encodes both for mainnet and compares, then checks that ours decodes
+// what rust-bitcoin encoded back to the same bytes.
+fn assert_same(name: &str, ours: BitcoinMsg, theirs: NetworkMessage) {
+    let ours = ours.encode(Magic::MAINNET);
+    let theirs = serialize(&RawNetworkMessage::new(TheirMagic::BITCOIN, theirs));
+    assert_eq!(
+        hex(&ours),
+        hex(&theirs),
+        "{name}: ours on the left, rust-bitcoin's on the right"
+    );
+
+    let decoded = BitcoinMsg::decode(&mut ByteReader::new(theirs.clone()), Magic::MAINNET);
+    assert_eq!(
+        hex(&decoded.encode(Magic::MAINNET)),
+        hex(&theirs),
+        "{name}: changed by decoding"
+    );
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[test]
+fn empty_messages() {
+    assert_same("verack", BitcoinMsg::verack(), NetworkMessage::Verack);
+    assert_same("getaddr", BitcoinMsg::getaddr(), NetworkMessage::GetAddr);
+    assert_same(
+        "sendheaders",
+        BitcoinMsg {
+            payload: BitcoinPayload::SendHeaders,
+        },
+        NetworkMessage::SendHeaders,
+    );
+}
+
+#[test]
+fn integer_messages() {
+    for n in [0, 1, 0xfc, 0xffff_ffff, u64::MAX] {
+        assert_same("ping", BitcoinMsg::ping(n), NetworkMessage::Ping(n));
+        assert_same("pong", BitcoinMsg::pong(n), NetworkMessage::Pong(n));
+        assert_same(
+            "feefilter",
+            BitcoinMsg {
+                payload: BitcoinPayload::FeeFilter(FeeFilter { feerate: n >> 1 }),
+            },
+            NetworkMessage::FeeFilter((n >> 1) as i64),
+        );
+        for flag in [false, true] {
+            assert_same(
+                "sendcmpct",
+                BitcoinMsg {
+                    payload: BitcoinPayload::SendCmpct(SendCmpct { flag, integer: n }),
+                },
+                NetworkMessage::SendCmpct(TheirSendCmpct {
+                    send_compact: flag,
+                    version: n,
+                }),
+            );
+        }
+    }
+}
+
+#[test]
+fn version() {
+    for (i, bits) in SERVICES.into_iter().enumerate() {
+        for user_agent in ["", "/Satoshi:27.0.0/", "/btc:0.1.0/"] {
+            for relay in [false, true] {
+                let (remote, receiver) = net_addr(bits, ADDRS[i % ADDRS.len()]);
+                let (local, sender) = net_addr(bits, ADDRS[(i + 1) % ADDRS.len()]);
+                let ours = Version::builder()
+                    .proto_ver(70016)
+                    .services(Services::from_bits(bits))
+                    .time(1_700_000_000 + i as u64)
+                    .remote(remote)
+                    .local(local)
+                    .nonce(0x0123_4567_89ab_cdef * i as u64)
+                    .user_agent(user_agent)
+                    .last_block(800_000 + i as u32)
+                    .relay(relay)
+                    .build()
+                    .unwrap();
+                let theirs = VersionMessage {
+                    version: 70016,
+                    services: ServiceFlags::from(bits),
+                    timestamp: 1_700_000_000 + i as i64,
+                    receiver,
+                    sender,
+                    nonce: 0x0123_4567_89ab_cdef * i as u64,
+                    user_agent: user_agent.to_string(),
+                    start_height: 800_000 + i as i32,
+                    relay,
+                };
+
+                assert_same(
+                    "version",
+                    BitcoinMsg {
+                        payload: BitcoinPayload::Version(ours),
+                    },
+                    NetworkMessage::Version(theirs),
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn addr() {
+    for len in LIST_LENGTHS.into_iter().filter(|len| *len <= 1000) {
+        let (ours, theirs): (Vec<_>, Vec<_>) = (0..len)
+            .map(|i| {
+                let (ours, theirs) = net_addr(SERVICES[i % SERVICES.len()], ADDRS[i % ADDRS.len()]);
+                let timestamp = 1_700_000_000 + i as u32;
+                ((timestamp, ours), (timestamp, theirs))
+            })
+            .unzip();
+        let mut addr = Addr::builder();
+        for (timestamp, ours) in ours {
+            addr = addr.addr(timestamp, ours);
+        }
+
+        assert_same(
+            "addr",
+            BitcoinMsg {
+                payload: BitcoinPayload::Addr(addr.build().unwrap()),
+            },
+            NetworkMessage::Addr(theirs),
+        );
+    }
+}
+
+#[test]
+fn inv() {
+    for len in LIST_LENGTHS {
+        let (ours, theirs): (Vec<_>, Vec<_>) = (0..len)
+            .map(|i| {
+                let bytes = hash(i);
+                let (kind, theirs) = match i % 7 {
+                    0 => (InventoryKind::Error, Inventory::Error),
+                    1 => (
+                        InventoryKind::Tx,
+                        Inventory::Transaction(Txid::from_byte_array(bytes)),
+                    ),
+                    2 => (
+                        InventoryKind::Block,
+                        Inventory::Block(BlockHash::from_byte_array(bytes)),
+                    ),
+                    3 => (
+                        InventoryKind::CmpctBlock,
+                        Inventory::CompactBlock(BlockHash::from_byte_array(bytes)),
+                    ),
+                    4 => (
+                        InventoryKind::WTx,
+                        Inventory::WTx(Wtxid::from_byte_array(bytes)),
+                    ),
+                    5 => (
+                        InventoryKind::WitnessTx,
+                        Inventory::WitnessTransaction(Txid::from_byte_array(bytes)),
+                    ),
+                    _ => (
+                        InventoryKind::WitnessBlock,
+                        Inventory::WitnessBlock(BlockHash::from_byte_array(bytes)),
+                    ),
+                };
+                // rust-bitcoin encodes Error with a zero hash.
+                let bytes = match kind {
+                    InventoryKind::Error => [0; 32],
+                    _ => bytes,
+                };
+                let ours = InventoryElement {
+                    kind,
+                    hash: Hash(bytes),
+                };
+                (ours, theirs)
+            })
+            .unzip();
+
+        assert_same(
+            "inv",
+            BitcoinMsg {
+                payload: BitcoinPayload::Inv(Inv::builder().elements(ours).build().unwrap()),
+            },
+            NetworkMessage::Inv(theirs),
+        );
+    }
+}
+
+#[test]
+fn headers() {
+    for len in LIST_LENGTHS.into_iter().filter(|len| *len <= 2000) {
+        let (ours, theirs): (Vec<_>, Vec<_>) = (0..len)
+            .map(|i| {
+                let ours = BlockHeader {
+                    version: 0x2000_0000 | i as i32,
+                    prev_block: Hash(hash(i)),
+                    merkle_root: Hash(hash(i + 1)),
+                    time: 1_231_006_505 + i as u32,
+                    bits: 0x1d00_ffff,
+                    nonce: 2_083_236_893u32.wrapping_mul(i as u32),
+                };
+                let theirs = Header {
+                    version: BlockVersion::from_consensus(ours.version),
+                    prev_blockhash: BlockHash::from_byte_array(ours.prev_block.0),
+                    merkle_root: TxMerkleNode::from_byte_array(ours.merkle_root.0),
+                    time: ours.time,
+                    bits: CompactTarget::from_consensus(ours.bits),
+                    nonce: ours.nonce,
+                };
+                (ours, theirs)
+            })
+            .unzip();
+
+        assert_same(
+            "headers",
+            BitcoinMsg::headers(ours),
+            NetworkMessage::Headers(theirs),
+        );
+    }
+}
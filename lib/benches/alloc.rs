@@ -0,0 +1,102 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use btc_lib::net::MockTransport;
+use btc_lib::prelude::*;
+
+// Counts the allocations made while reading messages off a connection,
+// to see what the read path costs the allocator per message. Run with
+//
+//     cargo bench -p btc-lib --bench alloc
+
+struct Counting;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const MESSAGES: usize = 2000;
+
+fn inv(entries: usize) -> BitcoinMsg {
+    let inv = Inv::builder()
+        .elements((0..entries).map(|i| InventoryElement {
+            kind: InventoryKind::Tx,
+            hash: Hash([i as u8; 32]),
+        }))
+        .build()
+        .unwrap();
+    BitcoinMsg {
+        payload: BitcoinPayload::Inv(inv),
+    }
+}
+
+// Reads MESSAGES copies of `msg` with `read` and prints the allocations
+// and time per message.
+fn bench(name: &str, msg: &BitcoinMsg, read: fn(&mut Connection<MockTransport>)) {
+    let transport = MockTransport::new();
+    let mut conn = Connection::new(transport.clone());
+    let frame = msg.encode(Magic::MAINNET);
+    for _ in 0..MESSAGES {
+        transport.push_bytes(&frame);
+    }
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..MESSAGES {
+        read(&mut conn);
+    }
+    let elapsed = start.elapsed();
+    let allocs = ALLOCS.load(Ordering::Relaxed) - allocs;
+    let bytes = BYTES.load(Ordering::Relaxed) - bytes;
+
+    println!(
+        "{name:<36} {:>8.1} allocs {:>10} bytes {:>8.2} us per message",
+        allocs as f64 / MESSAGES as f64,
+        bytes / MESSAGES,
+        elapsed.as_secs_f64() * 1e6 / MESSAGES as f64
+    );
+}
+
+fn main() {
+    let cases = [
+        ("ping", BitcoinMsg::ping(7)),
+        ("inv of 10", inv(10)),
+        ("inv of 1000", inv(1000)),
+        ("inv of 50000", inv(50000)),
+    ];
+
+    for (name, msg) in &cases {
+        bench(&format!("{name}, read_msg"), msg, |conn| {
+            conn.read_msg().unwrap();
+        });
+        bench(&format!("{name}, read_lazy"), msg, |conn| {
+            conn.read_lazy().unwrap();
+        });
+        bench(&format!("{name}, read_lazy and recycle"), msg, |conn| {
+            let payload = conn.read_lazy().unwrap();
+            conn.recycle(payload);
+        });
+    }
+}
@@ -1,44 +1,489 @@
 extern crate proc_macro;
 
-use proc_macro::{Delimiter, Group, Ident, Punct, Spacing, Span, TokenStream, TokenTree};
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
 
-#[proc_macro_derive(BitcoinType)]
+// Fields are encoded in order. A struct that is the payload of a
+// message names its command with #[bitcoin(command = "...")], which
+// implements Payload for it as well.
+//
+// A field whose wire format isn't that of its type can name a module
+// with #[bitcoin(with = "module")], whose `encode(&T) -> Vec<u8>` and
+// `decode(&mut ByteReader) -> Result<T, DecodeError>` are used for it
+// instead. ByteReader and DecodeError have to be in scope.
+//
+// An enum is encoded as a tag of the type in #[bitcoin(tag = "u32")]
+// followed by the fields of the variant, each variant giving its tag
+// with #[bitcoin(tag = 0x1)]. A variant marked #[bitcoin(other)], with
+// a single field of the tag type, takes the tags no other variant has.
+// Without one those fail to decode.
+#[proc_macro_derive(BitcoinType, attributes(bitcoin))]
 pub fn bitcoin_type_macro_derive(input: TokenStream) -> TokenStream {
-    let mut input = input.into_iter();
-    input.next();
-    input.next();
+    match derive(input) {
+        Ok(tokens) => tokens,
+        Err(e) => e.to_compile_error(),
+    }
+}
+
+// Something the derive can't handle, reported at the tokens it is about.
+struct Error {
+    span: Span,
+    msg: String,
+}
 
-    let type_name = input.next().unwrap();
+impl Error {
+    fn new(span: Span, msg: impl Into<String>) -> Error {
+        Error {
+            span,
+            msg: msg.into(),
+        }
+    }
 
-    let atributes = if let TokenTree::Group(g) = input.next().unwrap() {
-        let mut iter = g.stream().into_iter().peekable();
-        let mut ret = vec![];
+    // compile_error!("...");
+    fn to_compile_error(&self) -> TokenStream {
+        let mut msg = Literal::string(&self.msg);
+        msg.set_span(self.span);
+        let mut bang = Punct::new('!', Spacing::Alone);
+        bang.set_span(self.span);
+        let mut args = Group::new(
+            Delimiter::Parenthesis,
+            TokenStream::from(TokenTree::from(msg)),
+        );
+        args.set_span(self.span);
+        let mut semicolon = Punct::new(';', Spacing::Alone);
+        semicolon.set_span(self.span);
 
-        while let Some(t) = iter.next() {
-            if let Some(TokenTree::Punct(p)) = iter.peek() {
-                if p.as_char() == ':' {
-                    ret.push(t);
+        TokenStream::from_iter::<[TokenTree; 4]>([
+            Ident::new("compile_error", self.span).into(),
+            bang.into(),
+            args.into(),
+            semicolon.into(),
+        ])
+    }
+}
+
+fn derive(input: TokenStream) -> Result<TokenStream, Error> {
+    let mut input = input.into_iter();
+
+    // Attributes and the visibility come before the struct keyword.
+    let mut command = None;
+    let mut tag = None;
+    let keyword = loop {
+        match input.next() {
+            Some(TokenTree::Ident(ident)) => match ident.to_string().as_str() {
+                "struct" | "enum" => break ident,
+                "union" => {
+                    return Err(Error::new(
+                        ident.span(),
+                        "BitcoinType cannot be derived for unions",
+                    ))
+                }
+                _ => {}
+            },
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => {
+                match type_attribute(&g)? {
+                    Some(TypeAttribute::Command(literal)) => command = Some(literal),
+                    Some(TypeAttribute::Tag(ty)) => tag = Some((g.span(), ty)),
+                    None => {}
                 }
             }
+            Some(_) => {}
+            None => return Err(Error::new(Span::call_site(), "expected a struct")),
         }
-        ret
+    };
+
+    let type_name = match input.next() {
+        Some(name @ TokenTree::Ident(_)) => name,
+        _ => return Err(Error::new(keyword.span(), "expected a name")),
+    };
+
+    let body = match input.next() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g,
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+            return Err(Error::new(
+                g.span(),
+                "BitcoinType cannot be derived for tuple structs",
+            ));
+        }
+        Some(TokenTree::Punct(p)) if p.as_char() == '<' => {
+            return Err(Error::new(
+                p.span(),
+                format!("BitcoinType cannot be derived for generic {keyword}s"),
+            ));
+        }
+        Some(t) => {
+            return Err(Error::new(
+                t.span(),
+                "BitcoinType needs a struct with named fields",
+            ))
+        }
+        None => {
+            return Err(Error::new(
+                type_name.span(),
+                "BitcoinType needs a struct with named fields",
+            ))
+        }
+    };
+
+    let functions = if keyword.to_string() == "struct" {
+        if let Some((span, _)) = tag {
+            return Err(Error::new(span, "only enums have a tag"));
+        }
+        let fields = parse_fields(body.stream())?;
+        [
+            gen_to_blob(&fields),
+            gen_from_blob(&fields),
+            gen_size_hint(&fields),
+        ]
+        .concat()
     } else {
-        panic!()
+        let Some((_, tag)) = tag else {
+            return Err(Error::new(
+                keyword.span(),
+                "enums need #[bitcoin(tag = \"...\")] naming the type of their tag",
+            ));
+        };
+        let variants = parse_variants(body.stream())?;
+        [
+            gen_enum_to_blob(&tag, &variants),
+            gen_enum_from_blob(&type_name, &tag, &variants),
+            gen_enum_size_hint(&tag, &variants),
+        ]
+        .concat()
     };
 
-    let tks: Vec<TokenTree> = vec![
+    let mut tks: Vec<TokenTree> = vec![
         Ident::new("impl", Span::call_site()).into(),
         Ident::new("BitcoinType", Span::call_site()).into(),
         Ident::new("for", Span::call_site()).into(),
         type_name.clone(),
-        Group::new(
-            Delimiter::Brace,
-            TokenStream::from_iter([gen_to_blob(&atributes), gen_from_blob(&atributes)].concat()),
-        )
-        .into(),
+        Group::new(Delimiter::Brace, TokenStream::from_iter(functions)).into(),
     ];
 
-    TokenStream::from_iter(tks)
+    if let Some(command) = command {
+        tks.extend(gen_payload_impl(&type_name, command));
+    }
+
+    Ok(TokenStream::from_iter(tks))
+}
+
+// A field of a struct or of an enum variant and how it goes on the wire.
+struct Field {
+    // For the fields of a tuple variant, the name they are bound to.
+    name: TokenTree,
+    // #[bitcoin(rest)]: a Vec<u8> taking up the rest of the payload,
+    // without the usual length prefix. Only the last field can be one.
+    // The span is that of the attribute.
+    rest: Option<Span>,
+    // #[bitcoin(with = "module")]: the path of the module encoding it.
+    with: Option<TokenStream>,
+}
+
+impl Field {
+    fn new(name: TokenTree, rest: Option<Span>, with: Option<TokenStream>) -> Result<Field, Error> {
+        if let (Some(span), Some(_)) = (rest, &with) {
+            return Err(Error::new(
+                span,
+                "a field can't be both #[bitcoin(rest)] and #[bitcoin(with)]",
+            ));
+        }
+        Ok(Field { name, rest, with })
+    }
+}
+
+// The fields are the identifiers followed by a single colon, as the ones
+// in paths like `std::net::SocketAddr` come in pairs.
+fn parse_fields(body: TokenStream) -> Result<Vec<Field>, Error> {
+    let mut fields = vec![];
+    let mut rest = None;
+    let mut with = None;
+    let mut iter = body.into_iter().peekable();
+
+    while let Some(t) = iter.next() {
+        match (&t, iter.peek()) {
+            (TokenTree::Punct(p), Some(TokenTree::Group(g))) if p.as_char() == '#' => {
+                field_attribute(g, &mut rest, &mut with)?;
+            }
+            (TokenTree::Ident(_), Some(TokenTree::Punct(p)))
+                if p.as_char() == ':' && p.spacing() == Spacing::Alone =>
+            {
+                fields.push(Field::new(t, rest.take(), with.take())?);
+            }
+            _ => {}
+        }
+    }
+
+    check_rest(&fields)?;
+    Ok(fields)
+}
+
+// Fields without names are told apart by the commas between them, but
+// for those in the angle brackets of a type like `HashMap<K, V>`. They
+// are bound to f0, f1 and so on.
+fn parse_tuple_fields(body: TokenStream) -> Result<Vec<Field>, Error> {
+    let mut fields = vec![];
+    let mut rest = None;
+    let mut with = None;
+    let mut has_type = false;
+    let mut depth = 0;
+    let mut iter = body.into_iter().peekable();
+
+    let binding = |i: usize| Ident::new(&format!("f{i}"), Span::call_site()).into();
+    while let Some(t) = iter.next() {
+        match (&t, iter.peek()) {
+            (TokenTree::Punct(p), Some(TokenTree::Group(g))) if p.as_char() == '#' => {
+                field_attribute(g, &mut rest, &mut with)?;
+                iter.next();
+            }
+            (TokenTree::Punct(p), _) if p.as_char() == ',' && depth == 0 => {
+                fields.push(Field::new(binding(fields.len()), rest.take(), with.take())?);
+                has_type = false;
+            }
+            (TokenTree::Punct(p), _) => {
+                match p.as_char() {
+                    '<' => depth += 1,
+                    '>' => depth -= 1,
+                    _ => {}
+                }
+                has_type = true;
+            }
+            _ => has_type = true,
+        }
+    }
+    // Without a trailing comma.
+    if has_type {
+        fields.push(Field::new(binding(fields.len()), rest, with)?);
+    }
+
+    check_rest(&fields)?;
+    Ok(fields)
+}
+
+// Applies a #[bitcoin(...)] attribute of a field to what is known of it.
+fn field_attribute(
+    attribute: &Group,
+    rest: &mut Option<Span>,
+    with: &mut Option<TokenStream>,
+) -> Result<(), Error> {
+    match bitcoin_args(attribute)?.as_deref() {
+        None => {}
+        Some([TokenTree::Ident(arg)]) if arg.to_string() == "rest" => {
+            *rest = Some(attribute.span());
+        }
+        Some([TokenTree::Ident(key), TokenTree::Punct(eq), TokenTree::Literal(module)])
+            if key.to_string() == "with" && eq.as_char() == '=' =>
+        {
+            *with = Some(path_literal(module, "module")?);
+        }
+        Some(_) => {
+            return Err(Error::new(
+                attribute.span(),
+                "expected #[bitcoin(rest)] or #[bitcoin(with = \"...\")]",
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_rest(fields: &[Field]) -> Result<(), Error> {
+    let last = fields.len().saturating_sub(1);
+    if let Some(span) = fields[..last].iter().find_map(|field| field.rest) {
+        return Err(Error::new(
+            span,
+            "only the last field can be #[bitcoin(rest)]",
+        ));
+    }
+    Ok(())
+}
+
+enum VariantFields {
+    Unit,
+    Tuple(Vec<Field>),
+    Named(Vec<Field>),
+}
+
+struct Variant {
+    name: Ident,
+    // The literal in #[bitcoin(tag = ...)], None for the other variant.
+    tag: Option<TokenTree>,
+    fields: VariantFields,
+}
+
+fn parse_variants(body: TokenStream) -> Result<Vec<Variant>, Error> {
+    let mut variants: Vec<Variant> = vec![];
+    let mut tag = None;
+    let mut other = false;
+    let mut iter = body.into_iter().peekable();
+
+    while let Some(t) = iter.next() {
+        match t {
+            TokenTree::Punct(p) if p.as_char() == '#' => {
+                let Some(TokenTree::Group(g)) = iter.next() else {
+                    continue;
+                };
+                match bitcoin_args(&g)?.as_deref() {
+                    None => {}
+                    Some(
+                        [TokenTree::Ident(key), TokenTree::Punct(eq), value @ TokenTree::Literal(_)],
+                    ) if key.to_string() == "tag" && eq.as_char() == '=' => {
+                        tag = Some(value.clone());
+                    }
+                    Some([TokenTree::Ident(arg)]) if arg.to_string() == "other" => other = true,
+                    Some(_) => {
+                        return Err(Error::new(
+                            g.span(),
+                            "expected #[bitcoin(tag = ...)] or #[bitcoin(other)]",
+                        ));
+                    }
+                }
+            }
+            TokenTree::Ident(name) => {
+                let fields = match iter.peek() {
+                    Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+                        VariantFields::Tuple(parse_tuple_fields(g.stream())?)
+                    }
+                    Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => {
+                        VariantFields::Named(parse_fields(g.stream())?)
+                    }
+                    _ => VariantFields::Unit,
+                };
+                // Whatever else there is, like an explicit discriminant,
+                // goes up to the comma.
+                for t in iter.by_ref() {
+                    if matches!(&t, TokenTree::Punct(p) if p.as_char() == ',') {
+                        break;
+                    }
+                }
+
+                let tag = tag.take();
+                if tag.is_some() == other {
+                    return Err(Error::new(
+                        name.span(),
+                        "each variant needs either #[bitcoin(tag = ...)] or #[bitcoin(other)]",
+                    ));
+                }
+                if other {
+                    if !matches!(&fields, VariantFields::Tuple(fields)
+                        if fields.len() == 1 && fields[0].rest.is_none() && fields[0].with.is_none())
+                    {
+                        return Err(Error::new(
+                            name.span(),
+                            "the #[bitcoin(other)] variant holds just the tag, as in Unknown(u32)",
+                        ));
+                    }
+                    if variants.iter().any(|variant| variant.tag.is_none()) {
+                        return Err(Error::new(
+                            name.span(),
+                            "only one variant can be #[bitcoin(other)]",
+                        ));
+                    }
+                }
+
+                variants.push(Variant { name, tag, fields });
+                other = false;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(variants)
+}
+
+// The path in #[bitcoin(with = "...")] or #[bitcoin(tag = "...")],
+// spanned at the string so that one that doesn't resolve is reported
+// there.
+fn path_literal(literal: &Literal, what: &str) -> Result<TokenStream, Error> {
+    let path = literal.to_string();
+    let path = path
+        .strip_prefix('"')
+        .and_then(|path| path.strip_suffix('"'))
+        .filter(|path| !path.is_empty())
+        .filter(|path| {
+            path.chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == ':')
+        });
+    let Some(Ok(path)) = path.map(str::parse::<TokenStream>) else {
+        return Err(Error::new(
+            literal.span(),
+            format!("expected the path of a {what}"),
+        ));
+    };
+
+    Ok(path
+        .into_iter()
+        .map(|mut t| {
+            t.set_span(literal.span());
+            t
+        })
+        .collect())
+}
+
+// What is inside the parentheses of a #[bitcoin(...)] attribute, None
+// for other attributes.
+fn bitcoin_args(attribute: &Group) -> Result<Option<Vec<TokenTree>>, Error> {
+    let mut tokens = attribute.stream().into_iter();
+    match tokens.next() {
+        Some(TokenTree::Ident(ident)) if ident.to_string() == "bitcoin" => {}
+        _ => return Ok(None),
+    }
+
+    match tokens.next() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+            Ok(Some(g.stream().into_iter().collect()))
+        }
+        _ => Err(Error::new(attribute.span(), "expected #[bitcoin(...)]")),
+    }
+}
+
+enum TypeAttribute {
+    Command(TokenTree),
+    Tag(TokenStream),
+}
+
+// #[bitcoin(command = "...")] or #[bitcoin(tag = "...")] on the type,
+// None for other attributes.
+fn type_attribute(attribute: &Group) -> Result<Option<TypeAttribute>, Error> {
+    let Some(args) = bitcoin_args(attribute)? else {
+        return Ok(None);
+    };
+    match args.as_slice() {
+        [TokenTree::Ident(key), TokenTree::Punct(eq), command @ TokenTree::Literal(_)]
+            if key.to_string() == "command" && eq.as_char() == '=' =>
+        {
+            Ok(Some(TypeAttribute::Command(command.clone())))
+        }
+        [TokenTree::Ident(key), TokenTree::Punct(eq), TokenTree::Literal(ty)]
+            if key.to_string() == "tag" && eq.as_char() == '=' =>
+        {
+            Ok(Some(TypeAttribute::Tag(path_literal(ty, "type")?)))
+        }
+        _ => Err(Error::new(
+            attribute.span(),
+            "expected #[bitcoin(command = \"...\")] or #[bitcoin(tag = \"...\")]",
+        )),
+    }
+}
+
+fn gen_payload_impl(type_name: &TokenTree, command: TokenTree) -> Vec<TokenTree> {
+    let body: Vec<TokenTree> = vec![
+        Ident::new("const", Span::call_site()).into(),
+        Ident::new("COMMAND", Span::call_site()).into(),
+        Punct::new(':', Spacing::Alone).into(),
+        Punct::new('&', Spacing::Alone).into(),
+        Punct::new('\'', Spacing::Joint).into(),
+        Ident::new("static", Span::call_site()).into(),
+        Ident::new("str", Span::call_site()).into(),
+        Punct::new('=', Spacing::Alone).into(),
+        command,
+        Punct::new(';', Spacing::Alone).into(),
+    ];
+
+    vec![
+        Ident::new("impl", Span::call_site()).into(),
+        Ident::new("Payload", Span::call_site()).into(),
+        Ident::new("for", Span::call_site()).into(),
+        type_name.clone(),
+        Group::new(Delimiter::Brace, TokenStream::from_iter(body)).into(),
+    ]
 }
 
 fn gen_func(
@@ -59,6 +504,42 @@ fn gen_func(
     ret
 }
 
+// `module::function(args)`
+fn module_call(module: &TokenStream, function: &str, args: Vec<TokenTree>) -> Vec<TokenTree> {
+    let mut ret: Vec<TokenTree> = module.clone().into_iter().collect();
+    ret.extend(vec![
+        Punct::new(':', Spacing::Joint).into(),
+        Punct::new(':', Spacing::Alone).into(),
+        Ident::new(function, Span::call_site()).into(),
+        Group::new(Delimiter::Parenthesis, TokenStream::from_iter(args)).into(),
+    ]);
+    ret
+}
+
+// `BitcoinType::function(args)`, or `<T as BitcoinType>::function(args)`
+// given the type.
+fn bitcoin_type_call(
+    ty: Option<&TokenStream>,
+    function: &str,
+    args: Vec<TokenTree>,
+) -> Vec<TokenTree> {
+    let trait_name = Ident::new("BitcoinType", Span::call_site()).into();
+    let path = match ty {
+        None => vec![trait_name],
+        Some(ty) => {
+            let mut qualified = vec![Punct::new('<', Spacing::Alone).into()];
+            qualified.extend(ty.clone());
+            qualified.extend([
+                Ident::new("as", Span::call_site()).into(),
+                trait_name,
+                Punct::new('>', Spacing::Alone).into(),
+            ]);
+            qualified
+        }
+    };
+    module_call(&TokenStream::from_iter(path), function, args)
+}
+
 fn method_call(mut name: Vec<TokenTree>, method: &str, args: Vec<TokenTree>) -> Vec<TokenTree> {
     name.extend(vec![
         Punct::new('.', Spacing::Alone).into(),
@@ -68,13 +549,107 @@ fn method_call(mut name: Vec<TokenTree>, method: &str, args: Vec<TokenTree>) ->
     name
 }
 
-fn gen_to_blob(atributes: &Vec<TokenTree>) -> Vec<TokenTree> {
-    let args = vec![
+// `ret.extend(blob);`
+fn extend_ret(blob: Vec<TokenTree>) -> Vec<TokenTree> {
+    let mut ret = method_call(
+        vec![Ident::new("ret", Span::call_site()).into()],
+        "extend",
+        blob,
+    );
+    ret.push(Punct::new(';', Spacing::Alone).into());
+    ret
+}
+
+// Extends `ret` with a field, given a reference to its value.
+fn encode_field(field: &Field, value: Vec<TokenTree>) -> Vec<TokenTree> {
+    extend_ret(if field.rest.is_some() {
+        value
+    } else if let Some(module) = &field.with {
+        module_call(module, "encode", value)
+    } else {
+        bitcoin_type_call(None, "to_blob", value)
+    })
+}
+
+// The size hint of a field, given a reference to its value. Those
+// encoded by a module don't count, as it would take encoding them.
+fn field_size_hint(field: &Field, value: Vec<TokenTree>) -> Vec<TokenTree> {
+    if field.rest.is_some() {
+        let value = Group::new(Delimiter::Parenthesis, TokenStream::from_iter(value));
+        method_call(vec![value.into()], "len", vec![])
+    } else if field.with.is_some() {
+        vec![Literal::usize_unsuffixed(0).into()]
+    } else {
+        bitcoin_type_call(None, "encoded_size_hint", value)
+    }
+}
+
+// `0 + a + b ...`
+fn sum(terms: impl IntoIterator<Item = Vec<TokenTree>>) -> Vec<TokenTree> {
+    let mut ret = vec![Literal::usize_unsuffixed(0).into()];
+    for term in terms {
+        ret.push(Punct::new('+', Spacing::Alone).into());
+        ret.extend(term);
+    }
+    ret
+}
+
+// `?`
+fn question_mark() -> TokenTree {
+    Punct::new('?', Spacing::Alone).into()
+}
+
+// The expression decoding a field from `blob`, returning early with the
+// error if it doesn't.
+fn decode_field(field: &Field) -> Vec<TokenTree> {
+    let blob = || vec![Ident::new("blob", Span::call_site()).into()];
+    if field.rest.is_some() {
+        let remaining = method_call(blob(), "remaining", vec![]);
+        let mut take = method_call(blob(), "take", remaining);
+        take.push(question_mark());
+        method_call(take, "to_vec", vec![])
+    } else {
+        let mut ret = match &field.with {
+            Some(module) => module_call(module, "decode", blob()),
+            None => bitcoin_type_call(None, "from_blob", blob()),
+        };
+        ret.push(question_mark());
+        ret
+    }
+}
+
+// `name: value, ...`, or `value, ...` for fields without names.
+fn decode_fields(fields: &[Field], named: bool) -> TokenStream {
+    TokenStream::from_iter(fields.iter().flat_map(|field| {
+        let mut ret = vec![];
+        if named {
+            ret.extend([field.name.clone(), Punct::new(':', Spacing::Alone).into()]);
+        }
+        ret.extend(decode_field(field));
+        ret.push(Punct::new(',', Spacing::Alone).into());
+        ret
+    }))
+}
+
+fn to_blob_args() -> Vec<TokenTree> {
+    vec![
         Punct::new('&', Spacing::Alone).into(),
         Ident::new("self", Span::call_site()).into(),
-    ];
+    ]
+}
 
-    let mut body: Vec<TokenTree> = vec![
+fn to_blob_return() -> Vec<TokenTree> {
+    vec![
+        Ident::new("Vec", Span::call_site()).into(),
+        Punct::new('<', Spacing::Alone).into(),
+        Ident::new("u8", Span::call_site()).into(),
+        Punct::new('>', Spacing::Alone).into(),
+    ]
+}
+
+// `let mut ret = vec![];`
+fn new_ret() -> Vec<TokenTree> {
+    vec![
         Ident::new("let", Span::call_site()).into(),
         Ident::new("mut", Span::call_site()).into(),
         Ident::new("ret", Span::call_site()).into(),
@@ -83,76 +658,252 @@ fn gen_to_blob(atributes: &Vec<TokenTree>) -> Vec<TokenTree> {
         Punct::new('!', Spacing::Alone).into(),
         Group::new(Delimiter::Bracket, TokenStream::new()).into(),
         Punct::new(';', Spacing::Alone).into(),
-    ];
+    ]
+}
 
-    for atrib in atributes {
-        body.extend(method_call(
-            vec![Ident::new("ret", Span::call_site()).into()],
-            "extend",
-            method_call(
-                vec![
-                    Ident::new("self", Span::call_site()).into(),
-                    Punct::new('.', Spacing::Alone).into(),
-                    atrib.clone(),
-                ],
-                "to_blob",
-                vec![],
-            ),
-        ));
-        body.push(Punct::new(';', Spacing::Alone).into());
-    }
-    body.push(Ident::new("ret", Span::call_site()).into());
+// `Ok(value)`
+fn ok(value: Vec<TokenTree>) -> Vec<TokenTree> {
+    vec![
+        Ident::new("Ok", Span::call_site()).into(),
+        Group::new(Delimiter::Parenthesis, TokenStream::from_iter(value)).into(),
+    ]
+}
 
-    let ret = vec![
-        Ident::new("Vec", Span::call_site()).into(),
+// `Result<Self, DecodeError>`
+fn from_blob_return() -> Vec<TokenTree> {
+    vec![
+        Ident::new("Result", Span::call_site()).into(),
         Punct::new('<', Spacing::Alone).into(),
-        Ident::new("u8", Span::call_site()).into(),
+        Ident::new("Self", Span::call_site()).into(),
+        Punct::new(',', Spacing::Alone).into(),
+        Ident::new("DecodeError", Span::call_site()).into(),
         Punct::new('>', Spacing::Alone).into(),
-    ];
-
-    gen_func("to_blob", args, body, ret)
+    ]
 }
 
-fn gen_from_blob(atributes: &[TokenTree]) -> Vec<TokenTree> {
-    let args = vec![
+fn from_blob_args() -> Vec<TokenTree> {
+    vec![
         Ident::new("blob", Span::call_site()).into(),
         Punct::new(':', Spacing::Alone).into(),
         Punct::new('&', Spacing::Alone).into(),
         Ident::new("mut", Span::call_site()).into(),
-        Ident::new("Scanner", Span::call_site()).into(),
-    ];
+        Ident::new("ByteReader", Span::call_site()).into(),
+    ]
+}
 
-    let atribs = atributes.iter();
-    let atribs = atribs.flat_map(|at| {
-        [
-            at.clone(),
-            Punct::new(':', Spacing::Alone).into(),
-            Ident::new("BitcoinType", Span::call_site()).into(),
-            Punct::new(':', Spacing::Joint).into(),
-            Punct::new(':', Spacing::Alone).into(),
-            Ident::new("from_blob", Span::call_site()).into(),
-            Group::new(
-                Delimiter::Parenthesis,
-                TokenStream::from_iter(Vec::<TokenTree>::from([Ident::new(
-                    "blob",
-                    Span::call_site(),
-                )
-                .into()])),
-            )
-            .into(),
-            Punct::new(',', Spacing::Alone).into(),
-        ]
+fn gen_to_blob(fields: &[Field]) -> Vec<TokenTree> {
+    let mut body = new_ret();
+    for field in fields {
+        body.extend(encode_field(
+            field,
+            vec![
+                Punct::new('&', Spacing::Alone).into(),
+                Ident::new("self", Span::call_site()).into(),
+                Punct::new('.', Spacing::Alone).into(),
+                field.name.clone(),
+            ],
+        ));
+    }
+    body.push(Ident::new("ret", Span::call_site()).into());
+
+    gen_func("to_blob", to_blob_args(), body, to_blob_return())
+}
+
+fn gen_size_hint(fields: &[Field]) -> Vec<TokenTree> {
+    let body = sum(fields.iter().map(|field| {
+        field_size_hint(
+            field,
+            vec![
+                Punct::new('&', Spacing::Alone).into(),
+                Ident::new("self", Span::call_site()).into(),
+                Punct::new('.', Spacing::Alone).into(),
+                field.name.clone(),
+            ],
+        )
+    }));
+
+    gen_func(
+        "encoded_size_hint",
+        to_blob_args(),
+        body,
+        vec![Ident::new("usize", Span::call_site()).into()],
+    )
+}
+
+fn gen_from_blob(fields: &[Field]) -> Vec<TokenTree> {
+    let body = ok(vec![
+        Ident::new("Self", Span::call_site()).into(),
+        Group::new(Delimiter::Brace, decode_fields(fields, true)).into(),
+    ]);
+
+    gen_func("from_blob", from_blob_args(), body, from_blob_return())
+}
+
+// `Self::Variant`
+fn variant_path(variant: &Variant) -> Vec<TokenTree> {
+    vec![
+        Ident::new("Self", Span::call_site()).into(),
+        Punct::new(':', Spacing::Joint).into(),
+        Punct::new(':', Spacing::Alone).into(),
+        variant.name.clone().into(),
+    ]
+}
+
+// `=>`
+fn fat_arrow() -> [TokenTree; 2] {
+    [
+        Punct::new('=', Spacing::Joint).into(),
+        Punct::new('>', Spacing::Alone).into(),
+    ]
+}
+
+// `Self::Variant(f0, ..) =>`, binding the fields of the variant.
+fn variant_arm(variant: &Variant) -> (Vec<TokenTree>, &[Field]) {
+    let mut arm = variant_path(variant);
+    let fields = match &variant.fields {
+        VariantFields::Unit => &[][..],
+        VariantFields::Tuple(fields) | VariantFields::Named(fields) => {
+            let delimiter = match variant.fields {
+                VariantFields::Tuple(_) => Delimiter::Parenthesis,
+                _ => Delimiter::Brace,
+            };
+            let bindings = fields
+                .iter()
+                .flat_map(|field| [field.name.clone(), Punct::new(',', Spacing::Alone).into()]);
+            arm.push(Group::new(delimiter, TokenStream::from_iter(bindings)).into());
+            &fields[..]
+        }
+    };
+    arm.extend(fat_arrow());
+    (arm, fields)
+}
+
+// match self { Self::Variant(f0, ..) => { tag and fields } .. }
+fn gen_enum_to_blob(tag: &TokenStream, variants: &[Variant]) -> Vec<TokenTree> {
+    let arms = variants.iter().flat_map(|variant| {
+        let (mut arm, fields) = variant_arm(variant);
+
+        let mut body = vec![];
+        if let Some(value) = &variant.tag {
+            let value = vec![Punct::new('&', Spacing::Alone).into(), value.clone()];
+            body.extend(extend_ret(bitcoin_type_call(Some(tag), "to_blob", value)));
+        }
+        // The field of the other variant is the tag itself.
+        for field in fields {
+            body.extend(encode_field(field, vec![field.name.clone()]));
+        }
+        arm.push(Group::new(Delimiter::Brace, TokenStream::from_iter(body)).into());
+        arm
+    });
+
+    let mut body = new_ret();
+    body.extend::<[TokenTree; 4]>([
+        Ident::new("match", Span::call_site()).into(),
+        Ident::new("self", Span::call_site()).into(),
+        Group::new(Delimiter::Brace, TokenStream::from_iter(arms)).into(),
+        Ident::new("ret", Span::call_site()).into(),
+    ]);
+
+    gen_func("to_blob", to_blob_args(), body, to_blob_return())
+}
+
+// match self { Self::Variant(f0, ..) => { 0 + tag + fields } .. }
+fn gen_enum_size_hint(tag: &TokenStream, variants: &[Variant]) -> Vec<TokenTree> {
+    let arms = variants.iter().flat_map(|variant| {
+        let (mut arm, fields) = variant_arm(variant);
+        let tag_hint = variant.tag.iter().map(|value| {
+            let value = vec![Punct::new('&', Spacing::Alone).into(), value.clone()];
+            bitcoin_type_call(Some(tag), "encoded_size_hint", value)
+        });
+        let field_hints = fields
+            .iter()
+            .map(|field| field_size_hint(field, vec![field.name.clone()]));
+        let body = sum(tag_hint.chain(field_hints));
+        arm.push(Group::new(Delimiter::Brace, TokenStream::from_iter(body)).into());
+        arm
     });
 
     let body: Vec<TokenTree> = vec![
-        Ident::new("Self", Span::call_site()).into(),
-        Group::new(Delimiter::Brace, TokenStream::from_iter(atribs)).into(),
+        Ident::new("match", Span::call_site()).into(),
+        Ident::new("self", Span::call_site()).into(),
+        Group::new(Delimiter::Brace, TokenStream::from_iter(arms)).into(),
     ];
 
     gen_func(
-        "from_blob",
-        args,
+        "encoded_size_hint",
+        to_blob_args(),
         body,
-        vec![Ident::new("Self", Span::call_site()).into()],
+        vec![Ident::new("usize", Span::call_site()).into()],
     )
 }
+
+// Ok(match <T as BitcoinType>::from_blob(blob)? { 0x1 => Self::Variant(..), .. })
+fn gen_enum_from_blob(
+    type_name: &TokenTree,
+    tag: &TokenStream,
+    variants: &[Variant],
+) -> Vec<TokenTree> {
+    let mut arms = vec![];
+    for variant in variants {
+        let Some(value) = &variant.tag else {
+            continue;
+        };
+        arms.push(value.clone());
+        arms.extend(fat_arrow());
+        arms.extend(variant_path(variant));
+        match &variant.fields {
+            VariantFields::Unit => {}
+            VariantFields::Tuple(fields) => {
+                arms.push(Group::new(Delimiter::Parenthesis, decode_fields(fields, false)).into())
+            }
+            VariantFields::Named(fields) => {
+                arms.push(Group::new(Delimiter::Brace, decode_fields(fields, true)).into())
+            }
+        }
+        arms.push(Punct::new(',', Spacing::Alone).into());
+    }
+
+    let tag_binding: TokenTree = Ident::new("tag", Span::call_site()).into();
+    match variants.iter().find(|variant| variant.tag.is_none()) {
+        Some(other) => {
+            arms.push(tag_binding.clone());
+            arms.extend(fat_arrow());
+            arms.extend(variant_path(other));
+            arms.push(Group::new(Delimiter::Parenthesis, TokenStream::from(tag_binding)).into());
+        }
+        // _ => return Err(DecodeError::UnknownTag("Type")),
+        None => {
+            let name = Literal::string(&type_name.to_string());
+            let error: Vec<TokenTree> = vec![
+                Ident::new("DecodeError", Span::call_site()).into(),
+                Punct::new(':', Spacing::Joint).into(),
+                Punct::new(':', Spacing::Alone).into(),
+                Ident::new("UnknownTag", Span::call_site()).into(),
+                Group::new(
+                    Delimiter::Parenthesis,
+                    TokenStream::from(TokenTree::from(name)),
+                )
+                .into(),
+            ];
+            arms.push(Ident::new("_", Span::call_site()).into());
+            arms.extend(fat_arrow());
+            arms.extend::<[TokenTree; 2]>([
+                Ident::new("return", Span::call_site()).into(),
+                Ident::new("Err", Span::call_site()).into(),
+            ]);
+            arms.push(Group::new(Delimiter::Parenthesis, TokenStream::from_iter(error)).into());
+        }
+    }
+    arms.push(Punct::new(',', Spacing::Alone).into());
+
+    let mut body: Vec<TokenTree> = vec![Ident::new("match", Span::call_site()).into()];
+    body.extend(bitcoin_type_call(
+        Some(tag),
+        "from_blob",
+        vec![Ident::new("blob", Span::call_site()).into()],
+    ));
+    body.push(question_mark());
+    body.push(Group::new(Delimiter::Brace, TokenStream::from_iter(arms)).into());
+
+    gen_func("from_blob", from_blob_args(), ok(body), from_blob_return())
+}
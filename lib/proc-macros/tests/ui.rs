@@ -0,0 +1,80 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Each file in tests/ui is compiled on its own and has to fail with the
+// errors in the .stderr file next to it, in rustc's short format.
+#[test]
+fn derive_errors() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let proc_macros = proc_macros_lib();
+
+    let mut cases: Vec<PathBuf> = fs::read_dir(root.join("tests/ui"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rs"))
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty());
+
+    let mut failures = vec![];
+    for case in cases {
+        let case = case.strip_prefix(root).unwrap();
+        let output = Command::new(std::env::var("RUSTC").unwrap_or("rustc".to_string()))
+            .current_dir(root)
+            .args([
+                "--edition",
+                "2021",
+                "--crate-type",
+                "lib",
+                "--emit",
+                "metadata",
+            ])
+            .args(["--error-format", "short", "-A", "warnings"])
+            .arg("--out-dir")
+            .arg(env!("CARGO_TARGET_TMPDIR"))
+            .arg("--extern")
+            .arg(format!("btc_lib_proc_macros={}", proc_macros.display()))
+            .arg(case)
+            .output()
+            .unwrap();
+
+        // Only the errors in the case itself, not rustc's summary.
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let errors: Vec<&str> = stderr
+            .lines()
+            .filter(|line| line.starts_with(case.to_str().unwrap()))
+            .collect();
+        let expected = fs::read_to_string(case.with_extension("stderr")).unwrap_or_default();
+        let expected: Vec<&str> = expected.lines().collect();
+
+        if output.status.success() || errors != expected {
+            failures.push(format!(
+                "{}\nexpected:\n{}\ngot:\n{stderr}",
+                case.display(),
+                expected.join("\n")
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+// The proc macro library built for this test, next to its executable.
+fn proc_macros_lib() -> PathBuf {
+    let deps = std::env::current_exe().unwrap();
+    let deps = deps.parent().unwrap();
+    let prefix = format!("{}btc_lib_proc_macros-", std::env::consts::DLL_PREFIX);
+
+    fs::read_dir(deps)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with(&prefix) && name.ends_with(std::env::consts::DLL_SUFFIX)
+        })
+        .max_by_key(|entry| entry.metadata().unwrap().modified().unwrap())
+        .expect("the proc macro library is built before its tests")
+        .path()
+}
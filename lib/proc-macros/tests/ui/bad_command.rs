@@ -0,0 +1,7 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+#[bitcoin(command)]
+struct Ping {
+    nonce: u64,
+}
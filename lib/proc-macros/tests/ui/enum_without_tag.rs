@@ -0,0 +1,7 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+enum Kind {
+    Tx,
+    Block,
+}
@@ -0,0 +1,10 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+#[bitcoin(tag = "u8")]
+enum Either<L, R> {
+    #[bitcoin(tag = 0)]
+    Left(L),
+    #[bitcoin(tag = 1)]
+    Right(R),
+}
@@ -0,0 +1,6 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+struct List<T> {
+    items: Vec<T>,
+}
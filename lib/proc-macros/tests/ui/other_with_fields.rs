@@ -0,0 +1,10 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+#[bitcoin(tag = "u8")]
+enum Kind {
+    #[bitcoin(tag = 1)]
+    Tx,
+    #[bitcoin(other)]
+    Unknown { tag: u8 },
+}
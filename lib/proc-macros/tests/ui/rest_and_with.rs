@@ -0,0 +1,9 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+struct Reject {
+    code: u8,
+    #[bitcoin(rest)]
+    #[bitcoin(with = "hex")]
+    data: Vec<u8>,
+}
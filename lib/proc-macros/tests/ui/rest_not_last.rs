@@ -0,0 +1,8 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+struct Reject {
+    #[bitcoin(rest)]
+    data: Vec<u8>,
+    code: u8,
+}
@@ -0,0 +1,7 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+#[bitcoin(tag = "u8")]
+struct Ping {
+    nonce: u64,
+}
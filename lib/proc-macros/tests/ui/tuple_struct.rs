@@ -0,0 +1,4 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+struct Port(u16);
@@ -0,0 +1,12 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+#[bitcoin(tag = "u8")]
+enum Kind {
+    #[bitcoin(tag = 1)]
+    Tx,
+    #[bitcoin(other)]
+    Unknown(u8),
+    #[bitcoin(other)]
+    Unassigned(u8),
+}
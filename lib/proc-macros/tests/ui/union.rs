@@ -0,0 +1,7 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+union Bits {
+    int: u32,
+    float: f32,
+}
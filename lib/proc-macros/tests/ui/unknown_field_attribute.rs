@@ -0,0 +1,7 @@
+use btc_lib_proc_macros::BitcoinType;
+
+#[derive(BitcoinType)]
+struct Ping {
+    #[bitcoin(big_endian)]
+    nonce: u64,
+}
@@ -0,0 +1,477 @@
+use alloc::vec::Vec;
+#[cfg(feature = "crypto-hash")]
+use core::fmt;
+
+use crate::codec::BitcoinType;
+#[cfg(feature = "crypto-hash")]
+use crate::crypto::sha256d;
+use crate::siphash::siphash24;
+use crate::types::Hash;
+
+// Parameters of the BIP158 basic filter.
+pub const P: u8 = 19;
+pub const M: u64 = 784931;
+
+#[derive(Debug, Clone, Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    // Bits already used in the last byte, 0 meaning a new byte is needed.
+    used: u8,
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter::default()
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        if self.used == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> self.used;
+        }
+        self.used = (self.used + 1) % 8;
+    }
+
+    // Writes the low `n` bits of `value`, most significant first.
+    pub fn write_bits(&mut self, value: u64, n: u8) {
+        for i in (0..n).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader { bytes, pos: 0 }
+    }
+
+    pub fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.pos / 8)?;
+        let bit = byte & (0x80 >> (self.pos % 8)) != 0;
+        self.pos += 1;
+        Some(bit)
+    }
+
+    pub fn read_bits(&mut self, n: u8) -> Option<u64> {
+        let mut ret = 0;
+        for _ in 0..n {
+            ret = (ret << 1) | self.read_bit()? as u64;
+        }
+        Some(ret)
+    }
+}
+
+pub fn golomb_encode(writer: &mut BitWriter, value: u64, p: u8) {
+    for _ in 0..(value >> p) {
+        writer.write_bit(true);
+    }
+    writer.write_bit(false);
+    writer.write_bits(value, p);
+}
+
+pub fn golomb_decode(reader: &mut BitReader, p: u8) -> Option<u64> {
+    let mut quotient = 0;
+    while reader.read_bit()? {
+        quotient += 1;
+    }
+    Some((quotient << p) | reader.read_bits(p)?)
+}
+
+// Maps an item uniformly onto [0, range) without a modulo, as BIP158 does.
+fn hash_to_range(item: &[u8], range: u64, k0: u64, k1: u64) -> u64 {
+    ((siphash24(k0, k1, item) as u128 * range as u128) >> 64) as u64
+}
+
+// Reads the CompactSize element count at the start of a filter, returning
+// it together with its encoded length.
+fn read_count(bytes: &[u8]) -> Option<(u64, usize)> {
+    let (len, count) = match *bytes.first()? {
+        0xfd => (
+            3,
+            u16::from_le_bytes(bytes.get(1..3)?.try_into().unwrap()) as u64,
+        ),
+        0xfe => (
+            5,
+            u32::from_le_bytes(bytes.get(1..5)?.try_into().unwrap()) as u64,
+        ),
+        0xff => (9, u64::from_le_bytes(bytes.get(1..9)?.try_into().unwrap())),
+        x => (1, x as u64),
+    };
+    Some((count, len))
+}
+
+// A BIP158 basic block filter: the element count followed by the
+// Golomb-Rice coded set, keyed by the hash of the block it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockFilter {
+    pub block_hash: Hash,
+    pub content: Vec<u8>,
+}
+
+impl BlockFilter {
+    pub fn new(block_hash: Hash, content: Vec<u8>) -> BlockFilter {
+        BlockFilter {
+            block_hash,
+            content,
+        }
+    }
+
+    pub fn build<'a>(
+        block_hash: Hash,
+        elements: impl IntoIterator<Item = &'a [u8]>,
+    ) -> BlockFilter {
+        let mut elements: Vec<&[u8]> = elements.into_iter().collect();
+        elements.sort_unstable();
+        elements.dedup();
+
+        let n = elements.len() as u64;
+        let (k0, k1) = keys(&block_hash);
+
+        let mut values: Vec<u64> = elements
+            .iter()
+            .map(|element| hash_to_range(element, n * M, k0, k1))
+            .collect();
+        values.sort_unstable();
+
+        let mut writer = BitWriter::new();
+        let mut last = 0;
+        for value in values {
+            golomb_encode(&mut writer, value - last, P);
+            last = value;
+        }
+
+        let mut content = (n as usize).to_blob();
+        content.extend(writer.into_bytes());
+
+        BlockFilter {
+            block_hash,
+            content,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        read_count(&self.content).map_or(0, |(n, _)| n)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Whether any of `scripts` is (probably) in the filter. Filters come
+    // from peers, so a truncated or malformed one just matches less rather
+    // than causing a panic.
+    pub fn match_any<'a>(&self, scripts: impl IntoIterator<Item = &'a [u8]>) -> bool {
+        let Some((n, len)) = read_count(&self.content) else {
+            return false;
+        };
+        let (k0, k1) = keys(&self.block_hash);
+
+        let mut queries: Vec<u64> = scripts
+            .into_iter()
+            .map(|script| hash_to_range(script, n.saturating_mul(M), k0, k1))
+            .collect();
+        queries.sort_unstable();
+        queries.dedup();
+
+        let mut reader = BitReader::new(&self.content[len..]);
+        let mut queries = queries.into_iter().peekable();
+        let mut value: u64 = 0;
+
+        for _ in 0..n {
+            let Some(delta) = golomb_decode(&mut reader, P) else {
+                return false;
+            };
+            value = value.saturating_add(delta);
+
+            while let Some(&query) = queries.peek() {
+                if query == value {
+                    return true;
+                } else if query < value {
+                    queries.next();
+                } else {
+                    break;
+                }
+            }
+            if queries.peek().is_none() {
+                return false;
+            }
+        }
+
+        false
+    }
+}
+
+fn keys(block_hash: &Hash) -> (u64, u64) {
+    (
+        u64::from_le_bytes(block_hash.0[0..8].try_into().unwrap()),
+        u64::from_le_bytes(block_hash.0[8..16].try_into().unwrap()),
+    )
+}
+
+#[cfg(feature = "crypto-hash")]
+impl BlockFilter {
+    pub fn filter_hash(&self) -> Hash {
+        sha256d(&self.content)
+    }
+
+    // Filter headers chain each filter to the previous one, so agreeing on
+    // a tip header means agreeing on every filter below it.
+    pub fn header(&self, prev_header: &Hash) -> Hash {
+        let mut data = self.filter_hash().0.to_vec();
+        data.extend(prev_header.0);
+        sha256d(&data)
+    }
+}
+
+#[cfg(feature = "crypto-hash")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterMismatch {
+    UnknownBlock(Hash),
+    Filter {
+        block_hash: Hash,
+        expected: Hash,
+        got: Hash,
+    },
+    Header {
+        height: usize,
+        expected: Hash,
+        got: Hash,
+    },
+}
+
+#[cfg(feature = "crypto-hash")]
+impl fmt::Display for FilterMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterMismatch::UnknownBlock(hash) => write!(f, "no filter indexed for block {hash}"),
+            FilterMismatch::Filter {
+                block_hash,
+                expected,
+                got,
+            } => write!(
+                f,
+                "filter for block {block_hash} hashes to {got}, expected {expected}"
+            ),
+            FilterMismatch::Header {
+                height,
+                expected,
+                got,
+            } => write!(
+                f,
+                "filter header at height {height} is {got}, expected {expected}"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "crypto-hash")]
+#[derive(Debug, Clone)]
+pub struct FilterIndexEntry {
+    pub block_hash: Hash,
+    pub filter_hash: Hash,
+    pub header: Hash,
+}
+
+// Filters computed locally, in chain order starting at genesis, against
+// which filters and filter headers served by peers can be checked.
+#[cfg(feature = "crypto-hash")]
+#[derive(Debug, Clone, Default)]
+pub struct FilterIndex {
+    entries: Vec<FilterIndexEntry>,
+}
+
+#[cfg(feature = "crypto-hash")]
+impl FilterIndex {
+    pub fn new() -> FilterIndex {
+        FilterIndex::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The header the next filter builds on; all zeros before genesis.
+    pub fn tip_header(&self) -> Hash {
+        self.entries
+            .last()
+            .map_or(Hash::default(), |entry| entry.header)
+    }
+
+    // Appends the filter of the next block and returns its filter header.
+    pub fn push(&mut self, filter: &BlockFilter) -> Hash {
+        let header = filter.header(&self.tip_header());
+        self.entries.push(FilterIndexEntry {
+            block_hash: filter.block_hash,
+            filter_hash: filter.filter_hash(),
+            header,
+        });
+        header
+    }
+
+    pub fn get(&self, height: usize) -> Option<&FilterIndexEntry> {
+        self.entries.get(height)
+    }
+
+    pub fn find(&self, block_hash: &Hash) -> Option<(usize, &FilterIndexEntry)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, entry)| entry.block_hash == *block_hash)
+    }
+
+    pub fn check_filter(&self, filter: &BlockFilter) -> Result<(), FilterMismatch> {
+        let (_, entry) = self
+            .find(&filter.block_hash)
+            .ok_or(FilterMismatch::UnknownBlock(filter.block_hash))?;
+
+        let got = filter.filter_hash();
+        if got != entry.filter_hash {
+            return Err(FilterMismatch::Filter {
+                block_hash: filter.block_hash,
+                expected: entry.filter_hash,
+                got,
+            });
+        }
+        Ok(())
+    }
+
+    // Checks consecutive filter headers starting at `start_height`, as a
+    // peer would send them in cfheaders. Heights not indexed yet are
+    // skipped, since there is nothing to compare them with.
+    pub fn check_headers(
+        &self,
+        start_height: usize,
+        headers: &[Hash],
+    ) -> Result<(), FilterMismatch> {
+        for (height, got) in (start_height..).zip(headers) {
+            let Some(entry) = self.entries.get(height) else {
+                break;
+            };
+            if *got != entry.header {
+                return Err(FilterMismatch::Header {
+                    height,
+                    expected: entry.header,
+                    got: *got,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // The testnet genesis block pays to this script and nothing else.
+    const GENESIS_HASH: &str = "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943";
+    const GENESIS_SCRIPT: &str = "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61de\
+                                  b649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac";
+
+    #[test]
+    fn builds_the_genesis_filter() {
+        let script = hex(GENESIS_SCRIPT);
+        let filter = BlockFilter::build(GENESIS_HASH.parse().unwrap(), [script.as_slice()]);
+        assert_eq!(filter.content, hex("019dfca8"));
+        assert_eq!(filter.len(), 1);
+        assert!(filter.match_any([script.as_slice()]));
+        assert!(!filter.match_any([&script[1..]]));
+    }
+
+    // From BIP158's testnet vectors: block hash, previous filter header,
+    // filter and filter header.
+    #[cfg(feature = "crypto-hash")]
+    #[test]
+    fn chains_testnet_filter_headers() {
+        let vectors = [
+            (
+                GENESIS_HASH,
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "019dfca8",
+                "21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750",
+            ),
+            (
+                "000000006c02c8ea6e4ff69651f7fcde348fb9d557a06e6957b65552002a7820",
+                "d7bdac13a59d745b1add0d2ce852f1a0442e8945fc1bf3848d3cbffd88c24fe1",
+                "0174a170",
+                "186afd11ef2b5e7e3504f2e8cbf8df28a1fd251fe53d60dff8b1467d1b386cf0",
+            ),
+            (
+                "000000008b896e272758da5297bcd98fdc6d97c9b765ecec401e286dc1fdbe10",
+                "186afd11ef2b5e7e3504f2e8cbf8df28a1fd251fe53d60dff8b1467d1b386cf0",
+                "016cf7a0",
+                "8d63aadf5ab7257cb6d2316a57b16f517bff1c6388f124ec4c04af1212729d2a",
+            ),
+        ];
+
+        for (block_hash, prev_header, content, header) in vectors {
+            let filter = BlockFilter::new(block_hash.parse().unwrap(), hex(content));
+            assert_eq!(
+                filter.header(&prev_header.parse().unwrap()),
+                header.parse().unwrap(),
+                "block {block_hash}"
+            );
+        }
+    }
+
+    #[test]
+    fn golomb_round_trip() {
+        let values = [
+            0,
+            1,
+            7,
+            (1 << P) - 1,
+            1 << P,
+            (1 << P) + 1,
+            5 * M,
+            u32::MAX as u64,
+        ];
+        let mut writer = BitWriter::new();
+        for value in values {
+            golomb_encode(&mut writer, value, P);
+        }
+        let bytes = writer.into_bytes();
+
+        let mut reader = BitReader::new(&bytes);
+        for value in values {
+            assert_eq!(golomb_decode(&mut reader, P), Some(value));
+        }
+        // Only the padding of the last byte is left, all zero bits, which
+        // decode to a zero quotient and then run out.
+        assert_eq!(golomb_decode(&mut reader, P), None);
+    }
+
+    #[test]
+    fn truncated_filters_match_nothing() {
+        let script = hex(GENESIS_SCRIPT);
+        let block_hash = GENESIS_HASH.parse().unwrap();
+        for content in ["", "01", "019d", "029dfca8", "fd", "fd0100"] {
+            let filter = BlockFilter::new(block_hash, hex(content));
+            assert!(!filter.match_any([script.as_slice()]), "filter {content}");
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime};
+
+pub use btc_lib_proc_macros::BitcoinType;
+
+use crate::types::Magic;
+
+#[derive(Debug, Clone)]
+pub struct ByteReader {
+    bytes: Vec<u8>,
+    it: usize,
+}
+
+impl ByteReader {
+    pub fn new(bytes: Vec<u8>) -> ByteReader {
+        ByteReader { bytes, it: 0 }
+    }
+
+    pub fn take(&mut self, amnt: usize) -> Result<&[u8], DecodeError> {
+        let end = self.end(amnt)?;
+        let ret = &self.bytes[self.it..end];
+        self.it = end;
+        Ok(ret)
+    }
+
+    pub fn peek(&self, amnt: usize) -> Result<&[u8], DecodeError> {
+        Ok(&self.bytes[self.it..self.end(amnt)?])
+    }
+
+    // Where `amnt` more bytes end, if there are that many.
+    fn end(&self, amnt: usize) -> Result<usize, DecodeError> {
+        if amnt > self.remaining() {
+            return Err(DecodeError::UnexpectedEnd {
+                needed: amnt,
+                remaining: self.remaining(),
+            });
+        }
+        Ok(self.it + amnt)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.it
+    }
+
+    pub fn position(&self) -> usize {
+        self.it
+    }
+
+    // All the bytes, read or not.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    // The bytes not read yet, moved out rather than copied when nothing
+    // was read.
+    pub fn take_rest(&mut self) -> Vec<u8> {
+        let rest = if self.it == 0 {
+            core::mem::take(&mut self.bytes)
+        } else {
+            self.bytes.split_off(self.it)
+        };
+        self.it = self.bytes.len();
+        rest
+    }
+
+    // Bytes left over once a payload is decoded mean that the peer and
+    // this library disagree on its format.
+    pub fn expect_end(&self) -> Result<(), DecodeError> {
+        if self.remaining() == 0 {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingBytes {
+                size: self.bytes.len(),
+                consumed: self.position(),
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    TrailingBytes { size: usize, consumed: usize },
+    // The payload ended before what it says it holds.
+    UnexpectedEnd { needed: usize, remaining: usize },
+    // A derived enum without a #[bitcoin(other)] variant got a tag none
+    // of its variants have.
+    UnknownTag(&'static str),
+    // A frame for another network.
+    WrongMagic { expected: Magic, got: Magic },
+    // A frame whose payload doesn't hash to the checksum in its header.
+    ChecksumMismatch,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TrailingBytes { size, consumed } => write!(
+                f,
+                "{} trailing bytes after decoding {consumed} of {size}",
+                size - consumed
+            ),
+            DecodeError::UnexpectedEnd { needed, remaining } => write!(
+                f,
+                "unexpected end, {needed} more bytes needed but {remaining} left"
+            ),
+            DecodeError::UnknownTag(type_name) => write!(f, "unknown {type_name} tag"),
+            DecodeError::WrongMagic { expected, got } => {
+                write!(f, "network magic {got}, expected {expected}")
+            }
+            DecodeError::ChecksumMismatch => write!(f, "checksum mismatch"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+#[deprecated(note = "renamed to `ByteReader`")]
+pub type Scanner = ByteReader;
+
+pub trait BitcoinType {
+    fn to_blob(&self) -> Vec<u8>;
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError>
+    where
+        Self: Sized;
+
+    // How many bytes to_blob will return, for allocating buffers up
+    // front. Only a hint, 0 when it isn't known.
+    fn encoded_size_hint(&self) -> usize {
+        0
+    }
+}
+
+impl BitcoinType for u8 {
+    fn to_blob(&self) -> Vec<u8> {
+        vec![*self]
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        Ok(blob.take(1)?[0])
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        1
+    }
+}
+
+impl BitcoinType for u16 {
+    fn to_blob(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        Ok(Self::from_le_bytes(blob.take(2)?.try_into().unwrap()))
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        2
+    }
+}
+
+impl BitcoinType for u32 {
+    fn to_blob(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        Ok(Self::from_le_bytes(blob.take(4)?.try_into().unwrap()))
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        4
+    }
+}
+
+impl BitcoinType for i32 {
+    fn to_blob(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        Ok(Self::from_le_bytes(blob.take(4)?.try_into().unwrap()))
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        4
+    }
+}
+
+impl BitcoinType for u64 {
+    fn to_blob(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        Ok(Self::from_le_bytes(blob.take(8)?.try_into().unwrap()))
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        8
+    }
+}
+
+impl BitcoinType for bool {
+    fn to_blob(&self) -> Vec<u8> {
+        (*self as u8).to_blob()
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        Ok(u8::from_blob(blob)? != 0)
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        1
+    }
+}
+
+impl BitcoinType for usize {
+    fn to_blob(&self) -> Vec<u8> {
+        if *self < 0xfd {
+            (*self as u8).to_le_bytes().to_vec()
+        } else if *self <= 0xffff {
+            let mut ret = vec![0xfd];
+            ret.extend((*self as u16).to_le_bytes().to_vec());
+            ret
+        } else if *self <= 0xffff_ffff {
+            let mut ret = vec![0xfe];
+            ret.extend((*self as u32).to_le_bytes().to_vec());
+            ret
+        } else {
+            let mut ret = vec![0xff];
+            ret.extend((*self as u64).to_le_bytes().to_vec());
+            ret
+        }
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        let first_byte = u8::from_blob(blob)?;
+        Ok(match first_byte {
+            0xff => u64::from_blob(blob)? as usize,
+            0xfe => u32::from_blob(blob)? as usize,
+            0xfd => u16::from_blob(blob)? as usize,
+            x => x as usize,
+        })
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        match *self {
+            0..=0xfc => 1,
+            0xfd..=0xffff => 3,
+            0x1_0000..=0xffff_ffff => 5,
+            _ => 9,
+        }
+    }
+}
+
+impl BitcoinType for String {
+    fn to_blob(&self) -> Vec<u8> {
+        let mut ret = vec![];
+        ret.extend(self.len().to_blob());
+        ret.extend(self.bytes());
+        ret
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        let len = usize::from_blob(blob)?;
+        let str = blob.take(len)?;
+        Ok(String::from_utf8_lossy(str).to_string())
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        self.len().encoded_size_hint() + self.len()
+    }
+}
+
+#[cfg(feature = "std")]
+impl BitcoinType for SystemTime {
+    fn to_blob(&self) -> Vec<u8> {
+        let time = self.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        time.as_secs().to_blob()
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        let secs = u64::from_blob(blob)?;
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        8
+    }
+}
+
+impl<T: BitcoinType, const N: usize> BitcoinType for [T; N] {
+    fn to_blob(&self) -> Vec<u8> {
+        self.iter().flat_map(|e| e.to_blob()).collect()
+    }
+
+    // Hashes are arrays, so this runs for every inventory entry and
+    // header field and shouldn't go through a Vec.
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        let elements: [Result<T, DecodeError>; N] = core::array::from_fn(|_| T::from_blob(blob));
+        if let Some(Err(e)) = elements.iter().find(|e| e.is_err()) {
+            return Err(e.clone());
+        }
+        Ok(elements.map(|e| e.ok().unwrap()))
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        self.iter().map(T::encoded_size_hint).sum()
+    }
+}
+
+impl<T: BitcoinType> BitcoinType for Vec<T> {
+    fn to_blob(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(self.encoded_size_hint());
+        ret.extend(self.len().to_blob());
+        for e in self {
+            ret.extend(e.to_blob());
+        }
+        ret
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        let count = usize::from_blob(blob)?;
+        // The count comes from the peer, so it only reserves as much as
+        // the bytes left could hold, each element taking at least one.
+        let mut vec = Vec::with_capacity(count.min(blob.remaining()));
+        for _ in 0..count {
+            vec.push(T::from_blob(blob)?);
+        }
+        Ok(vec)
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        self.len().encoded_size_hint() + self.iter().map(T::encoded_size_hint).sum::<usize>()
+    }
+}
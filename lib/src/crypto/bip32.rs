@@ -0,0 +1,623 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use hmac::{Hmac, Mac};
+use k256::elliptic_curve::ff::PrimeField;
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::{ProjectivePoint, PublicKey, Scalar, SecretKey};
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::types::Network;
+
+pub const HARDENED: u32 = 1 << 31;
+
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+const XPRV_VERSION: [u8; 4] = [0x04, 0x88, 0xad, 0xe4];
+const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+const TPRV_VERSION: [u8; 4] = [0x04, 0x35, 0x83, 0x94];
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    InvalidBase58,
+    InvalidChecksum,
+    InvalidLength(usize),
+    UnknownVersion([u8; 4]),
+    InvalidKey,
+    HardenedFromPublic,
+    InvalidPath(String),
+    // A key at depth 255 has no children; the depth is a single byte.
+    MaxDepth,
+    // A master key, at depth 0, with a parent fingerprint or child number.
+    InvalidDepth,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidBase58 => write!(f, "invalid base58 character"),
+            Error::InvalidChecksum => write!(f, "invalid base58 checksum"),
+            Error::InvalidLength(len) => write!(f, "extended key has {len} bytes, expected 78"),
+            Error::UnknownVersion(v) => write!(f, "unknown extended key version {v:02x?}"),
+            Error::InvalidKey => write!(f, "derived key is invalid"),
+            Error::HardenedFromPublic => {
+                write!(f, "hardened child can not be derived from a public key")
+            }
+            Error::InvalidPath(p) => write!(f, "invalid derivation path \"{p}\""),
+            Error::MaxDepth => write!(f, "key is at the maximum depth of 255"),
+            Error::InvalidDepth => {
+                write!(f, "key at depth 0 has a parent fingerprint or child number")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+// Mainnet keys are xpub/xprv and all the test networks share tpub/tprv,
+// which decode as Network::Testnet.
+fn versions(network: Network) -> ([u8; 4], [u8; 4]) {
+    match network {
+        Network::Mainnet => (XPUB_VERSION, XPRV_VERSION),
+        Network::Testnet | Network::Testnet4 | Network::Signet | Network::Regtest => {
+            (TPUB_VERSION, TPRV_VERSION)
+        }
+    }
+}
+
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(Sha256::digest(data)).into()
+}
+
+fn hmac_sha512(key: &[u8], data: &[u8]) -> [u8; 64] {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+pub fn base58check_encode(data: &[u8]) -> String {
+    let mut payload = data.to_vec();
+    payload.extend(&Sha256::digest(Sha256::digest(data))[0..4]);
+
+    let mut digits: Vec<u8> = vec![];
+    for &byte in &payload {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let zeros = payload.iter().take_while(|&&b| b == 0).count();
+    let mut ret = "1".repeat(zeros);
+    ret.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|&d| BASE58_ALPHABET[d as usize] as char),
+    );
+    ret
+}
+
+pub fn base58check_decode(s: &str) -> Result<Vec<u8>, Error> {
+    let mut bytes: Vec<u8> = vec![];
+    for c in s.bytes() {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or(Error::InvalidBase58)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let zeros = s.bytes().take_while(|&c| c == b'1').count();
+    let mut ret = vec![0; zeros];
+    ret.extend(bytes.iter().rev());
+
+    if ret.len() < 4 {
+        return Err(Error::InvalidChecksum);
+    }
+
+    let (data, check_sum) = ret.split_at(ret.len() - 4);
+    if Sha256::digest(Sha256::digest(data))[0..4] != *check_sum {
+        return Err(Error::InvalidChecksum);
+    }
+
+    Ok(data.to_vec())
+}
+
+pub fn parse_path(path: &str) -> Result<Vec<u32>, Error> {
+    let mut parts = path.split('/');
+    if parts.next() != Some("m") {
+        return Err(Error::InvalidPath(path.to_string()));
+    }
+
+    parts
+        .map(|p| {
+            let (index, hardened) = match p.strip_suffix(['\'', 'h']) {
+                Some(index) => (index, true),
+                None => (p, false),
+            };
+
+            match index.parse::<u32>() {
+                Ok(index) if index < HARDENED => {
+                    Ok(if hardened { index | HARDENED } else { index })
+                }
+                _ => Err(Error::InvalidPath(path.to_string())),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xpub {
+    pub network: Network,
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub public_key: PublicKey,
+}
+
+#[derive(Clone)]
+pub struct Xprv {
+    pub network: Network,
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub private_key: SecretKey,
+}
+
+impl fmt::Debug for Xprv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Xprv")
+            .field("network", &self.network)
+            .field("depth", &self.depth)
+            .field("parent_fingerprint", &self.parent_fingerprint)
+            .field("child_number", &self.child_number)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Xpub {
+    pub fn public_key_bytes(&self) -> [u8; 33] {
+        self.public_key
+            .to_encoded_point(true)
+            .as_bytes()
+            .try_into()
+            .unwrap()
+    }
+
+    pub fn identifier(&self) -> [u8; 20] {
+        hash160(&self.public_key_bytes())
+    }
+
+    pub fn fingerprint(&self) -> [u8; 4] {
+        self.identifier()[0..4].try_into().unwrap()
+    }
+
+    pub fn derive_child(&self, index: u32) -> Result<Xpub, Error> {
+        if index >= HARDENED {
+            return Err(Error::HardenedFromPublic);
+        }
+
+        let mut data = self.public_key_bytes().to_vec();
+        data.extend(index.to_be_bytes());
+        let (tweak, chain_code) = split_hmac(&self.chain_code, &data)?;
+
+        let point = ProjectivePoint::GENERATOR * tweak + self.public_key.to_projective();
+        let public_key =
+            PublicKey::from_affine(point.to_affine()).map_err(|_| Error::InvalidKey)?;
+
+        Ok(Xpub {
+            network: self.network,
+            depth: self.depth.checked_add(1).ok_or(Error::MaxDepth)?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            public_key,
+        })
+    }
+
+    pub fn derive_path(&self, path: &[u32]) -> Result<Xpub, Error> {
+        path.iter()
+            .try_fold(self.clone(), |key, &index| key.derive_child(index))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut ret = versions(self.network).0.to_vec();
+        ret.push(self.depth);
+        ret.extend(self.parent_fingerprint);
+        ret.extend(self.child_number.to_be_bytes());
+        ret.extend(self.chain_code);
+        ret.extend(self.public_key_bytes());
+        ret
+    }
+}
+
+impl Xprv {
+    pub fn new_master(seed: &[u8], network: Network) -> Result<Xprv, Error> {
+        let (key, chain_code) = split_hmac(b"Bitcoin seed", seed)?;
+
+        Ok(Xprv {
+            network,
+            depth: 0,
+            parent_fingerprint: [0; 4],
+            child_number: 0,
+            chain_code,
+            private_key: SecretKey::from_bytes(&key.to_repr()).map_err(|_| Error::InvalidKey)?,
+        })
+    }
+
+    pub fn to_xpub(&self) -> Xpub {
+        Xpub {
+            network: self.network,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            public_key: self.private_key.public_key(),
+        }
+    }
+
+    pub fn fingerprint(&self) -> [u8; 4] {
+        self.to_xpub().fingerprint()
+    }
+
+    pub fn derive_child(&self, index: u32) -> Result<Xprv, Error> {
+        let mut data = if index >= HARDENED {
+            let mut data = vec![0];
+            data.extend(self.private_key.to_bytes());
+            data
+        } else {
+            self.to_xpub().public_key_bytes().to_vec()
+        };
+        data.extend(index.to_be_bytes());
+        let (tweak, chain_code) = split_hmac(&self.chain_code, &data)?;
+
+        let key = tweak + *self.private_key.to_nonzero_scalar();
+        let private_key = SecretKey::from_bytes(&key.to_repr()).map_err(|_| Error::InvalidKey)?;
+
+        Ok(Xprv {
+            network: self.network,
+            depth: self.depth.checked_add(1).ok_or(Error::MaxDepth)?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            private_key,
+        })
+    }
+
+    pub fn derive_path(&self, path: &[u32]) -> Result<Xprv, Error> {
+        path.iter()
+            .try_fold(self.clone(), |key, &index| key.derive_child(index))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut ret = versions(self.network).1.to_vec();
+        ret.push(self.depth);
+        ret.extend(self.parent_fingerprint);
+        ret.extend(self.child_number.to_be_bytes());
+        ret.extend(self.chain_code);
+        ret.push(0);
+        ret.extend(self.private_key.to_bytes());
+        ret
+    }
+}
+
+fn split_hmac(key: &[u8], data: &[u8]) -> Result<(Scalar, [u8; 32]), Error> {
+    let hash = hmac_sha512(key, data);
+    let tweak: [u8; 32] = hash[0..32].try_into().unwrap();
+    let tweak = Option::from(Scalar::from_repr(tweak.into())).ok_or(Error::InvalidKey)?;
+    Ok((tweak, hash[32..64].try_into().unwrap()))
+}
+
+struct RawExtendedKey {
+    version: [u8; 4],
+    depth: u8,
+    parent_fingerprint: [u8; 4],
+    child_number: u32,
+    chain_code: [u8; 32],
+    key: [u8; 33],
+}
+
+impl FromStr for RawExtendedKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = base58check_decode(s)?;
+        if data.len() != 78 {
+            return Err(Error::InvalidLength(data.len()));
+        }
+
+        let depth = data[4];
+        let parent_fingerprint: [u8; 4] = data[5..9].try_into().unwrap();
+        let child_number = u32::from_be_bytes(data[9..13].try_into().unwrap());
+        if depth == 0 && (parent_fingerprint != [0; 4] || child_number != 0) {
+            return Err(Error::InvalidDepth);
+        }
+
+        Ok(RawExtendedKey {
+            version: data[0..4].try_into().unwrap(),
+            depth,
+            parent_fingerprint,
+            child_number,
+            chain_code: data[13..45].try_into().unwrap(),
+            key: data[45..78].try_into().unwrap(),
+        })
+    }
+}
+
+impl FromStr for Xpub {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = RawExtendedKey::from_str(s)?;
+
+        let network = match raw.version {
+            XPUB_VERSION => Network::Mainnet,
+            TPUB_VERSION => Network::Testnet,
+            v => return Err(Error::UnknownVersion(v)),
+        };
+
+        Ok(Xpub {
+            network,
+            depth: raw.depth,
+            parent_fingerprint: raw.parent_fingerprint,
+            child_number: raw.child_number,
+            chain_code: raw.chain_code,
+            public_key: PublicKey::from_sec1_bytes(&raw.key).map_err(|_| Error::InvalidKey)?,
+        })
+    }
+}
+
+impl FromStr for Xprv {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = RawExtendedKey::from_str(s)?;
+
+        let network = match raw.version {
+            XPRV_VERSION => Network::Mainnet,
+            TPRV_VERSION => Network::Testnet,
+            v => return Err(Error::UnknownVersion(v)),
+        };
+
+        if raw.key[0] != 0 {
+            return Err(Error::InvalidKey);
+        }
+
+        Ok(Xprv {
+            network,
+            depth: raw.depth,
+            parent_fingerprint: raw.parent_fingerprint,
+            child_number: raw.child_number,
+            chain_code: raw.chain_code,
+            private_key: SecretKey::from_slice(&raw.key[1..]).map_err(|_| Error::InvalidKey)?,
+        })
+    }
+}
+
+impl fmt::Display for Xpub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", base58check_encode(&self.encode()))
+    }
+}
+
+impl fmt::Display for Xprv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", base58check_encode(&self.encode()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // Derives each path from the seed and checks both keys, that they
+    // parse back, and that public derivation agrees where it can be used.
+    fn check(seed: &str, cases: &[(&str, &str, &str)]) {
+        let master = Xprv::new_master(&hex(seed), Network::Mainnet).unwrap();
+        for &(path, xprv, xpub) in cases {
+            let path = parse_path(path).unwrap();
+            let key = master.derive_path(&path).unwrap();
+            assert_eq!(key.to_string(), xprv);
+            assert_eq!(key.to_xpub().to_string(), xpub);
+            assert_eq!(Xprv::from_str(xprv).unwrap().to_string(), xprv);
+            assert_eq!(Xpub::from_str(xpub).unwrap(), key.to_xpub());
+
+            if let Some((&last, parent)) = path.split_last() {
+                if last < HARDENED {
+                    let parent = master.derive_path(parent).unwrap().to_xpub();
+                    assert_eq!(parent.derive_child(last).unwrap(), key.to_xpub());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bip32_vector_1() {
+        check(
+            "000102030405060708090a0b0c0d0e0f",
+            &[
+                (
+                    "m",
+                    "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi",
+                    "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
+                ),
+                (
+                    "m/0h",
+                    "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7",
+                    "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw",
+                ),
+                (
+                    "m/0h/1",
+                    "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs",
+                    "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ",
+                ),
+                (
+                    "m/0h/1/2h",
+                    "xprv9z4pot5VBttmtdRTWfWQmoH1taj2axGVzFqSb8C9xaxKymcFzXBDptWmT7FwuEzG3ryjH4ktypQSAewRiNMjANTtpgP4mLTj34bhnZX7UiM",
+                    "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5",
+                ),
+                (
+                    "m/0h/1/2h/2",
+                    "xprvA2JDeKCSNNZky6uBCviVfJSKyQ1mDYahRjijr5idH2WwLsEd4Hsb2Tyh8RfQMuPh7f7RtyzTtdrbdqqsunu5Mm3wDvUAKRHSC34sJ7in334",
+                    "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV",
+                ),
+                (
+                    "m/0h/1/2h/2/1000000000",
+                    "xprvA41z7zogVVwxVSgdKUHDy1SKmdb533PjDz7J6N6mV6uS3ze1ai8FHa8kmHScGpWmj4WggLyQjgPie1rFSruoUihUZREPSL39UNdE3BBDu76",
+                    "xpub6H1LXWLaKsWFhvm6RVpEL9P4KfRZSW7abD2ttkWP3SSQvnyA8FSVqNTEcYFgJS2UaFcxupHiYkro49S8yGasTvXEYBVPamhGW6cFJodrTHy",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn bip32_vector_2() {
+        check(
+            "fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542",
+            &[
+                (
+                    "m",
+                    "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U",
+                    "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB",
+                ),
+                (
+                    "m/0",
+                    "xprv9vHkqa6EV4sPZHYqZznhT2NPtPCjKuDKGY38FBWLvgaDx45zo9WQRUT3dKYnjwih2yJD9mkrocEZXo1ex8G81dwSM1fwqWpWkeS3v86pgKt",
+                    "xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH",
+                ),
+                (
+                    "m/0/2147483647h",
+                    "xprv9wSp6B7kry3Vj9m1zSnLvN3xH8RdsPP1Mh7fAaR7aRLcQMKTR2vidYEeEg2mUCTAwCd6vnxVrcjfy2kRgVsFawNzmjuHc2YmYRmagcEPdU9",
+                    "xpub6ASAVgeehLbnwdqV6UKMHVzgqAG8Gr6riv3Fxxpj8ksbH9ebxaEyBLZ85ySDhKiLDBrQSARLq1uNRts8RuJiHjaDMBU4Zn9h8LZNnBC5y4a",
+                ),
+                (
+                    "m/0/2147483647h/1",
+                    "xprv9zFnWC6h2cLgpmSA46vutJzBcfJ8yaJGg8cX1e5StJh45BBciYTRXSd25UEPVuesF9yog62tGAQtHjXajPPdbRCHuWS6T8XA2ECKADdw4Ef",
+                    "xpub6DF8uhdarytz3FWdA8TvFSvvAh8dP3283MY7p2V4SeE2wyWmG5mg5EwVvmdMVCQcoNJxGoWaU9DCWh89LojfZ537wTfunKau47EL2dhHKon",
+                ),
+                (
+                    "m/0/2147483647h/1/2147483646h",
+                    "xprvA1RpRA33e1JQ7ifknakTFpgNXPmW2YvmhqLQYMmrj4xJXXWYpDPS3xz7iAxn8L39njGVyuoseXzU6rcxFLJ8HFsTjSyQbLYnMpCqE2VbFWc",
+                    "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL",
+                ),
+                (
+                    "m/0/2147483647h/1/2147483646h/2",
+                    "xprvA2nrNbFZABcdryreWet9Ea4LvTJcGsqrMzxHx98MMrotbir7yrKCEXw7nadnHM8Dq38EGfSh6dqA9QWTyefMLEcBYJUuekgW4BYPJcr9E7j",
+                    "xpub6FnCn6nSzZAw5Tw7cgR9bi15UV96gLZhjDstkXXxvCLsUXBGXPdSnLFbdpq8p9HmGsApME5hQTZ3emM2rnY5agb9rXpVGyy3bdW6EEgAtqt",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn bip32_vector_3() {
+        check(
+            "4b381541583be4423346c643850da4b320e46a87ae3d2a4e6da11eba819cd4acba45d239319ac14f863b8d5ab5a0d0c64d2e8a1e7d1457df2e5a3c51c73235be",
+            &[
+                (
+                    "m",
+                    "xprv9s21ZrQH143K25QhxbucbDDuQ4naNntJRi4KUfWT7xo4EKsHt2QJDu7KXp1A3u7Bi1j8ph3EGsZ9Xvz9dGuVrtHHs7pXeTzjuxBrCmmhgC6",
+                    "xpub661MyMwAqRbcEZVB4dScxMAdx6d4nFc9nvyvH3v4gJL378CSRZiYmhRoP7mBy6gSPSCYk6SzXPTf3ND1cZAceL7SfJ1Z3GC8vBgp2epUt13",
+                ),
+                (
+                    "m/0h",
+                    "xprv9uPDJpEQgRQfDcW7BkF7eTya6RPxXeJCqCJGHuCJ4GiRVLzkTXBAJMu2qaMWPrS7AANYqdq6vcBcBUdJCVVFceUvJFjaPdGZ2y9WACViL4L",
+                    "xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y",
+                ),
+            ],
+        );
+    }
+
+    // Test vector 5, keys that must not parse.
+    #[test]
+    fn bip32_invalid_keys() {
+        let keys = [
+            // Pubkey version / prvkey mismatch.
+            "xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6LBpB85b3D2yc8sfvZU521AAwdZafEz7mnzBBsz4wKY5fTtTQBm",
+            // Prvkey version / pubkey mismatch.
+            "xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFGTQQD3dC4H2D5GBj7vWvSQaaBv5cxi9gafk7NF3pnBju6dwKvH",
+            // Invalid pubkey prefix 04.
+            "xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6Txnt3siSujt9RCVYsx4qHZGc62TG4McvMGcAUjeuwZdduYEvFn",
+            // Invalid prvkey prefix 04.
+            "xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFGpWnsj83BHtEy5Zt8CcDr1UiRXuWCmTQLxEK9vbz5gPstX92JQ",
+            // Invalid pubkey prefix 01.
+            "xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6N8ZMMXctdiCjxTNq964yKkwrkBJJwpzZS4HS2fxvyYUA4q2Xe4",
+            // Invalid prvkey prefix 01.
+            "xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFAzHGBP2UuGCqWLTAPLcMtD9y5gkZ6Eq3Rjuahrv17fEQ3Qen6J",
+            // Zero depth with non-zero parent fingerprint.
+            "xprv9s2SPatNQ9Vc6GTbVMFPFo7jsaZySyzk7L8n2uqKXJen3KUmvQNTuLh3fhZMBoG3G4ZW1N2kZuHEPY53qmbZzCHshoQnNf4GvELZfqTUrcv",
+            // Zero depth with non-zero parent fingerprint.
+            "xpub661no6RGEX3uJkY4bNnPcw4URcQTrSibUZ4NqJEw5eBkv7ovTwgiT91XX27VbEXGENhYRCf7hyEbWrR3FewATdCEebj6znwMfQkhRYHRLpJ",
+            // Zero depth with non-zero index.
+            "xprv9s21ZrQH4r4TsiLvyLXqM9P7k1K3EYhA1kkD6xuquB5i39AU8KF42acDyL3qsDbU9NmZn6MsGSUYZEsuoePmjzsB3eFKSUEh3Gu1N3cqVUN",
+            // Zero depth with non-zero index.
+            "xpub661MyMwAuDcm6CRQ5N4qiHKrJ39Xe1R1NyfouMKTTWcguwVcfrZJaNvhpebzGerh7gucBvzEQWRugZDuDXjNDRmXzSZe4c7mnTK97pTvGS8",
+            // Unknown extended key version.
+            "DMwo58pR1QLEFihHiXPVykYB6fJmsTeHvyTp7hRThAtCX8CvYzgPcn8XnmdfHGMQzT7ayAmfo4z3gY5KfbrZWZ6St24UVf2Qgo6oujFktLHdHY4",
+            // Unknown extended key version.
+            "DMwo58pR1QLEFihHiXPVykYB6fJmsTeHvyTp7hRThAtCX8CvYzgPcn8XnmdfHPmHJiEDXkTiJTVV9rHEBUem2mwVbbNfvT2MTcAqj3nesx8uBf9",
+            // Private key 0 not in 1..n-1.
+            "xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzF93Y5wvzdUayhgkkFoicQZcP3y52uPPxFnfoLZB21Teqt1VvEHx",
+            // Private key n not in 1..n-1.
+            "xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFAzHGBP2UuGCqWLTAPLcMtD9y5gkZ6Eq3Rjuahrv17fENZ3QzxW",
+            // Invalid pubkey 020000..07.
+            "xpub661MyMwAqRbcEYS8w7XLSVeEsBXy79zSzH1J8vCdxAZningWLdN3zgtU6Q5JXayek4PRsn35jii4veMimro1xefsM58PgBMrvdYre8QyULY",
+            // Invalid checksum.
+            "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHL",
+        ];
+        for key in keys {
+            assert!(
+                Xpub::from_str(key).is_err() && Xprv::from_str(key).is_err(),
+                "{key}"
+            );
+        }
+
+        assert_eq!(
+            Xprv::from_str("xprv9s2SPatNQ9Vc6GTbVMFPFo7jsaZySyzk7L8n2uqKXJen3KUmvQNTuLh3fhZMBoG3G4ZW1N2kZuHEPY53qmbZzCHshoQnNf4GvELZfqTUrcv").unwrap_err(),
+            Error::InvalidDepth
+        );
+        assert_eq!(
+            Xpub::from_str("xpub661MyMwAuDcm6CRQ5N4qiHKrJ39Xe1R1NyfouMKTTWcguwVcfrZJaNvhpebzGerh7gucBvzEQWRugZDuDXjNDRmXzSZe4c7mnTK97pTvGS8").unwrap_err(),
+            Error::InvalidDepth
+        );
+    }
+
+    #[test]
+    fn stops_at_max_depth() {
+        let mut key = Xprv::new_master(&[7; 32], Network::Testnet).unwrap();
+        key.depth = u8::MAX;
+        assert_eq!(key.derive_child(0).unwrap_err(), Error::MaxDepth);
+        assert_eq!(key.to_xpub().derive_child(0).unwrap_err(), Error::MaxDepth);
+        assert!(key.to_string().starts_with("tprv"));
+    }
+}
@@ -0,0 +1,97 @@
+use alloc::vec::Vec;
+
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::codec::{BitcoinType, ByteReader};
+use crate::types::Hash;
+
+#[cfg(feature = "crypto")]
+pub mod bip32;
+
+pub fn sha256d(data: &[u8]) -> Hash {
+    Hash(Sha256::digest(Sha256::digest(data)).into())
+}
+
+pub fn sha256(data: &[u8]) -> Hash {
+    Hash(Sha256::digest(data).into())
+}
+
+pub fn get_check_sum(src: &[u8]) -> Vec<u8> {
+    let mut checksum = Checksum::new();
+    checksum.update(src);
+    checksum.finalize_reset().to_vec()
+}
+
+// Incremental message checksum (first 4 bytes of a double SHA256). After
+// finalize_reset() the same instance can be used for the next message.
+#[derive(Debug, Clone, Default)]
+pub struct Checksum {
+    hasher: Sha256,
+}
+
+impl Checksum {
+    pub fn new() -> Checksum {
+        Checksum::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    pub fn finalize_reset(&mut self) -> [u8; 4] {
+        let hash = Sha256::digest(self.hasher.finalize_reset());
+        [hash[0], hash[1], hash[2], hash[3]]
+    }
+}
+
+// The txid of a serialized transaction: the double SHA256 of it without
+// the segwit marker, flag and witnesses. None if it doesn't parse.
+pub fn txid(raw: &[u8]) -> Option<Hash> {
+    let segwit = raw.get(4..6) == Some(&[0x00, 0x01]);
+    if !segwit {
+        return (raw.len() >= 10).then(|| sha256d(raw));
+    }
+
+    let mut pos = 6;
+    let read_count = |pos: &mut usize| -> Option<usize> {
+        let bytes = raw.get(*pos..)?;
+        let len = match *bytes.first()? {
+            0xfd => 3,
+            0xfe => 5,
+            0xff => 9,
+            _ => 1,
+        };
+        let count = usize::from_blob(&mut ByteReader::new(bytes.get(..len)?.to_vec())).ok()?;
+        *pos += len;
+        Some(count)
+    };
+
+    let inputs_start = pos;
+    let inputs = read_count(&mut pos)?;
+    for _ in 0..inputs {
+        pos += 36;
+        let script = read_count(&mut pos)?;
+        pos += script + 4;
+    }
+    let outputs = read_count(&mut pos)?;
+    for _ in 0..outputs {
+        pos += 8;
+        let script = read_count(&mut pos)?;
+        pos += script;
+    }
+    let inputs_end = pos;
+
+    for _ in 0..inputs {
+        let items = read_count(&mut pos)?;
+        for _ in 0..items {
+            let len = read_count(&mut pos)?;
+            pos += len;
+        }
+    }
+
+    let mut stripped = raw.get(..4)?.to_vec();
+    stripped.extend(raw.get(inputs_start..inputs_end)?);
+    stripped.extend(raw.get(pos..pos + 4)?);
+    (raw.len() == pos + 4).then(|| sha256d(&stripped))
+}
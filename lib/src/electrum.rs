@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::result;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::crypto::{self, sha256};
+use crate::types::Hash;
+
+pub const PROTOCOL_VERSION: &str = "1.4";
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    IoErr(io::Error),
+    ProtocolErr,
+    // An error response, with the code the server gave.
+    ServerErr(i64),
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub msg: Option<String>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Error {
+        Error { kind, msg: None }
+    }
+
+    pub fn with_msg(kind: ErrorKind, msg: impl ToString) -> Error {
+        Error {
+            kind,
+            msg: Some(msg.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::new(ErrorKind::IoErr(e))
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+// Electrum servers index scripts by their SHA256, which is shown byte
+// reversed like block and transaction hashes.
+pub fn script_hash(script: &[u8]) -> Hash {
+    sha256(script)
+}
+
+// Sent by the server when the history of a subscribed script changes.
+// The status is a digest of the history, None if there is none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusUpdate {
+    pub script_hash: Hash,
+    pub status: Option<String>,
+}
+
+// A client for the Electrum protocol: newline separated JSON-RPC over
+// plain TCP. Notifications that arrive while waiting for a response are
+// kept until asked for with next_update().
+pub struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    next_id: u64,
+    updates: VecDeque<StatusUpdate>,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Client> {
+        let stream = TcpStream::connect(addr)?;
+        let reader = BufReader::new(stream.try_clone()?);
+
+        Ok(Client {
+            stream,
+            reader,
+            next_id: 0,
+            updates: VecDeque::new(),
+        })
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.stream.set_read_timeout(timeout)?)
+    }
+
+    // Negotiates the protocol version, which servers expect before
+    // anything else. Returns the server software and protocol version.
+    pub fn server_version(&mut self, client_name: &str) -> Result<(String, String)> {
+        let result = self.call("server.version", json!([client_name, PROTOCOL_VERSION]))?;
+
+        match result.as_array().map(Vec::as_slice) {
+            Some([Value::String(software), Value::String(version)]) => {
+                Ok((software.clone(), version.clone()))
+            }
+            _ => Err(unexpected("server.version", &result)),
+        }
+    }
+
+    // Subscribes to changes in the history of `script` and returns its
+    // current status.
+    pub fn script_hash_subscribe(&mut self, script: &[u8]) -> Result<Option<String>> {
+        let script_hash = script_hash(script);
+        let result = self.call(
+            "blockchain.scripthash.subscribe",
+            json!([script_hash.to_string()]),
+        )?;
+
+        match result {
+            Value::Null => Ok(None),
+            Value::String(status) => Ok(Some(status)),
+            _ => Err(unexpected("blockchain.scripthash.subscribe", &result)),
+        }
+    }
+
+    // Fetches a raw transaction, checking that it really has the txid
+    // that was asked for.
+    pub fn transaction_get(&mut self, txid: &Hash) -> Result<Vec<u8>> {
+        let result = self.call("blockchain.transaction.get", json!([txid.to_string()]))?;
+
+        let Some(raw) = result.as_str().and_then(parse_hex) else {
+            return Err(unexpected("blockchain.transaction.get", &result));
+        };
+        match crypto::txid(&raw) {
+            Some(got) if got == *txid => Ok(raw),
+            Some(got) => Err(Error::with_msg(
+                ErrorKind::ProtocolErr,
+                format!("Asked for transaction {txid}, got {got}"),
+            )),
+            None => Err(Error::with_msg(
+                ErrorKind::ProtocolErr,
+                format!("Could not parse transaction {txid}"),
+            )),
+        }
+    }
+
+    // Returns the oldest pending status update, waiting for one if there
+    // is none.
+    pub fn next_update(&mut self) -> Result<StatusUpdate> {
+        loop {
+            if let Some(update) = self.updates.pop_front() {
+                return Ok(update);
+            }
+            let msg = self.read_msg()?;
+            self.handle_notification(&msg);
+        }
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+        request.push('\n');
+        self.stream.write_all(request.as_bytes())?;
+
+        loop {
+            let mut msg = self.read_msg()?;
+            if msg.get("id").and_then(Value::as_u64) != Some(id) {
+                self.handle_notification(&msg);
+                continue;
+            }
+
+            return match msg["error"].take() {
+                Value::Null => Ok(msg["result"].take()),
+                error => Err(Error::with_msg(
+                    ErrorKind::ServerErr(error["code"].as_i64().unwrap_or(0)),
+                    match &error["message"] {
+                        Value::String(message) => message.clone(),
+                        _ => error.to_string(),
+                    },
+                )),
+            };
+        }
+    }
+
+    fn read_msg(&mut self) -> Result<Value> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(Error::new(ErrorKind::IoErr(
+                io::ErrorKind::UnexpectedEof.into(),
+            )));
+        }
+
+        serde_json::from_str(&line).map_err(|e| Error::with_msg(ErrorKind::ProtocolErr, e))
+    }
+
+    // Notifications other than status updates, such as new headers, are
+    // dropped since there is no way to subscribe to them here.
+    fn handle_notification(&mut self, msg: &Value) {
+        if msg["method"] != "blockchain.scripthash.subscribe" {
+            return;
+        }
+
+        if let Some(script_hash) = msg["params"][0].as_str().and_then(|s| s.parse().ok()) {
+            self.updates.push_back(StatusUpdate {
+                script_hash,
+                status: msg["params"][1].as_str().map(String::from),
+            });
+        }
+    }
+}
+
+fn unexpected(method: &str, result: &Value) -> Error {
+    Error::with_msg(
+        ErrorKind::ProtocolErr,
+        format!("Unexpected {method} result: {result}"),
+    )
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::types::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Sent => "sent",
+            Direction::Received => "received",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Counters {
+    pub messages: BTreeMap<(Direction, String), u64>,
+    // Bytes per command, message headers included.
+    pub command_bytes: BTreeMap<(Direction, String), u64>,
+    pub bytes: BTreeMap<Direction, u64>,
+    pub peers: u64,
+    pub handshake_failures: u64,
+    pub decode_errors: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Metrics> {
+        Arc::new(Metrics::default())
+    }
+
+    pub fn record_message(&self, direction: Direction, command: &Command, size: usize) {
+        // Unknown commands are chosen by the peer, so they share one label
+        // instead of growing the series set without bound.
+        let label = match command {
+            Command::Unknown(_) => "other".into(),
+            command => command.as_str(),
+        };
+
+        let mut counters = self.counters.lock().unwrap();
+        *counters
+            .messages
+            .entry((direction, label.to_string()))
+            .or_default() += 1;
+        *counters
+            .command_bytes
+            .entry((direction, label.to_string()))
+            .or_default() += size as u64;
+        *counters.bytes.entry(direction).or_default() += size as u64;
+    }
+
+    pub fn peer_connected(&self) {
+        self.counters.lock().unwrap().peers += 1;
+    }
+
+    pub fn peer_disconnected(&self) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.peers = counters.peers.saturating_sub(1);
+    }
+
+    pub fn handshake_failed(&self) {
+        self.counters.lock().unwrap().handshake_failures += 1;
+    }
+
+    pub fn decode_error(&self) {
+        self.counters.lock().unwrap().decode_errors += 1;
+    }
+
+    pub fn snapshot(&self) -> Counters {
+        self.counters.lock().unwrap().clone()
+    }
+
+    pub fn to_prometheus(&self) -> String {
+        let counters = self.snapshot();
+        let mut ret = String::new();
+
+        ret.push_str("# HELP btc_messages_total Messages exchanged with peers.\n");
+        ret.push_str("# TYPE btc_messages_total counter\n");
+        for ((direction, command), count) in &counters.messages {
+            writeln!(
+                ret,
+                "btc_messages_total{{direction=\"{}\",command=\"{command}\"}} {count}",
+                direction.as_str()
+            )
+            .unwrap();
+        }
+
+        ret.push_str("# HELP btc_bytes_total Bytes exchanged with peers, headers included.\n");
+        ret.push_str("# TYPE btc_bytes_total counter\n");
+        for (direction, bytes) in &counters.bytes {
+            writeln!(
+                ret,
+                "btc_bytes_total{{direction=\"{}\"}} {bytes}",
+                direction.as_str()
+            )
+            .unwrap();
+        }
+
+        ret.push_str("# HELP btc_message_bytes_total Bytes exchanged per command.\n");
+        ret.push_str("# TYPE btc_message_bytes_total counter\n");
+        for ((direction, command), bytes) in &counters.command_bytes {
+            writeln!(
+                ret,
+                "btc_message_bytes_total{{direction=\"{}\",command=\"{command}\"}} {bytes}",
+                direction.as_str()
+            )
+            .unwrap();
+        }
+
+        ret.push_str("# HELP btc_peers Currently connected peers.\n");
+        ret.push_str("# TYPE btc_peers gauge\n");
+        writeln!(ret, "btc_peers {}", counters.peers).unwrap();
+
+        ret.push_str("# HELP btc_handshake_failures_total Failed version handshakes.\n");
+        ret.push_str("# TYPE btc_handshake_failures_total counter\n");
+        writeln!(
+            ret,
+            "btc_handshake_failures_total {}",
+            counters.handshake_failures
+        )
+        .unwrap();
+
+        ret.push_str("# HELP btc_decode_errors_total Frames that could not be decoded.\n");
+        ret.push_str("# TYPE btc_decode_errors_total counter\n");
+        writeln!(ret, "btc_decode_errors_total {}", counters.decode_errors).unwrap();
+
+        ret
+    }
+
+    // Writes through a temporary file so collectors like node_exporter's
+    // textfile collector never observe a partially written file.
+    pub fn write_textfile(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("prom.tmp");
+        fs::write(&tmp, self.to_prometheus())?;
+        fs::rename(tmp, path)
+    }
+
+    pub fn serve(self: &Arc<Self>, addr: SocketAddr) -> io::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(addr)?;
+        let metrics = self.clone();
+
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+
+                // Every request gets the metrics, so the request itself is
+                // only drained, not parsed.
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+
+                let body = metrics.to_prometheus();
+                let _ = write!(
+                    stream,
+                    "HTTP/1.0 200 OK\r\n\
+                     Content-Type: text/plain; version=0.0.4\r\n\
+                     Content-Length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        }))
+    }
+}
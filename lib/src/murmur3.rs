@@ -0,0 +1,87 @@
+// MurmurHash3 (x86, 32-bit), the hash function of BIP37 bloom filters.
+pub fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mut h = seed;
+
+    let mut blocks = data.chunks_exact(4);
+    for block in &mut blocks {
+        let mut k = u32::from_le_bytes(block.try_into().unwrap());
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+        h ^= k;
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let mut k = tail
+            .iter()
+            .enumerate()
+            .fold(0u32, |k, (i, b)| k | (*b as u32) << (8 * i));
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h ^= k;
+    }
+
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^= h >> 16;
+
+    h
+}
+
+// The seed for each of a bloom filter's hash functions, as defined by BIP37.
+pub fn bloom_hash(hash_num: u32, tweak: u32, data: &[u8]) -> u32 {
+    murmur3(hash_num.wrapping_mul(0xfba04eba).wrapping_add(tweak), data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> alloc::vec::Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // The vectors Bitcoin Core uses for its BIP37 implementation.
+    #[test]
+    fn bip37_vectors() {
+        let vectors = [
+            (0x00000000, 0x00000000, ""),
+            (0x6a396f08, 0xfba4c795, ""),
+            (0x81f16f39, 0xffffffff, ""),
+            (0x514e28b7, 0x00000000, "00"),
+            (0xea3f0b17, 0xfba4c795, "00"),
+            (0xfd6cf10d, 0x00000000, "ff"),
+            (0x16c6b7ab, 0x00000000, "0011"),
+            (0x8eb51c3d, 0x00000000, "001122"),
+            (0xb4471bf8, 0x00000000, "00112233"),
+            (0xe2301fa8, 0x00000000, "0011223344"),
+            (0xfc2e4a15, 0x00000000, "001122334455"),
+            (0xb074502c, 0x00000000, "00112233445566"),
+            (0x8034d2a0, 0x00000000, "0011223344556677"),
+            (0xb4698def, 0x00000000, "001122334455667788"),
+        ];
+
+        for (expected, seed, data) in vectors {
+            assert_eq!(
+                murmur3(seed, &hex(data)),
+                expected,
+                "seed {seed:x}, data {data}"
+            );
+        }
+    }
+
+    #[test]
+    fn bloom_hash_seeds() {
+        assert_eq!(bloom_hash(0, 0, b"abc"), murmur3(0, b"abc"));
+        assert_eq!(bloom_hash(1, 5, b"abc"), murmur3(0xfba04eba + 5, b"abc"));
+    }
+}
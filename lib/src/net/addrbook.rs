@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
+
+use super::enrich::{self, Enricher};
+use super::PeerAddr;
+use crate::p2p::messages::AddrElement;
+use crate::types::NetAddr;
+
+// Addresses learned from addr messages or imported from a file, with the
+// newest timestamp seen for each. They can be exported as CSV or JSON so
+// crawl results can be shared between runs and machines, and imported
+// back from a plain list.
+#[derive(Debug, Clone, Default)]
+pub struct AddrBook {
+    addrs: HashMap<SocketAddr, AddrElement>,
+}
+
+impl AddrBook {
+    pub fn new() -> AddrBook {
+        AddrBook::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+
+    pub fn contains(&self, addr: &SocketAddr) -> bool {
+        self.addrs.contains_key(addr)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AddrElement> {
+        self.addrs.values()
+    }
+
+    // Returns whether the address is new to the book.
+    pub fn add(&mut self, element: AddrElement) -> bool {
+        match self.addrs.get_mut(&element.addr.addr) {
+            Some(known) => {
+                if element.timestamp > known.timestamp {
+                    *known = element;
+                }
+                false
+            }
+            None => {
+                self.addrs.insert(element.addr.addr, element);
+                true
+            }
+        }
+    }
+
+    // Reads one address per line, as ip:port, [ipv6]:port or a bare ip
+    // using `default_port`. Empty lines and lines starting with '#' are skipped.
+    // Only the first comma separated field is looked at, so CSV files
+    // written by write_csv() can be read back too, header and all.
+    // Imported addresses have no known services and a timestamp of 0.
+    // Returns how many of them were new.
+    pub fn import(&mut self, reader: impl BufRead, default_port: u16) -> io::Result<usize> {
+        let mut new = 0;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let field = line.split(',').next().unwrap_or_default().trim();
+            if field.is_empty() || field.starts_with('#') || (i == 0 && field == "address") {
+                continue;
+            }
+
+            // Host names would have to be looked up, so only IPs are taken.
+            let addr = match PeerAddr::parse(field, default_port) {
+                Ok(PeerAddr::Ip(addr)) => addr,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {}: invalid address \"{field}\"", i + 1),
+                    ))
+                }
+            };
+
+            let element = AddrElement {
+                timestamp: 0,
+                addr: NetAddr {
+                    services: Default::default(),
+                    addr,
+                },
+            };
+            if self.add(element) {
+                new += 1;
+            }
+        }
+
+        Ok(new)
+    }
+
+    // Writes `address,services,last_seen` rows, services as a hex bitfield
+    // and last_seen as a unix timestamp, newest first.
+    pub fn write_csv(&self, writer: impl Write) -> io::Result<()> {
+        self.write_csv_enriched(writer, &[])
+    }
+
+    // As write_csv(), with a column for every field of the enrichers,
+    // left empty where they don't know the address.
+    pub fn write_csv_enriched(
+        &self,
+        mut writer: impl Write,
+        enrichers: &[&dyn Enricher],
+    ) -> io::Result<()> {
+        let mut header = String::from("address,services,last_seen");
+        for field in enrich::fields(enrichers) {
+            header += &format!(",{}", enrich::csv_field(field));
+        }
+        writeln!(writer, "{header}")?;
+
+        for element in self.sorted() {
+            write!(
+                writer,
+                "{},{:016x},{}",
+                element.addr.addr,
+                element.addr.services.bits(),
+                element.timestamp
+            )?;
+            for value in enrich::values(enrichers, &element.addr.addr) {
+                write!(writer, ",{}", enrich::csv_field(&value.unwrap_or_default()))?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    // The same fields as write_csv(), as an array of objects.
+    pub fn write_json(&self, writer: impl Write) -> io::Result<()> {
+        self.write_json_enriched(writer, &[])
+    }
+
+    // Enriched fields are strings, or null where unknown.
+    pub fn write_json_enriched(
+        &self,
+        mut writer: impl Write,
+        enrichers: &[&dyn Enricher],
+    ) -> io::Result<()> {
+        let fields = enrich::fields(enrichers);
+        write!(writer, "[")?;
+        for (i, element) in self.sorted().into_iter().enumerate() {
+            if i > 0 {
+                write!(writer, ",")?;
+            }
+            write!(
+                writer,
+                "\n  {{\"address\": \"{}\", \"services\": \"{:016x}\", \"last_seen\": {}",
+                element.addr.addr,
+                element.addr.services.bits(),
+                element.timestamp
+            )?;
+            let values = enrich::values(enrichers, &element.addr.addr);
+            for (field, value) in fields.iter().zip(values) {
+                let value = match value {
+                    Some(value) => enrich::json_string(&value),
+                    None => String::from("null"),
+                };
+                write!(writer, ", {}: {value}", enrich::json_string(field))?;
+            }
+            write!(writer, "}}")?;
+        }
+        writeln!(writer, "\n]")
+    }
+
+    fn sorted(&self) -> Vec<&AddrElement> {
+        let mut ret: Vec<&AddrElement> = self.addrs.values().collect();
+        ret.sort_by(|a, b| {
+            b.timestamp
+                .cmp(&a.timestamp)
+                .then_with(|| a.addr.addr.cmp(&b.addr.addr))
+        });
+        ret
+    }
+}
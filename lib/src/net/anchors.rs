@@ -0,0 +1,57 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::codec::{BitcoinType, ByteReader};
+use crate::crypto::sha256d;
+use crate::types::{Magic, NetAddr};
+
+// Core keeps two block-relay-only peers as anchors.
+pub const MAX_ANCHORS: usize = 2;
+
+// Anchors are peers that worked last time, reconnected to first on the
+// next start so an attacker can't easily replace all of them across a
+// restart. The file holds the network magic, the addresses and a double
+// SHA256 of both, like Core's anchors.dat (though not its exact layout).
+pub fn write_anchors(path: impl AsRef<Path>, magic: Magic, anchors: &[NetAddr]) -> io::Result<()> {
+    let anchors = &anchors[..anchors.len().min(MAX_ANCHORS)];
+
+    let mut data = magic.to_blob();
+    data.extend(anchors.to_vec().to_blob());
+    data.extend(sha256d(&data).to_blob());
+
+    let path = path.as_ref();
+    let tmp = path.with_extension("dat.tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(tmp, path)
+}
+
+// Reads the anchors and deletes the file, as Core does, so that anchors
+// that somehow make startup fail aren't tried again and again. A missing
+// file means no anchors.
+pub fn read_anchors(path: impl AsRef<Path>, magic: Magic) -> io::Result<Vec<NetAddr>> {
+    let path = path.as_ref();
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    fs::remove_file(path)?;
+
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    if data.len() < 4 + 1 + 32 {
+        return Err(invalid("anchors file is truncated"));
+    }
+    let (body, checksum) = data.split_at(data.len() - 32);
+    if sha256d(body).0 != checksum {
+        return Err(invalid("anchors file checksum mismatch"));
+    }
+    if body[..4] != magic.0 {
+        return Err(invalid("anchors file is for another network"));
+    }
+
+    let mut anchors = Vec::<NetAddr>::from_blob(&mut ByteReader::new(body[4..].to_vec()))
+        .map_err(|e| invalid(&format!("anchors file doesn't decode: {e}")))?;
+    anchors.truncate(MAX_ANCHORS);
+    Ok(anchors)
+}
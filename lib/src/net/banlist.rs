@@ -0,0 +1,370 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::str::FromStr;
+
+// How long setban bans for when no time is given, a day as in Core.
+pub const DEFAULT_BAN_TIME: u64 = 24 * 60 * 60;
+
+// An IP address and how many of its leading bits are fixed, as in
+// 203.0.113.0/24. The address bits past the prefix are always zero, so
+// equal subnets compare equal however they were written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Subnet {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    // None if the prefix is longer than the address. IPv4 addresses
+    // mapped into IPv6 are taken as the IPv4 address they map.
+    pub fn new(ip: IpAddr, prefix: u8) -> Option<Subnet> {
+        let ip = ip.to_canonical();
+        let (bits, width) = to_bits(ip);
+        if prefix > width {
+            return None;
+        }
+        Some(Subnet {
+            network: from_bits(ip, bits & mask(width, prefix)),
+            prefix,
+        })
+    }
+
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if ip.is_ipv4() != self.network.is_ipv4() {
+            return false;
+        }
+        let (bits, width) = to_bits(ip);
+        bits & mask(width, self.prefix) == to_bits(self.network).0
+    }
+}
+
+impl From<IpAddr> for Subnet {
+    fn from(ip: IpAddr) -> Subnet {
+        let ip = ip.to_canonical();
+        Subnet::new(ip, to_bits(ip).1).unwrap()
+    }
+}
+
+// Accepts what Core's setban does: an address, an address and a prefix
+// length, or an address and a netmask like 255.255.255.0.
+impl FromStr for Subnet {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((ip, mask)) = s.split_once('/') else {
+            return Ok(IpAddr::from_str(s).map_err(|_| ())?.into());
+        };
+        let ip = IpAddr::from_str(ip).map_err(|_| ())?;
+        if let Ok(prefix) = mask.parse::<u8>() {
+            return Subnet::new(ip, prefix).ok_or(());
+        }
+
+        let netmask = IpAddr::from_str(mask).map_err(|_| ())?;
+        if netmask.is_ipv4() != ip.to_canonical().is_ipv4() {
+            return Err(());
+        }
+        let (bits, width) = to_bits(netmask);
+        let prefix = bits.count_ones() as u8;
+        // Core stopped accepting masks with holes in them.
+        if bits != self::mask(width, prefix) {
+            return Err(());
+        }
+        Subnet::new(ip, prefix).ok_or(())
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+fn to_bits(ip: IpAddr) -> (u128, u8) {
+    match ip {
+        IpAddr::V4(ip) => (u32::from(ip) as u128, 32),
+        IpAddr::V6(ip) => (u128::from(ip), 128),
+    }
+}
+
+// An address of the same kind as `like` with these bits.
+fn from_bits(like: IpAddr, bits: u128) -> IpAddr {
+    match like {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(bits as u32)),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(bits)),
+    }
+}
+
+fn mask(width: u8, prefix: u8) -> u128 {
+    if prefix == 0 {
+        return 0;
+    }
+    (!0u128 >> (128 - prefix as u32)) << (width - prefix)
+}
+
+// Unix times, as Core keeps them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BanEntry {
+    pub created: i64,
+    pub until: i64,
+}
+
+// Addresses and subnets not to connect to or accept connections from
+// until their bans run out, with the semantics of Core's setban: a
+// subnet can only be banned once at a time, and only what was banned
+// can be unbanned, not an address inside a banned subnet.
+#[derive(Debug, Clone, Default)]
+pub struct BanList {
+    bans: BTreeMap<Subnet, BanEntry>,
+}
+
+impl BanList {
+    pub fn new() -> BanList {
+        BanList::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bans.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Subnet, &BanEntry)> {
+        self.bans.iter()
+    }
+
+    // Bans `subnet` for `secs` from `now`. Returns false if it is banned
+    // already, as Core refuses to ban it again.
+    pub fn ban(&mut self, subnet: Subnet, secs: u64, now: i64) -> bool {
+        if self.bans.get(&subnet).is_some_and(|ban| ban.until > now) {
+            return false;
+        }
+        let until = now.saturating_add(secs.min(i64::MAX as u64) as i64);
+        self.bans.insert(
+            subnet,
+            BanEntry {
+                created: now,
+                until,
+            },
+        );
+        true
+    }
+
+    // Returns whether `subnet` was banned.
+    pub fn unban(&mut self, subnet: &Subnet) -> bool {
+        self.bans.remove(subnet).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.bans.clear();
+    }
+
+    // Adds the bans of `other` on subnets not banned here, keeping the
+    // bans in place as they are. Returns how many were added.
+    pub fn merge(&mut self, other: BanList) -> usize {
+        let before = self.bans.len();
+        for (subnet, ban) in other.bans {
+            self.bans.entry(subnet).or_insert(ban);
+        }
+        self.bans.len() - before
+    }
+
+    pub fn is_banned(&self, ip: IpAddr, now: i64) -> bool {
+        self.bans
+            .iter()
+            .any(|(subnet, ban)| ban.until > now && subnet.contains(ip))
+    }
+
+    // Forgets the bans that ran out by `now` and returns how many.
+    pub fn sweep(&mut self, now: i64) -> usize {
+        let before = self.bans.len();
+        self.bans.retain(|_, ban| ban.until > now);
+        before - self.bans.len()
+    }
+
+    // Reads `subnet,ban_created,banned_until` rows as written by
+    // write_csv(), header and all.
+    pub fn read_csv(reader: impl BufRead) -> io::Result<BanList> {
+        let mut bans = BanList::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.is_empty() || (i == 0 && line.starts_with("subnet,")) {
+                continue;
+            }
+
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: invalid ban \"{line}\"", i + 1),
+                )
+            };
+            let mut fields = line.split(',');
+            let (Some(subnet), Some(created), Some(until), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid());
+            };
+            let subnet = subnet.parse().map_err(|_| invalid())?;
+            let created = created.parse().map_err(|_| invalid())?;
+            let until = until.parse().map_err(|_| invalid())?;
+            bans.bans.insert(subnet, BanEntry { created, until });
+        }
+        Ok(bans)
+    }
+
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "subnet,ban_created,banned_until")?;
+        for (subnet, ban) in &self.bans {
+            writeln!(writer, "{subnet},{},{}", ban.created, ban.until)?;
+        }
+        Ok(())
+    }
+
+    // The layout of Core's banlist.json, so the bans can be handed to a
+    // node.
+    pub fn write_json(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "{{\"banned_nets\": [")?;
+        for (i, (subnet, ban)) in self.bans.iter().enumerate() {
+            if i > 0 {
+                write!(writer, ",")?;
+            }
+            write!(
+                writer,
+                "\n  {{\"version\": 1, \"ban_created\": {}, \"banned_until\": {}, \
+                 \"address\": \"{subnet}\"}}",
+                ban.created, ban.until
+            )?;
+        }
+        writeln!(writer, "\n]}}")
+    }
+
+    // Reads Core's banlist.json, or what write_json() wrote.
+    #[cfg(feature = "rpc")]
+    pub fn read_json(reader: impl io::Read) -> io::Result<BanList> {
+        let invalid = |error: String| io::Error::new(io::ErrorKind::InvalidData, error);
+        let json: serde_json::Value =
+            serde_json::from_reader(reader).map_err(|e| invalid(e.to_string()))?;
+        let Some(nets) = json["banned_nets"].as_array() else {
+            return Err(invalid("no banned_nets list".to_string()));
+        };
+
+        let mut bans = BanList::new();
+        for net in nets {
+            let subnet = net["address"].as_str().and_then(|s| s.parse().ok());
+            let created = net["ban_created"].as_i64();
+            let until = net["banned_until"].as_i64();
+            let (Some(subnet), Some(created), Some(until)) = (subnet, created, until) else {
+                return Err(invalid(format!("invalid ban {net}")));
+            };
+            bans.bans.insert(subnet, BanEntry { created, until });
+        }
+        Ok(bans)
+    }
+
+    // A missing file means no bans.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<BanList> {
+        match File::open(path) {
+            Ok(file) => BanList::read_csv(BufReader::new(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BanList::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Written to a temporary file first, so a crash can't leave half a
+    // ban list behind.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("csv.tmp");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        self.write_csv(&mut file)?;
+        file.flush()?;
+        drop(file);
+        fs::rename(tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_subnets_like_core() {
+        let subnet: Subnet = "203.0.113.7/255.255.255.0".parse().unwrap();
+        assert_eq!(subnet.to_string(), "203.0.113.0/24");
+        assert_eq!("203.0.113.99/24".parse(), Ok(subnet));
+        assert!(subnet.contains("203.0.113.200".parse().unwrap()));
+        assert!(subnet.contains("::ffff:203.0.113.1".parse().unwrap()));
+        assert!(!subnet.contains("203.0.114.1".parse().unwrap()));
+        assert!(!subnet.contains("2001:db8::1".parse().unwrap()));
+
+        assert_eq!(
+            "2001:db8::1".parse::<Subnet>().unwrap().to_string(),
+            "2001:db8::1/128"
+        );
+        assert_eq!("10.0.0.0/33".parse::<Subnet>(), Err(()));
+        assert_eq!("10.0.0.0/255.0.255.0".parse::<Subnet>(), Err(()));
+        assert_eq!("10.0.0.0/ffff::".parse::<Subnet>(), Err(()));
+    }
+
+    #[test]
+    fn bans_expire_and_round_trip() {
+        let mut bans = BanList::new();
+        let subnet: Subnet = "10.1.0.0/16".parse().unwrap();
+        assert!(bans.ban(subnet, 60, 1000));
+        assert!(!bans.ban(subnet, 60, 1030));
+        assert!(bans.ban("2001:db8::1".parse().unwrap(), 10, 1000));
+
+        let mut csv = vec![];
+        bans.write_csv(&mut csv).unwrap();
+        let mut bans = BanList::read_csv(csv.as_slice()).unwrap();
+        assert_eq!(bans.len(), 2);
+        assert!(bans.is_banned("10.1.2.3".parse().unwrap(), 1030));
+
+        assert_eq!(bans.sweep(1030), 1);
+        assert!(!bans.is_banned("10.1.2.3".parse().unwrap(), 1060));
+        assert!(bans.ban(subnet, 60, 1060));
+        assert!(!bans.unban(&"10.1.2.3".parse().unwrap()));
+        assert!(bans.unban(&subnet));
+        assert!(bans.is_empty());
+    }
+
+    #[cfg(feature = "rpc")]
+    #[test]
+    fn round_trips_core_json() {
+        let mut bans = BanList::new();
+        bans.ban("10.1.0.0/16".parse().unwrap(), 60, 1000);
+        bans.ban("2001:db8::1".parse().unwrap(), 10, 1000);
+        let mut json = vec![];
+        bans.write_json(&mut json).unwrap();
+        let read = BanList::read_json(json.as_slice()).unwrap();
+        assert_eq!(
+            read.iter().collect::<Vec<_>>(),
+            bans.iter().collect::<Vec<_>>()
+        );
+
+        // As Core 27 writes it.
+        let core = r#"{"banned_nets": [{"version": 1, "ban_created": 1700000000,
+            "banned_until": 1700086400, "address": "203.0.113.0/24"}]}"#;
+        let mut read = BanList::read_json(core.as_bytes()).unwrap();
+        assert!(read.is_banned("203.0.113.9".parse().unwrap(), 1700000001));
+        assert_eq!(read.merge(bans.clone()), 2);
+        assert_eq!(read.merge(bans), 0);
+        assert_eq!(read.len(), 3);
+
+        assert!(BanList::read_json(r#"{"banned_nets": [{"address": "x"}]}"#.as_bytes()).is_err());
+    }
+}
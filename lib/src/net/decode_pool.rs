@@ -0,0 +1,47 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::codec::DecodeError;
+use crate::p2p::messages::{BitcoinPayload, LazyPayload};
+
+// The receiving end is closed without a result if decoding panicked.
+pub type Decoding = Receiver<Result<BitcoinPayload, DecodeError>>;
+
+type Job = (LazyPayload, Sender<Result<BitcoinPayload, DecodeError>>);
+
+// Threads that decode payloads off the connections' own threads, so that
+// a connection can go on reading, and answering pings, while a large
+// payload is decoded. Clones share the threads, which exit once the last
+// clone is dropped.
+#[derive(Debug, Clone)]
+pub struct DecodePool {
+    jobs: Sender<Job>,
+}
+
+impl DecodePool {
+    pub fn new(threads: usize) -> DecodePool {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        for _ in 0..threads.max(1) {
+            let queue = queue.clone();
+            thread::spawn(move || loop {
+                let job = queue.lock().unwrap().recv();
+                let Ok((payload, result)) = job else {
+                    return;
+                };
+                let _ = result.send(payload.decode());
+            });
+        }
+
+        DecodePool { jobs }
+    }
+
+    pub fn decode(&self, payload: LazyPayload) -> Decoding {
+        let (result, decoding) = mpsc::channel();
+        // The threads only exit once every sender is gone.
+        self.jobs.send((payload, result)).unwrap();
+        decoding
+    }
+}
@@ -0,0 +1,57 @@
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use super::unix_time;
+use crate::crypto::sha256d;
+use crate::types::Command;
+
+// Keeps a frame whose payload did not decode, so that messages this
+// library gets wrong or doesn't support yet can be looked at offline.
+// The frame goes to <dir>/<time>-<command>-<hash>.bin exactly as
+// received, with a .txt next to it saying where it came from:
+//
+//     peer: 203.0.113.1:8333
+//     time: 1700000000
+//     command: version
+//     size: 126
+//     error: 3 trailing bytes after decoding 99 of 102
+//
+// The hash is of the frame, so the same frame is only kept once.
+// Returns the path of the .bin file.
+pub(super) fn dump_frame(
+    dir: &Path,
+    peer: Option<SocketAddr>,
+    command: &Command,
+    error: &str,
+    frame: &[u8],
+) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let time = unix_time();
+    // The command comes from the peer, so it is kept out of the path
+    // unless it is plain.
+    let name: String = command
+        .as_str()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    let hash = sha256d(frame).to_string();
+    let path = dir.join(format!("{time}-{name}-{}", &hash[..16]));
+
+    let peer = match peer {
+        Some(peer) => peer.to_string(),
+        None => String::from("unknown"),
+    };
+    let meta = format!(
+        "peer: {peer}\ntime: {time}\ncommand: {}\nsize: {}\nerror: {error}\n",
+        command.as_str().escape_default(),
+        frame.len()
+    );
+
+    let bin = path.with_extension("bin");
+    fs::write(&bin, frame)?;
+    fs::write(path.with_extension("txt"), meta)?;
+    Ok(bin)
+}
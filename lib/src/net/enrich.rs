@@ -0,0 +1,138 @@
+use std::net::SocketAddr;
+
+use crate::netgroup::{AddressType, NetGroup};
+
+// Adds fields to exported addresses, like the country from a GeoIP
+// database or the AS from an offline ASN table. The crate ships no such
+// database, implementations wrap whichever one is at hand.
+pub trait Enricher {
+    // The names of the added fields, used as CSV columns and JSON keys.
+    fn fields(&self) -> &[&str];
+
+    // One value per field, None where the address isn't known.
+    fn enrich(&self, addr: &SocketAddr) -> Vec<Option<String>>;
+}
+
+// The address type and netgroup, which need no database.
+pub struct NetGroups;
+
+impl Enricher for NetGroups {
+    fn fields(&self) -> &[&str] {
+        &["type", "group"]
+    }
+
+    fn enrich(&self, addr: &SocketAddr) -> Vec<Option<String>> {
+        vec![
+            Some(AddressType::of(&addr.ip()).to_string()),
+            Some(NetGroup::of(&addr.ip()).to_string()),
+        ]
+    }
+}
+
+// The fields of all enrichers, in order.
+pub(super) fn fields<'a>(enrichers: &'a [&dyn Enricher]) -> Vec<&'a str> {
+    enrichers
+        .iter()
+        .flat_map(|e| e.fields().iter().copied())
+        .collect()
+}
+
+// The values of all enrichers, as many as fields() returns. An enricher
+// that returns too few or too many values is padded or cut to fit.
+pub(super) fn values(enrichers: &[&dyn Enricher], addr: &SocketAddr) -> Vec<Option<String>> {
+    let mut ret = vec![];
+    for enricher in enrichers {
+        let mut values = enricher.enrich(addr);
+        values.resize(enricher.fields().len(), None);
+        ret.extend(values);
+    }
+    ret
+}
+
+// Quoted when it holds a comma, quote or line break, as in RFC 4180.
+pub(super) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub(super) fn json_string(value: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => ret += "\\\"",
+            '\\' => ret += "\\\\",
+            '\n' => ret += "\\n",
+            c if c.is_control() => ret += &format!("\\u{:04x}", c as u32),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::AddrBook;
+    use crate::p2p::messages::AddrElement;
+    use crate::types::NetAddr;
+
+    struct Asn;
+
+    impl Enricher for Asn {
+        fn fields(&self) -> &[&str] {
+            &["asn", "as_name"]
+        }
+
+        fn enrich(&self, addr: &SocketAddr) -> Vec<Option<String>> {
+            match addr.ip().to_string().as_str() {
+                "1.2.3.4" => vec![Some("64500".into()), Some("Example, \"Inc\"".into())],
+                _ => vec![],
+            }
+        }
+    }
+
+    fn book() -> AddrBook {
+        let mut book = AddrBook::new();
+        for (timestamp, addr) in [(2, "1.2.3.4:8333"), (1, "5.6.7.8:8333")] {
+            book.add(AddrElement {
+                timestamp,
+                addr: NetAddr {
+                    services: Default::default(),
+                    addr: addr.parse().unwrap(),
+                },
+            });
+        }
+        book
+    }
+
+    #[test]
+    fn enriches_csv() {
+        let mut csv = vec![];
+        book().write_csv_enriched(&mut csv, &[&Asn]).unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "address,services,last_seen,asn,as_name\n\
+             1.2.3.4:8333,0000000000000000,2,64500,\"Example, \"\"Inc\"\"\"\n\
+             5.6.7.8:8333,0000000000000000,1,,\n"
+        );
+    }
+
+    #[test]
+    fn enriches_json() {
+        let mut json = vec![];
+        book()
+            .write_json_enriched(&mut json, &[&Asn, &NetGroups])
+            .unwrap();
+
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains(
+            "\"asn\": \"64500\", \"as_name\": \"Example, \\\"Inc\\\"\", \"type\": \"ipv4\""
+        ));
+        assert!(json.contains("\"last_seen\": 1, \"asn\": null, \"as_name\": null"));
+    }
+}
@@ -0,0 +1,21 @@
+use std::net::SocketAddr;
+
+use crate::p2p::messages::{Addr, BitcoinMsg, Inv, Version};
+
+#[derive(Debug, Clone)]
+pub enum DisconnectReason {
+    Local,
+    ClosedByPeer,
+    Error(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    PeerConnected { addr: SocketAddr, version: Version },
+    InvReceived(Inv),
+    AddrReceived(Addr),
+    PingReceived(u64),
+    PongReceived(u64),
+    MessageReceived(BitcoinMsg),
+    Disconnected(DisconnectReason),
+}
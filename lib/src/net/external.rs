@@ -0,0 +1,64 @@
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::netgroup::AddressType;
+
+const MAX_SAMPLES: usize = 200;
+// A single peer could report anything, so at least this many have to
+// agree on an address before it is believed.
+const MIN_VOTES: usize = 2;
+
+// Our address as peers see it, from the `remote` field of their version
+// messages. Only the IP is of interest: the port they report is the one
+// our outbound connection happened to use, not one we listen on.
+#[derive(Debug, Clone, Default)]
+pub struct ExternalAddr {
+    samples: VecDeque<(IpAddr, IpAddr)>,
+}
+
+impl ExternalAddr {
+    pub fn new() -> ExternalAddr {
+        ExternalAddr::default()
+    }
+
+    // As with AdjustedTime, one sample is kept per peer address. Reports
+    // of addresses that can't be reached from the internet are dropped,
+    // since many peers fill in a placeholder.
+    pub fn add_sample(&mut self, peer: IpAddr, reported: SocketAddr) {
+        self.samples.retain(|(sample_peer, _)| *sample_peer != peer);
+        if !AddressType::of(&reported.ip()).is_routable() {
+            return;
+        }
+
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((peer, reported.ip()));
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    // The most reported address and how many peers reported it.
+    pub fn best(&self) -> Option<(IpAddr, usize)> {
+        let mut votes: Vec<(IpAddr, usize)> = vec![];
+        for (_, ip) in &self.samples {
+            match votes.iter_mut().find(|(voted, _)| voted == ip) {
+                Some((_, count)) => *count += 1,
+                None => votes.push((*ip, 1)),
+            }
+        }
+
+        // Ties go to the address that was reported first.
+        votes.into_iter().rev().max_by_key(|(_, count)| *count)
+    }
+
+    // The address a majority of the peers agree on, if enough of them do.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self.best() {
+            Some((ip, count)) if count >= MIN_VOTES && count * 2 > self.samples.len() => Some(ip),
+            _ => None,
+        }
+    }
+}
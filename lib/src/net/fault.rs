@@ -0,0 +1,88 @@
+use std::mem;
+use std::time::{Duration, Instant};
+
+use crate::p2p::messages::HEADER_SIZE;
+use crate::types::Command;
+
+// Ways to mangle outbound messages, to see how peers cope with them.
+// They act on the encoded message, so they apply however it was sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    CorruptChecksum,
+    // Cuts the payload down to this many bytes. The header still has the
+    // full length, so the peer is left waiting for the rest.
+    Truncate(usize),
+    // Holds the message back this long. It goes out with the next message
+    // sent or read after that, so how late it really is depends on the
+    // read timeout.
+    Delay(Duration),
+    // Holds the message back until the next one has been sent.
+    Reorder,
+}
+
+struct Held {
+    command: Command,
+    blob: Vec<u8>,
+    // None for messages waiting to go out after the next one.
+    until: Option<Instant>,
+}
+
+#[derive(Default)]
+pub(super) struct Faults {
+    rules: Vec<(Command, Fault)>,
+    held: Vec<Held>,
+}
+
+impl Faults {
+    pub(super) fn add(&mut self, command: Command, fault: Fault) {
+        self.rules.push((command, fault));
+    }
+
+    pub(super) fn clear(&mut self, command: &Command) {
+        self.rules.retain(|(c, _)| c != command);
+    }
+
+    // Applies the faults for `command` to an encoded message. Returns the
+    // message to send now, or None if it is held back.
+    pub(super) fn apply(&mut self, command: &Command, mut blob: Vec<u8>) -> Option<Vec<u8>> {
+        let mut hold = None;
+        for (_, fault) in self.rules.iter().filter(|(c, _)| c == command) {
+            match *fault {
+                Fault::CorruptChecksum => blob[20..HEADER_SIZE].iter_mut().for_each(|b| *b ^= 0xff),
+                Fault::Truncate(len) => blob.truncate(HEADER_SIZE + len),
+                Fault::Delay(delay) => hold = Some(Some(Instant::now() + delay)),
+                Fault::Reorder => hold = Some(None),
+            }
+        }
+
+        match hold {
+            Some(until) => {
+                self.held.push(Held {
+                    command: command.clone(),
+                    blob,
+                    until,
+                });
+                None
+            }
+            None => Some(blob),
+        }
+    }
+
+    // Takes the held messages that are due, in the order they were held.
+    // `after_send` releases the ones waiting for another message to go out.
+    pub(super) fn take_due(&mut self, after_send: bool) -> Vec<(Command, Vec<u8>)> {
+        if self.held.is_empty() {
+            return vec![];
+        }
+
+        let now = Instant::now();
+        let (due, held) = mem::take(&mut self.held)
+            .into_iter()
+            .partition(|held| held.until.map_or(after_send, |until| until <= now));
+        self.held = held;
+
+        due.into_iter()
+            .map(|held: Held| (held.command, held.blob))
+            .collect()
+    }
+}
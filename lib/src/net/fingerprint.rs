@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::p2p::messages::Version;
+use crate::types::Command;
+
+// The software a peer most likely runs, going by its user agent (BIP14),
+// e.g. "/Satoshi:27.0.0/" or "/btcwire:0.5.0/btcd:0.24.2/".
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Implementation {
+    Core(String),
+    Knots(String),
+    Btcd(String),
+    Libbitcoin(String),
+    Bitcoinj(String),
+    // Nodes of chains that split off from Bitcoin, such as Bitcoin Cash,
+    // that still turn up in addr messages.
+    Fork { name: String, version: String },
+    Other { name: String, version: String },
+    Unknown,
+}
+
+impl Implementation {
+    pub fn from_user_agent(user_agent: &str) -> Implementation {
+        // Each part is name:version, maybe followed by (comments).
+        let parts: Vec<(&str, &str, &str)> = user_agent
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let (part, comments) = part.split_once('(').unwrap_or((part, ""));
+                let (name, version) = part.split_once(':').unwrap_or((part, ""));
+                (name, version, comments)
+            })
+            .collect();
+        let version_of = |wanted: &str| {
+            parts
+                .iter()
+                .find(|(name, _, _)| name.eq_ignore_ascii_case(wanted))
+                .map(|(_, version, _)| version.to_string())
+        };
+
+        let knots = parts.iter().any(|(name, _, comments)| {
+            name.eq_ignore_ascii_case("knots") || comments.to_lowercase().contains("knots")
+        });
+        if let Some(version) = version_of("Satoshi") {
+            return match knots {
+                true => Implementation::Knots(version),
+                false => Implementation::Core(version),
+            };
+        }
+        if let Some(version) = version_of("btcd") {
+            return Implementation::Btcd(version);
+        }
+        if let Some(version) = version_of("libbitcoin") {
+            return Implementation::Libbitcoin(version);
+        }
+        if let Some(version) = version_of("bitcoinj") {
+            return Implementation::Bitcoinj(version);
+        }
+
+        match parts.first() {
+            None => Implementation::Unknown,
+            Some((name, version, _)) => {
+                let (name, version) = (name.to_string(), version.to_string());
+                if FORKS.iter().any(|fork| name.eq_ignore_ascii_case(fork)) {
+                    Implementation::Fork { name, version }
+                } else {
+                    Implementation::Other { name, version }
+                }
+            }
+        }
+    }
+
+    // The version down to the release series, "27" for Core 27.1.0 and
+    // "0.21" for 0.21.2, for counting peers by release.
+    pub fn series(&self) -> Implementation {
+        let series = |version: &str| {
+            let mut parts = version.split('.');
+            match (parts.next(), parts.next()) {
+                (Some("0"), Some(minor)) => format!("0.{minor}"),
+                (Some(major), _) => major.to_string(),
+                (None, _) => String::new(),
+            }
+        };
+
+        match self {
+            Implementation::Core(v) => Implementation::Core(series(v)),
+            Implementation::Knots(v) => Implementation::Knots(series(v)),
+            Implementation::Btcd(v) => Implementation::Btcd(series(v)),
+            Implementation::Libbitcoin(v) => Implementation::Libbitcoin(series(v)),
+            Implementation::Bitcoinj(v) => Implementation::Bitcoinj(series(v)),
+            Implementation::Fork { name, version } => Implementation::Fork {
+                name: name.clone(),
+                version: series(version),
+            },
+            Implementation::Other { name, version } => Implementation::Other {
+                name: name.clone(),
+                version: series(version),
+            },
+            Implementation::Unknown => Implementation::Unknown,
+        }
+    }
+
+    // The release of Core or Knots, as (major, minor) where 0.21 is
+    // (0, 21) and 27.1 is (27, 1).
+    fn core_release(&self) -> Option<(u32, u32)> {
+        let (Implementation::Core(version) | Implementation::Knots(version)) = self else {
+            return None;
+        };
+        let mut parts = version.split('.').map(|part| part.parse::<u32>().ok());
+        Some((parts.next()??, parts.next().flatten().unwrap_or(0)))
+    }
+}
+
+impl fmt::Display for Implementation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, version) = match self {
+            Implementation::Core(v) => ("Core", v.as_str()),
+            Implementation::Knots(v) => ("Knots", v.as_str()),
+            Implementation::Btcd(v) => ("btcd", v.as_str()),
+            Implementation::Libbitcoin(v) => ("libbitcoin", v.as_str()),
+            Implementation::Bitcoinj(v) => ("bitcoinj", v.as_str()),
+            Implementation::Fork { name, version } | Implementation::Other { name, version } => {
+                (name.as_str(), version.as_str())
+            }
+            Implementation::Unknown => return write!(f, "unknown"),
+        };
+        match version {
+            "" => write!(f, "{name}"),
+            _ => write!(f, "{name} {version}"),
+        }
+    }
+}
+
+const FORKS: [&str; 5] = [
+    "Bitcoin ABC",
+    "Bitcoin Cash Node",
+    "Bitcoin SV",
+    "Bitcoin Unlimited",
+    "Bitcoin Gold",
+];
+
+// What a peer's handshake says about the software it runs. The user
+// agent is only a claim, so other signs that disagree with it are kept
+// in `doubts`: peers that merely pose as Core, like many crawlers and
+// spy nodes, tend to get details of its handshake wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub implementation: Implementation,
+    pub doubts: Vec<String>,
+}
+
+impl Fingerprint {
+    // `handshake` are the commands the peer sent between its version and
+    // verack, in order, and `local_proto_ver` the protocol version we
+    // sent it.
+    pub fn of(version: &Version, handshake: &[Command], local_proto_ver: u32) -> Fingerprint {
+        let implementation = Implementation::from_user_agent(&version.user_agent);
+        let mut doubts = vec![];
+
+        if let Some(release) = implementation.core_release() {
+            let sent = |command: &str| handshake.iter().any(|c| c.as_str() == command);
+
+            // Core moved to protocol 70016 in 0.21, and since then
+            // negotiates wtxid relay and addrv2 before verack, though only
+            // with peers that speak 70016 too.
+            if release >= (0, 21) {
+                if version.proto_ver != 70016 {
+                    doubts.push(format!("protocol {} instead of 70016", version.proto_ver));
+                }
+                if version.proto_ver.min(local_proto_ver) >= 70016 {
+                    for command in ["wtxidrelay", "sendaddrv2"] {
+                        if !sent(command) {
+                            doubts.push(format!("no {command} before verack"));
+                        }
+                    }
+                }
+            }
+            // Every release since 0.13.1 has segwit, pruned or not.
+            if release >= (0, 14) && !version.services.witness {
+                doubts.push("no witness service".to_string());
+            }
+        }
+
+        Fingerprint {
+            implementation,
+            doubts,
+        }
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.implementation)?;
+        if !self.doubts.is_empty() {
+            write!(f, " (doubtful: {})", self.doubts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// How many peers run each release series, most common first.
+pub fn implementation_counts<'a>(
+    fingerprints: impl IntoIterator<Item = &'a Fingerprint>,
+) -> Vec<(Implementation, usize)> {
+    let mut counts: HashMap<Implementation, usize> = HashMap::new();
+    for fingerprint in fingerprints {
+        *counts
+            .entry(fingerprint.implementation.series())
+            .or_default() += 1;
+    }
+
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|(a, a_count), (b, b_count)| {
+        b_count
+            .cmp(a_count)
+            .then_with(|| a.to_string().cmp(&b.to_string()))
+    });
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::builder::PROTOCOL_VERSION;
+    use crate::types::Services;
+
+    fn version(user_agent: &str, proto_ver: u32, witness: bool) -> Version {
+        let mut version = Version::builder().build().unwrap();
+        version.user_agent = user_agent.to_string();
+        version.proto_ver = proto_ver;
+        version.services = Services {
+            network: true,
+            witness,
+            ..Default::default()
+        };
+        version
+    }
+
+    #[test]
+    fn reads_user_agents() {
+        let of = Implementation::from_user_agent;
+
+        assert_eq!(
+            of("/Satoshi:27.1.0/"),
+            Implementation::Core("27.1.0".into())
+        );
+        assert_eq!(
+            of("/Satoshi:26.1.0/Knots:20240513/"),
+            Implementation::Knots("26.1.0".into())
+        );
+        assert_eq!(
+            of("/Satoshi:25.1.0(knots)/"),
+            Implementation::Knots("25.1.0".into())
+        );
+        assert_eq!(
+            of("/btcwire:0.5.0/btcd:0.24.2/"),
+            Implementation::Btcd("0.24.2".into())
+        );
+        assert_eq!(
+            of("/Bitcoin ABC:0.29.2(EB32.0)/"),
+            Implementation::Fork {
+                name: "Bitcoin ABC".into(),
+                version: "0.29.2".into()
+            }
+        );
+        assert_eq!(of(""), Implementation::Unknown);
+    }
+
+    #[test]
+    fn doubts_poor_imitations_of_core() {
+        let handshake = [Command::from("wtxidrelay"), Command::from("sendaddrv2")];
+
+        let core = version("/Satoshi:27.0.0/", 70016, true);
+        let fingerprint = Fingerprint::of(&core, &handshake, 70016);
+        assert!(fingerprint.doubts.is_empty(), "{fingerprint}");
+
+        // Core sends neither to a peer that only speaks 70014, as we do.
+        let fingerprint = Fingerprint::of(&core, &[], PROTOCOL_VERSION);
+        assert!(fingerprint.doubts.is_empty(), "{fingerprint}");
+        let fingerprint = Fingerprint::of(&core, &[], 70016);
+        assert_eq!(fingerprint.doubts.len(), 2, "{fingerprint}");
+
+        let poser = version("/Satoshi:27.0.0/", 70015, false);
+        let fingerprint = Fingerprint::of(&poser, &[], 70016);
+        assert_eq!(fingerprint.doubts.len(), 2, "{fingerprint}");
+
+        let old = Fingerprint::of(&version("/Satoshi:0.20.1/", 70015, true), &[], 70016);
+        assert!(old.doubts.is_empty(), "{old}");
+    }
+
+    #[test]
+    fn counts_release_series() {
+        let fingerprints: Vec<_> = ["/Satoshi:27.0.0/", "/Satoshi:27.1.0/", "/btcd:0.24.2/"]
+            .into_iter()
+            .map(|user_agent| {
+                Fingerprint::of(&version(user_agent, 70016, true), &[], PROTOCOL_VERSION)
+            })
+            .collect();
+
+        assert_eq!(
+            implementation_counts(&fingerprints),
+            [
+                (Implementation::Core("27".into()), 2),
+                (Implementation::Btcd("0.24".into()), 1)
+            ]
+        );
+    }
+}
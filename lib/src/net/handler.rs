@@ -0,0 +1,15 @@
+use super::{Connection, Result};
+use crate::p2p::messages::BitcoinMsg;
+
+// What a message handler wants done with the message afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handled {
+    // Carry on with the remaining handlers and the built-in handling.
+    Continue,
+    // Stop here: no further handlers, no built-in handling (such as the
+    // pong answering a ping) and no event for subscribers.
+    Consumed,
+}
+
+pub type MessageHandler<T> =
+    Box<dyn FnMut(&mut Connection<T>, &BitcoinMsg) -> Result<Handled> + Send>;
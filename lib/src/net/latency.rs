@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use super::{Connection, Transport};
+
+// Upper bounds of the histogram buckets, in milliseconds. Anything slower
+// than the last one goes in an extra overflow bucket.
+pub const LATENCY_BUCKETS_MS: [u64; 8] = [10, 25, 50, 100, 250, 500, 1000, 2500];
+
+// Response times of one peer: the version exchange and every answered
+// ping.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    count: u64,
+    total: Duration,
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> LatencyHistogram {
+        LatencyHistogram::default()
+    }
+
+    pub fn add(&mut self, latency: Duration) {
+        let ms = latency.as_millis();
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound as u128)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total += latency;
+        self.min = Some(self.min.map_or(latency, |min| min.min(latency)));
+        self.max = Some(self.max.map_or(latency, |max| max.max(latency)));
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count {
+            0 => None,
+            count => Some(self.total.div_f64(count as f64)),
+        }
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    // Pairs of bucket upper bound in milliseconds, None for the overflow
+    // bucket, and number of samples in it.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<u64>, u64)> + '_ {
+        LATENCY_BUCKETS_MS
+            .iter()
+            .map(|bound| Some(*bound))
+            .chain([None])
+            .zip(self.buckets.iter().copied())
+    }
+}
+
+// Longer than this, uptime stops counting towards the quality score.
+const MAX_SCORED_UPTIME: Duration = Duration::from_secs(24 * 60 * 60);
+// Assumed for peers that have never answered anything.
+const UNKNOWN_LATENCY: Duration = Duration::from_secs(1);
+
+// A score for ranking peers, higher is better: fast responses and a long
+// time connected, uptime being capped at a day so that a fast new peer
+// still beats a slow old one. Only meaningful compared between peers.
+pub fn quality_score(latency: &LatencyHistogram, uptime: Duration) -> f64 {
+    let latency_ms = latency.mean().unwrap_or(UNKNOWN_LATENCY).as_secs_f64() * 1000.0;
+    let uptime_hours = uptime.min(MAX_SCORED_UPTIME).as_secs_f64() / 3600.0;
+
+    (1.0 + uptime_hours) / (1.0 + latency_ms / 100.0)
+}
+
+// Peers from best to worst quality score, for picking whom to ask first.
+pub fn rank_by_quality<'a, T: Transport + 'a>(
+    peers: impl IntoIterator<Item = &'a Connection<T>>,
+) -> Vec<&'a Connection<T>> {
+    let mut ranked: Vec<(f64, &Connection<T>)> = peers
+        .into_iter()
+        .map(|conn| (conn.quality_score(), conn))
+        .collect();
+    ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    ranked.into_iter().map(|(_, conn)| conn).collect()
+}
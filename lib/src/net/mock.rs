@@ -0,0 +1,784 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::Transport;
+use crate::codec::ByteReader;
+use crate::p2p::messages::{BitcoinMsg, HEADER_SIZE};
+use crate::types::Magic;
+
+#[derive(Debug)]
+struct State {
+    incoming: VecDeque<u8>,
+    outgoing: Vec<u8>,
+    write_capacity: Option<usize>,
+    closed: bool,
+    peer_addr: SocketAddr,
+}
+
+// An in-memory transport for tests. Incoming data is scripted up front
+// and everything written is captured. Clones share the same state, so a
+// test keeps one clone while the Connection owns another.
+//
+// Reads never block: with nothing queued they fail with WouldBlock, as
+// a socket with a read timeout would, or return end of file once
+// close() has been called. Writes take everything unless limited with
+// set_write_capacity().
+#[derive(Debug, Clone)]
+pub struct MockTransport {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockTransport {
+    pub fn new() -> MockTransport {
+        MockTransport::with_peer_addr(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8333))
+    }
+
+    pub fn with_peer_addr(peer_addr: SocketAddr) -> MockTransport {
+        MockTransport {
+            state: Arc::new(Mutex::new(State {
+                incoming: VecDeque::new(),
+                outgoing: vec![],
+                write_capacity: None,
+                closed: false,
+                peer_addr,
+            })),
+        }
+    }
+
+    pub fn push_bytes(&self, bytes: &[u8]) {
+        self.state.lock().unwrap().incoming.extend(bytes);
+    }
+
+    pub fn push_msg(&self, msg: &BitcoinMsg, magic: Magic) {
+        self.push_bytes(&msg.encode(magic));
+    }
+
+    // Lets writes take only `capacity` more bytes, failing with WouldBlock
+    // after that like a non-blocking socket whose peer stopped reading.
+    // None takes the limit away.
+    pub fn set_write_capacity(&self, capacity: Option<usize>) {
+        self.state.lock().unwrap().write_capacity = capacity;
+    }
+
+    // Makes reads return end of file once the queued data is used up.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+    }
+
+    pub fn take_sent_bytes(&self) -> Vec<u8> {
+        std::mem::take(&mut self.state.lock().unwrap().outgoing)
+    }
+
+    // Decodes the complete messages written so far. Magic and checksums
+    // are not checked; a trailing partial message is left in place.
+    pub fn take_sent(&self) -> Vec<BitcoinMsg> {
+        let mut state = self.state.lock().unwrap();
+
+        let mut ret = vec![];
+        let mut pos = 0;
+        while let Some(header) = state.outgoing.get(pos..pos + HEADER_SIZE) {
+            let size = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+            let Some(msg) = state.outgoing.get(pos..pos + HEADER_SIZE + size) else {
+                break;
+            };
+            ret.push(BitcoinMsg::decode_unchecked(&mut ByteReader::new(
+                msg.to_vec(),
+            )));
+            pos += HEADER_SIZE + size;
+        }
+        state.outgoing.drain(..pos);
+
+        ret
+    }
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        MockTransport::new()
+    }
+}
+
+impl Read for MockTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.incoming.is_empty() {
+            return if state.closed {
+                Ok(0)
+            } else {
+                Err(io::ErrorKind::WouldBlock.into())
+            };
+        }
+
+        let len = buf.len().min(state.incoming.len());
+        for (dst, src) in buf.iter_mut().zip(state.incoming.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for MockTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        let len = match &mut state.write_capacity {
+            Some(0) => return Err(io::ErrorKind::WouldBlock.into()),
+            Some(capacity) => {
+                let len = buf.len().min(*capacity);
+                *capacity -= len;
+                len
+            }
+            None => buf.len(),
+        };
+        state.outgoing.extend(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MockTransport {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.state.lock().unwrap().peer_addr)
+    }
+
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    use super::*;
+    use crate::codec::BitcoinType;
+    use crate::crypto::sha256d;
+    use crate::net::{Connection, DecodePool, DisconnectReason, ErrorKind, Event};
+    use crate::p2p::messages::{
+        Addr, AddrElement, BitcoinPayload, BlockHeader, Inv, NotFound, Version,
+    };
+    use crate::types::{Hash, InventoryElement, InventoryKind, NetAddr};
+
+    fn peer_version() -> BitcoinMsg {
+        BitcoinMsg {
+            payload: BitcoinPayload::Version(
+                Version::builder().user_agent("/mock:0.1/").build().unwrap(),
+            ),
+        }
+    }
+
+    fn connected() -> (Connection<MockTransport>, MockTransport) {
+        let transport = MockTransport::new();
+        transport.push_msg(&peer_version(), Magic::MAINNET);
+        transport.push_msg(&BitcoinMsg::verack(), Magic::MAINNET);
+
+        let mut conn = Connection::new(transport.clone());
+        conn.handshake().unwrap();
+        transport.take_sent();
+
+        (conn, transport)
+    }
+
+    #[test]
+    fn handshake() {
+        let transport = MockTransport::new();
+        transport.push_msg(&peer_version(), Magic::MAINNET);
+        transport.push_msg(
+            &BitcoinMsg {
+                payload: BitcoinPayload::Unknown {
+                    command: "sendaddrv2".into(),
+                    payload: vec![],
+                },
+            },
+            Magic::MAINNET,
+        );
+        transport.push_msg(&BitcoinMsg::verack(), Magic::MAINNET);
+
+        let mut conn = Connection::new(transport.clone());
+        let events = conn.subscribe();
+        conn.handshake().unwrap();
+
+        let sent = transport.take_sent();
+        assert!(matches!(sent[0].payload, BitcoinPayload::Version(_)));
+        assert!(matches!(sent[1].payload, BitcoinPayload::VerAck));
+        assert_eq!(sent.len(), 2);
+
+        assert_eq!(conn.peer_version().unwrap().user_agent, "/mock:0.1/");
+        assert!(matches!(events.try_recv(), Ok(Event::PeerConnected { .. })));
+    }
+
+    #[test]
+    fn handshake_needs_version_first() {
+        let transport = MockTransport::new();
+        transport.push_msg(&BitcoinMsg::verack(), Magic::MAINNET);
+
+        assert!(Connection::new(transport).handshake().is_err());
+    }
+
+    #[test]
+    fn handshake_times_out() {
+        let transport = MockTransport::new();
+        transport.push_msg(&peer_version(), Magic::MAINNET);
+
+        let mut conn = Connection::new(transport);
+        conn.set_handshake_timeout(Duration::ZERO);
+        let err = conn.handshake().unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Timeout), "{err:?}");
+        assert!(conn.peer_version().is_none());
+    }
+
+    #[test]
+    fn seeded_rng_repeats_version_nonce() {
+        let nonce = || {
+            let transport = MockTransport::new();
+            transport.push_msg(&peer_version(), Magic::MAINNET);
+            transport.push_msg(&BitcoinMsg::verack(), Magic::MAINNET);
+
+            let mut conn = Connection::new(transport.clone());
+            conn.set_rng(StdRng::seed_from_u64(1));
+            conn.handshake().unwrap();
+
+            match &transport.take_sent()[0].payload {
+                BitcoinPayload::Version(version) => version.nonce,
+                payload => panic!("expected a version, got {payload:?}"),
+            }
+        };
+
+        assert_eq!(nonce(), nonce());
+    }
+
+    #[test]
+    fn rejects_own_nonce() {
+        let transport = MockTransport::new();
+        let mut conn = Connection::new(transport.clone());
+        conn.set_rng(StdRng::seed_from_u64(1));
+        let nonce = StdRng::seed_from_u64(1).next_u64();
+
+        let mut version = peer_version();
+        if let BitcoinPayload::Version(version) = &mut version.payload {
+            version.nonce = nonce;
+        }
+        transport.push_msg(&version, Magic::MAINNET);
+        transport.push_msg(&BitcoinMsg::verack(), Magic::MAINNET);
+
+        assert!(conn.handshake().is_err());
+        assert_eq!(conn.local_nonce(), Some(nonce));
+    }
+
+    #[test]
+    fn answers_ping() {
+        let (mut conn, transport) = connected();
+        transport.push_msg(&BitcoinMsg::ping(7), Magic::MAINNET);
+        conn.process_next_msg().unwrap();
+
+        let sent = transport.take_sent();
+        assert!(matches!(
+            sent[..],
+            [BitcoinMsg {
+                payload: BitcoinPayload::Pong(7)
+            }]
+        ));
+    }
+
+    #[test]
+    fn rejects_wrong_magic() {
+        let (mut conn, transport) = connected();
+        let events = conn.subscribe();
+        transport.push_msg(&BitcoinMsg::ping(7), Magic::TESTNET);
+        transport.push_msg(&BitcoinMsg::ping(8), Magic::MAINNET);
+
+        let error = conn.process_next_msg().unwrap_err();
+        assert!(error.msg.unwrap().contains("Unexpected network magic"));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(Event::Disconnected(DisconnectReason::Error(_)))
+        ));
+        let error = conn.process_next_msg().unwrap_err();
+        assert!(matches!(
+            error.kind,
+            ErrorKind::IoErr(e) if e.kind() == io::ErrorKind::NotConnected
+        ));
+        assert!(events.try_recv().is_err());
+        assert!(transport.take_sent().is_empty());
+    }
+
+    #[test]
+    fn rejects_trailing_payload_bytes() {
+        let (mut conn, transport) = connected();
+        let mut payload = 7u64.to_blob();
+        payload.extend([0xde, 0xad]);
+        transport.push_msg(
+            &BitcoinMsg {
+                payload: BitcoinPayload::Unknown {
+                    command: "ping".into(),
+                    payload,
+                },
+            },
+            Magic::MAINNET,
+        );
+        transport.push_msg(&BitcoinMsg::ping(8), Magic::MAINNET);
+
+        let error = conn.process_next_msg().unwrap_err();
+        assert!(error.msg.unwrap().contains("2 trailing bytes"));
+        conn.process_next_msg().unwrap();
+
+        let sent = transport.take_sent();
+        assert!(matches!(
+            sent[..],
+            [BitcoinMsg {
+                payload: BitcoinPayload::Pong(8)
+            }]
+        ));
+    }
+
+    #[test]
+    fn checks_headers_before_frames_arrive() {
+        let (mut conn, transport) = connected();
+        let ping = BitcoinMsg::ping(7).encode(Magic::MAINNET);
+        transport.push_bytes(&ping[..HEADER_SIZE + 3]);
+        assert!(conn.process_next_msg().is_err());
+        transport.push_bytes(&ping[HEADER_SIZE + 3..]);
+        conn.process_next_msg().unwrap();
+        assert!(matches!(
+            transport.take_sent()[..],
+            [BitcoinMsg {
+                payload: BitcoinPayload::Pong(7)
+            }]
+        ));
+
+        // Refused from the header alone, without waiting for 4 MB.
+        let mut header = ping[..HEADER_SIZE].to_vec();
+        header[16..20].copy_from_slice(&4_000_001u32.to_le_bytes());
+        let events = conn.subscribe();
+        transport.push_bytes(&header);
+        let error = conn.process_next_msg().unwrap_err();
+        assert!(error.msg.unwrap().contains("at most 4000000"));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(Event::Disconnected(DisconnectReason::Error(_)))
+        ));
+        let error = conn.process_next_msg().unwrap_err();
+        assert!(error.msg.is_none());
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn rejects_short_payloads() {
+        let (mut conn, transport) = connected();
+        // An inv claiming far more entries than it has, a ping cut short
+        // and a version that stops in its user agent.
+        let mut inv = usize::MAX.to_blob();
+        inv.extend(1u32.to_blob());
+        inv.extend([0xab; 32]);
+        let mut version = peer_version().encode(Magic::MAINNET)[HEADER_SIZE..].to_vec();
+        version.truncate(85);
+        for (command, payload) in [("inv", inv), ("ping", vec![0; 5]), ("version", version)] {
+            transport.push_msg(
+                &BitcoinMsg {
+                    payload: BitcoinPayload::Unknown {
+                        command: command.into(),
+                        payload,
+                    },
+                },
+                Magic::MAINNET,
+            );
+        }
+        transport.push_msg(&BitcoinMsg::ping(8), Magic::MAINNET);
+
+        for _ in 0..3 {
+            let error = conn.process_next_msg().unwrap_err();
+            assert!(error.msg.unwrap().contains("unexpected end"));
+        }
+        conn.process_next_msg().unwrap();
+
+        let sent = transport.take_sent();
+        assert!(matches!(
+            sent[..],
+            [BitcoinMsg {
+                payload: BitcoinPayload::Pong(8)
+            }]
+        ));
+    }
+
+    #[test]
+    fn reads_payloads_lazily() {
+        let (mut conn, transport) = connected();
+        let mut payload = 7u64.to_blob();
+        payload.push(0);
+        transport.push_msg(
+            &BitcoinMsg {
+                payload: BitcoinPayload::Unknown {
+                    command: "ping".into(),
+                    payload: payload.clone(),
+                },
+            },
+            Magic::MAINNET,
+        );
+        transport.push_msg(&BitcoinMsg::ping(8), Magic::MAINNET);
+
+        // Not decoded, so the trailing byte goes unnoticed.
+        let lazy = conn.read_lazy().unwrap();
+        assert_eq!(lazy.command().as_str(), "ping");
+        assert_eq!(lazy.bytes(), payload);
+        assert!(lazy.decode().is_err());
+
+        let lazy = conn.read_lazy().unwrap();
+        assert!(matches!(lazy.decode(), Ok(BitcoinPayload::Ping(8))));
+    }
+
+    #[test]
+    fn answers_pings_while_decoding() {
+        let (mut conn, transport) = connected();
+        conn.set_decode_pool(Some(DecodePool::new(2)));
+        let events = conn.subscribe();
+        let inv = |byte| InventoryElement {
+            kind: InventoryKind::Block,
+            hash: Hash([byte; 32]),
+        };
+        for byte in [1, 2] {
+            let inv = Inv::builder()
+                .elements(vec![inv(byte); 1000])
+                .build()
+                .unwrap();
+            transport.push_msg(
+                &BitcoinMsg {
+                    payload: BitcoinPayload::Inv(inv),
+                },
+                Magic::MAINNET,
+            );
+        }
+        transport.push_msg(&BitcoinMsg::ping(7), Magic::MAINNET);
+
+        for _ in 0..3 {
+            conn.process_next_msg().unwrap();
+        }
+        assert!(matches!(
+            transport.take_sent()[..],
+            [BitcoinMsg {
+                payload: BitcoinPayload::Pong(7)
+            }]
+        ));
+
+        while conn.pending_decodes() > 0 {
+            conn.dispatch_decoded().unwrap();
+        }
+        let hashes: Vec<Hash> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::InvReceived(inv) => Some(inv.inventory[0].hash),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(hashes, [Hash([1; 32]), Hash([2; 32])]);
+    }
+
+    #[test]
+    fn dumps_undecodable_frames() {
+        let dir = std::env::temp_dir().join(format!("btc-dump-{}", std::process::id()));
+        let (mut conn, transport) = connected();
+        conn.set_dump_dir(Some(dir.clone()));
+        let msg = BitcoinMsg {
+            payload: BitcoinPayload::Unknown {
+                command: "pong".into(),
+                payload: vec![0; 9],
+            },
+        };
+        transport.push_msg(&msg, Magic::MAINNET);
+        conn.process_next_msg().unwrap_err();
+
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            std::fs::read(&files[0]).unwrap(),
+            msg.encode(Magic::MAINNET)
+        );
+        let meta = std::fs::read_to_string(&files[1]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(meta.contains("command: pong\nsize: 33\nerror: 1 trailing bytes"));
+    }
+
+    #[test]
+    fn dumps_frames_decoded_with_a_pool() {
+        let dir = std::env::temp_dir().join(format!("btc-pool-dump-{}", std::process::id()));
+        let (mut conn, transport) = connected();
+        conn.set_dump_dir(Some(dir.clone()));
+        conn.set_decode_pool(Some(DecodePool::new(1)));
+        let unknown = |command: &str, payload| BitcoinMsg {
+            payload: BitcoinPayload::Unknown {
+                command: command.into(),
+                payload,
+            },
+        };
+        // Small enough to be decoded in place, and large enough to be
+        // handed to the pool.
+        let small = unknown("pong", vec![0; 9]);
+        let large = unknown("inv", vec![0; 20_000]);
+        transport.push_msg(&small, Magic::MAINNET);
+        transport.push_msg(&large, Magic::MAINNET);
+
+        conn.process_next_msg().unwrap_err();
+        conn.process_next_msg().unwrap();
+        while conn.dispatch_decoded().is_ok() {}
+
+        let files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        let mut frames: Vec<_> = files
+            .iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == "bin"))
+            .map(|path| std::fs::read(path).unwrap())
+            .collect();
+        frames.sort_by_key(|frame| frame.len());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files.len(), 4);
+        assert_eq!(
+            frames,
+            [small.encode(Magic::MAINNET), large.encode(Magic::MAINNET)]
+        );
+    }
+
+    #[test]
+    fn answers_one_getaddr() {
+        let (mut conn, transport) = connected();
+        let events = conn.subscribe();
+        transport.push_msg(&BitcoinMsg::getaddr(), Magic::MAINNET);
+        transport.push_msg(&BitcoinMsg::getaddr(), Magic::MAINNET);
+        conn.process_next_msg().unwrap();
+        conn.process_next_msg().unwrap();
+
+        assert_eq!(events.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn advertises_own_addr() {
+        let (mut conn, transport) = connected();
+        let addr = NetAddr {
+            services: Default::default(),
+            addr: "203.0.113.1:8333".parse().unwrap(),
+        };
+        conn.set_advertised_addr(Some(addr));
+        transport.push_msg(&BitcoinMsg::ping(1), Magic::MAINNET);
+        transport.push_msg(&BitcoinMsg::ping(2), Magic::MAINNET);
+        conn.process_next_msg().unwrap();
+        conn.process_next_msg().unwrap();
+
+        let addrs: Vec<_> = transport
+            .take_sent()
+            .into_iter()
+            .filter_map(|msg| match msg.payload {
+                BitcoinPayload::Addr(addr) => Some(addr),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(addrs.len(), 1);
+        assert_eq!(
+            addrs[0].addr_list[0].addr.addr,
+            "203.0.113.1:8333".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn trickles_announcements() {
+        let (mut conn, transport) = connected();
+        let inv = |kind, i| InventoryElement {
+            kind,
+            hash: Hash([i; 32]),
+        };
+        let sent_invs = || -> Vec<Inv> {
+            transport
+                .take_sent()
+                .into_iter()
+                .filter_map(|msg| match msg.payload {
+                    BitcoinPayload::Inv(inv) => Some(inv),
+                    _ => None,
+                })
+                .collect()
+        };
+        let now = Arc::new(Mutex::new(Instant::now()));
+        conn.set_clock({
+            let now = now.clone();
+            move || *now.lock().unwrap()
+        });
+        for i in 0..3 {
+            conn.announce_inv(inv(InventoryKind::Tx, i)).unwrap();
+        }
+        conn.announce_inv(inv(InventoryKind::Block, 9)).unwrap();
+        transport.push_msg(&BitcoinMsg::ping(1), Magic::MAINNET);
+        conn.process_next_msg().unwrap();
+
+        // Only the block went out, the transactions wait for the timer.
+        let invs = sent_invs();
+        assert_eq!(invs.len(), 1);
+        assert_eq!(invs[0].inventory[0].hash, Hash([9; 32]));
+        assert_eq!(conn.queued_announcements(), 3);
+
+        *now.lock().unwrap() += Duration::from_secs(3600);
+        transport.push_msg(&BitcoinMsg::ping(2), Magic::MAINNET);
+        conn.process_next_msg().unwrap();
+
+        let invs = sent_invs();
+        assert_eq!(invs.len(), 1);
+        assert_eq!(invs[0].inventory.len(), 3);
+        assert_eq!(conn.queued_announcements(), 0);
+    }
+
+    #[test]
+    fn pairs_requests_with_replies() {
+        let (mut conn, transport) = connected();
+        let header = |prev| BlockHeader {
+            version: 1,
+            prev_block: Hash([prev; 32]),
+            merkle_root: Hash::default(),
+            time: 0,
+            bits: 0,
+            nonce: 0,
+        };
+
+        // A new block announced by headers isn't the answer.
+        transport.push_msg(&BitcoinMsg::headers(vec![header(9)]), Magic::MAINNET);
+        transport.push_msg(&BitcoinMsg::headers(vec![header(1)]), Magic::MAINNET);
+        let reply = conn
+            .request(
+                BitcoinMsg::getheaders(vec![Hash([2; 32]), Hash([1; 32])], Hash::default()),
+                Duration::from_secs(1),
+            )
+            .unwrap();
+        let BitcoinPayload::Headers(headers) = reply.payload else {
+            panic!("expected headers, got {:?}", reply.payload);
+        };
+        assert_eq!(headers.headers[0].header, header(1));
+
+        // Version 1, no inputs or outputs and a zero lock time.
+        let tx = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let element = |hash| InventoryElement {
+            kind: InventoryKind::Tx,
+            hash,
+        };
+        transport.push_msg(
+            &BitcoinMsg {
+                payload: BitcoinPayload::NotFound(NotFound {
+                    inventory: vec![element(Hash([3; 32]))],
+                }),
+            },
+            Magic::MAINNET,
+        );
+        conn.send_raw("tx", &tx).unwrap();
+        transport.push_bytes(&transport.take_sent_bytes());
+        let reply = conn
+            .request(
+                BitcoinMsg::getdata(vec![element(sha256d(&tx))]),
+                Duration::from_secs(1),
+            )
+            .unwrap();
+        assert!(matches!(
+            reply.payload,
+            BitcoinPayload::Unknown { command, payload } if command == *"tx" && payload == tx
+        ));
+    }
+
+    #[test]
+    fn skips_self_announcements_for_getaddr() {
+        let (mut conn, transport) = connected();
+        let addr = |ip: &str| {
+            let addr = SocketAddr::new(ip.parse().unwrap(), 8333);
+            AddrElement {
+                timestamp: 0,
+                addr: NetAddr {
+                    services: Default::default(),
+                    addr,
+                },
+            }
+        };
+        let addr_msg = |ips: &[&str]| BitcoinMsg {
+            payload: BitcoinPayload::Addr(Addr {
+                addr_list: ips.iter().map(|ip| addr(ip)).collect(),
+            }),
+        };
+        // The peer, at 127.0.0.1, announcing itself first.
+        transport.push_msg(&addr_msg(&["::ffff:127.0.0.1"]), Magic::MAINNET);
+        transport.push_msg(&addr_msg(&["203.0.113.1"]), Magic::MAINNET);
+
+        let reply = conn
+            .request(BitcoinMsg::getaddr(), Duration::from_secs(1))
+            .unwrap();
+        let BitcoinPayload::Addr(reply) = reply.payload else {
+            panic!("expected addr, got {:?}", reply.payload);
+        };
+        assert_eq!(reply.addr_list[0].addr.addr.ip().to_string(), "203.0.113.1");
+    }
+
+    #[test]
+    fn queues_for_slow_peer() {
+        let (mut conn, transport) = connected();
+        transport.set_write_capacity(Some(30));
+
+        conn.send_raw("block", &[0; 1000]).unwrap();
+        conn.send_msg(&BitcoinMsg::ping(1)).unwrap();
+        assert_eq!(conn.queued_bytes(), 1024 - 30 + 32);
+
+        // The started block goes out first, then the pings jump ahead of
+        // the getaddr sent before them.
+        conn.send_msg(&BitcoinMsg::getaddr()).unwrap();
+        transport.set_write_capacity(None);
+        conn.send_msg(&BitcoinMsg::ping(2)).unwrap();
+
+        assert_eq!(conn.queued_bytes(), 0);
+        let commands: Vec<String> = transport
+            .take_sent()
+            .iter()
+            .map(|msg| msg.payload.command().to_string())
+            .collect();
+        assert_eq!(commands, ["block", "ping", "ping", "getaddr"]);
+    }
+
+    #[test]
+    fn full_queue_drops_gossip() {
+        let (mut conn, transport) = connected();
+        transport.set_write_capacity(Some(0));
+        conn.set_max_queued_bytes(100);
+
+        let inv = BitcoinMsg {
+            payload: BitcoinPayload::Inv(Inv { inventory: vec![] }),
+        };
+        conn.send_msg(&inv).unwrap();
+        conn.send_msg(&inv).unwrap();
+        conn.send_msg(&inv).unwrap();
+        assert_eq!(conn.queued_bytes(), 75);
+
+        // Gossip makes way for other messages, which fail once there is
+        // nothing left to drop.
+        conn.send_raw("block", &[0; 30]).unwrap();
+        assert_eq!(conn.queued_bytes(), 79);
+        assert!(conn.send_raw("block", &[0; 30]).is_err());
+        // Pings always get through.
+        conn.send_msg(&BitcoinMsg::ping(1)).unwrap();
+        assert_eq!(conn.queued_bytes(), 111);
+    }
+
+    #[test]
+    fn eof_disconnects() {
+        let (mut conn, transport) = connected();
+        let events = conn.subscribe();
+        transport.close();
+
+        assert!(conn.process_next_msg().is_err());
+        assert!(matches!(events.try_recv(), Ok(Event::Disconnected(_))));
+    }
+}
@@ -0,0 +1,1302 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::result;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+use crate::codec::{BitcoinType, ByteReader, DecodeError};
+use crate::crypto::{sha256d, txid, Checksum};
+#[cfg(feature = "metrics")]
+use crate::metrics::{Direction, Metrics};
+use crate::p2p::builder::{MAX_ADDR, MAX_INV, PROTOCOL_VERSION};
+use crate::p2p::messages::{
+    Addr, AddrElement, BitcoinHeader, BitcoinMsg, BitcoinPayload, Inv, LazyPayload, Version,
+    HEADER_SIZE, MAX_PROTOCOL_MESSAGE_LENGTH,
+};
+use crate::trace::{debug, debug_span, info, info_span, trace, warn, Span};
+use crate::types::{Command, InventoryElement, InventoryKind, Magic, NetAddr};
+
+mod addrbook;
+mod anchors;
+mod banlist;
+mod decode_pool;
+mod dump;
+mod enrich;
+mod event;
+mod external;
+#[cfg(feature = "faults")]
+mod fault;
+mod fingerprint;
+mod handler;
+mod latency;
+mod mock;
+mod peer_addr;
+mod pool;
+mod queue;
+mod record;
+mod time;
+mod trickle;
+
+pub use addrbook::AddrBook;
+pub use anchors::{read_anchors, write_anchors, MAX_ANCHORS};
+pub use banlist::{BanEntry, BanList, Subnet, DEFAULT_BAN_TIME};
+pub use decode_pool::{DecodePool, Decoding};
+pub use enrich::{Enricher, NetGroups};
+pub use event::{DisconnectReason, Event};
+pub use external::ExternalAddr;
+#[cfg(feature = "faults")]
+pub use fault::Fault;
+pub use fingerprint::{implementation_counts, Fingerprint, Implementation};
+pub use handler::{Handled, MessageHandler};
+pub use latency::{quality_score, rank_by_quality, LatencyHistogram, LATENCY_BUCKETS_MS};
+pub use mock::MockTransport;
+pub use peer_addr::PeerAddr;
+pub use queue::{Priority, DEFAULT_MAX_QUEUED_BYTES};
+pub use record::{EventRecorder, RECORD_HEADER};
+pub use time::{format_age, format_duration, unix_time, AdjustedTime};
+pub use trickle::{ADDR_INTERVAL, INBOUND_INV_INTERVAL, OUTBOUND_INV_INTERVAL};
+
+use pool::BufferPool;
+use queue::OutboundQueue;
+use time::random_delay;
+use trickle::Trickle;
+
+pub const USER_AGENT: &str = "my bitcoin client";
+// How long a peer has to complete the handshake, as Core's
+// -peertimeout defaults to.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+// Our own address is advertised to each peer about once a day, as Core
+// does.
+const ADVERTISE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+// The most read from the transport at once.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+// With a decode pool, payloads smaller than this are decoded in place,
+// as that's quicker than handing them to another thread. An inv of about
+// 450 entries.
+const DEFER_MIN_SIZE: usize = 16 * 1024;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    IoErr(io::Error),
+    ProtocolErr,
+    Timeout,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub msg: Option<String>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Error {
+        Error { kind, msg: None }
+    }
+
+    pub fn with_msg(kind: ErrorKind, msg: impl ToString) -> Error {
+        Error {
+            kind,
+            msg: Some(msg.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::new(ErrorKind::IoErr(e))
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+pub trait Transport: Read + Write {
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Transport for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+pub struct Connection<T: Transport = TcpStream> {
+    transport: T,
+    // Received bytes not yet decoded start at read_pos.
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    // The header of the frame at read_pos, once it is checked, until the
+    // rest of the frame arrives.
+    pending_header: Option<BitcoinHeader>,
+    // Set once a frame couldn't be told apart from the next, after which
+    // nothing more is read from the peer.
+    broken: bool,
+    buffers: BufferPool,
+    outbound: OutboundQueue,
+    peer_version: Option<Version>,
+    // What the peer sent between its version and verack.
+    handshake_commands: Vec<Command>,
+    read_timeout: Option<Duration>,
+    handshake_timeout: Duration,
+    // When reads give up however much data keeps coming, while set.
+    read_deadline: Option<Instant>,
+    magic: Magic,
+    checksum: Checksum,
+    verify_checksums: bool,
+    relay: bool,
+    time_offset: Option<i64>,
+    latency: Option<Duration>,
+    latencies: LatencyHistogram,
+    ping_sent: Option<(u64, Instant)>,
+    getaddr_received: bool,
+    advertised_addr: Option<NetAddr>,
+    next_advertisement: Instant,
+    clock: Box<dyn Fn() -> Instant + Send>,
+    inv_trickle: Trickle<InventoryElement>,
+    addr_trickle: Trickle<AddrElement>,
+    rng: Box<dyn RngCore + Send>,
+    local_nonce: Option<u64>,
+    subscribers: Vec<Sender<Event>>,
+    handlers: Vec<(Command, MessageHandler<T>)>,
+    connected: bool,
+    connected_at: Option<Instant>,
+    dump_dir: Option<PathBuf>,
+    decode_pool: Option<DecodePool>,
+    // Payloads with the pool, oldest first, and their frames if they
+    // would be dumped.
+    decoding: VecDeque<(Command, Option<Vec<u8>>, Decoding)>,
+    span: Span,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<Metrics>>,
+    #[cfg(feature = "faults")]
+    faults: fault::Faults,
+}
+
+impl Connection<TcpStream> {
+    pub fn connect(addr: SocketAddr) -> Result<Connection<TcpStream>> {
+        let mut conn = Connection::new(TcpStream::connect(addr)?);
+        conn.handshake()?;
+        Ok(conn)
+    }
+}
+
+impl<T: Transport> Connection<T> {
+    pub fn new(transport: T) -> Connection<T> {
+        let span = info_span!("peer", addr = ?transport.peer_addr().ok());
+
+        Connection {
+            transport,
+            read_buf: vec![],
+            read_pos: 0,
+            pending_header: None,
+            broken: false,
+            buffers: BufferPool::default(),
+            outbound: OutboundQueue::new(),
+            peer_version: None,
+            handshake_commands: vec![],
+            read_timeout: None,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            read_deadline: None,
+            magic: Magic::MAINNET,
+            checksum: Checksum::new(),
+            verify_checksums: true,
+            relay: true,
+            time_offset: None,
+            latency: None,
+            latencies: LatencyHistogram::new(),
+            ping_sent: None,
+            getaddr_received: false,
+            advertised_addr: None,
+            next_advertisement: Instant::now(),
+            clock: Box::new(Instant::now),
+            inv_trickle: Trickle::new(OUTBOUND_INV_INTERVAL, Instant::now()),
+            addr_trickle: Trickle::new(ADDR_INTERVAL, Instant::now()),
+            rng: Box::new(StdRng::from_entropy()),
+            local_nonce: None,
+            subscribers: vec![],
+            handlers: vec![],
+            connected: false,
+            connected_at: None,
+            dump_dir: None,
+            decode_pool: None,
+            decoding: VecDeque::new(),
+            span,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            #[cfg(feature = "faults")]
+            faults: fault::Faults::default(),
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.transport.peer_addr()
+    }
+
+    pub fn peer_version(&self) -> Option<&Version> {
+        self.peer_version.as_ref()
+    }
+
+    // A guess at the software the peer runs, once the handshake is done.
+    pub fn fingerprint(&self) -> Option<Fingerprint> {
+        let version = self.peer_version.as_ref()?;
+        Some(Fingerprint::of(
+            version,
+            &self.handshake_commands,
+            PROTOCOL_VERSION,
+        ))
+    }
+
+    // Frames whose payload doesn't decode are written to `dir` along with
+    // where they came from, for working out later what the peer meant.
+    // None, the default, stops that.
+    pub fn set_dump_dir(&mut self, dir: Option<PathBuf>) {
+        self.dump_dir = dir;
+    }
+
+    // How long handshake() waits for the peer's version and verack in
+    // all, however slowly it trickles in other messages before them.
+    pub fn set_handshake_timeout(&mut self, timeout: Duration) {
+        self.handshake_timeout = timeout;
+    }
+
+    pub fn set_magic(&mut self, magic: Magic) {
+        self.magic = magic;
+    }
+
+    pub fn magic(&self) -> Magic {
+        self.magic
+    }
+
+    // Skipping verification saves a double SHA256 over every received
+    // payload, for uses like crawling where a corrupted message costs
+    // little. TCP already catches most transmission errors.
+    pub fn set_verify_checksums(&mut self, verify: bool) {
+        self.verify_checksums = verify;
+    }
+
+    // The relay flag we advertise in our version message, so it only has an
+    // effect when set before the handshake.
+    pub fn set_relay(&mut self, relay: bool) {
+        self.relay = relay;
+    }
+
+    pub fn relay(&self) -> bool {
+        self.relay
+    }
+
+    // Whether the peer asked for transactions to be relayed to it. Until
+    // the handshake completes nothing is known, so this is false.
+    pub fn peer_relay(&self) -> bool {
+        self.peer_version
+            .as_ref()
+            .is_some_and(|version| version.relay)
+    }
+
+    // Seconds the peer's clock is ahead of ours, as of its version message.
+    pub fn time_offset(&self) -> Option<i64> {
+        self.time_offset
+    }
+
+    // Round trip of the version exchange, refreshed by every answered ping.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    pub fn latency_histogram(&self) -> &LatencyHistogram {
+        &self.latencies
+    }
+
+    // Time since the handshake completed.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.connected_at.map(|at| at.elapsed())
+    }
+
+    pub fn quality_score(&self) -> f64 {
+        quality_score(&self.latencies, self.uptime().unwrap_or_default())
+    }
+
+    fn record_latency(&mut self, latency: Duration) {
+        self.latency = Some(latency);
+        self.latencies.add(latency);
+    }
+
+    // Replaces where version and ping nonces and random delays come from,
+    // so tests can seed it and get the same messages every run.
+    pub fn set_rng(&mut self, rng: impl RngCore + Send + 'static) {
+        self.rng = Box::new(rng);
+    }
+
+    // Replaces where the timers of advertisements and trickles get the
+    // time from, so tests can move it forward instead of waiting.
+    pub fn set_clock(&mut self, clock: impl Fn() -> Instant + Send + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    fn now(&self) -> Instant {
+        (self.clock)()
+    }
+
+    // The nonce of the version message we sent, once it has been sent.
+    pub fn local_nonce(&self) -> Option<u64> {
+        self.local_nonce
+    }
+
+    // Sends a ping with a random nonce, which is returned. Its pong updates
+    // latency().
+    pub fn ping(&mut self) -> Result<u64> {
+        let nonce = self.rng.next_u64();
+        self.send_msg(&BitcoinMsg::ping(nonce))?;
+        Ok(nonce)
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    // Sets the address advertised to the peer in addr messages. The first
+    // advertisement goes out soon after, later ones at random intervals.
+    pub fn set_advertised_addr(&mut self, addr: Option<NetAddr>) {
+        self.advertised_addr = addr;
+        self.next_advertisement = self.now();
+    }
+
+    pub fn advertised_addr(&self) -> Option<&NetAddr> {
+        self.advertised_addr.as_ref()
+    }
+
+    fn advertise_if_due(&mut self) -> Result<()> {
+        let Some(addr) = self.advertised_addr.clone() else {
+            return Ok(());
+        };
+        let now = self.now();
+        if !self.connected || now < self.next_advertisement {
+            return Ok(());
+        }
+
+        self.next_advertisement = now + random_delay(&mut self.rng, ADVERTISE_INTERVAL);
+        debug!(addr = %addr.addr, "advertising own address");
+        self.send_msg(&BitcoinMsg {
+            payload: BitcoinPayload::Addr(Addr {
+                addr_list: vec![AddrElement {
+                    timestamp: unix_time() as u32,
+                    addr,
+                }],
+            }),
+        })
+    }
+
+    // Queues an inventory element to be announced to the peer with others
+    // at the next random trickle, as Core relays transactions. Blocks are
+    // announced at once, and transactions not at all to a peer that asked
+    // not to be sent them.
+    pub fn announce_inv(&mut self, element: InventoryElement) -> Result<()> {
+        if element.kind.is_tx() && !self.peer_relay() {
+            return Ok(());
+        }
+        if !element.kind.is_tx() {
+            return self.send_msg(&BitcoinMsg {
+                payload: BitcoinPayload::Inv(Inv {
+                    inventory: vec![element],
+                }),
+            });
+        }
+        let now = self.now();
+        self.inv_trickle.push(element, now, &mut self.rng);
+        Ok(())
+    }
+
+    // Queues an address to be relayed to the peer with others at the next
+    // random trickle.
+    pub fn announce_addr(&mut self, element: AddrElement) {
+        let now = self.now();
+        self.addr_trickle.push(element, now, &mut self.rng);
+    }
+
+    // Inventory and addresses queued by announce_inv() and announce_addr()
+    // that haven't gone out yet.
+    pub fn queued_announcements(&self) -> usize {
+        self.inv_trickle.len() + self.addr_trickle.len()
+    }
+
+    // The average time between inventory trickles, OUTBOUND_INV_INTERVAL
+    // unless set, as connections are ones we made. Core trickles slower
+    // to inbound peers, with INBOUND_INV_INTERVAL.
+    pub fn set_inv_trickle_interval(&mut self, mean: Duration) {
+        let now = self.now();
+        self.inv_trickle.set_mean(mean, now, &mut self.rng);
+    }
+
+    // The average time between address trickles, ADDR_INTERVAL unless set.
+    pub fn set_addr_trickle_interval(&mut self, mean: Duration) {
+        let now = self.now();
+        self.addr_trickle.set_mean(mean, now, &mut self.rng);
+    }
+
+    fn trickle_if_due(&mut self) -> Result<()> {
+        if !self.connected {
+            return Ok(());
+        }
+
+        let now = self.now();
+        if let Some(inventory) = self.inv_trickle.take_if_due(MAX_INV, now, &mut self.rng) {
+            trace!(count = inventory.len(), "trickling inventory");
+            self.send_msg(&BitcoinMsg {
+                payload: BitcoinPayload::Inv(Inv { inventory }),
+            })?;
+        }
+        if let Some(addr_list) = self.addr_trickle.take_if_due(MAX_ADDR, now, &mut self.rng) {
+            trace!(count = addr_list.len(), "trickling addresses");
+            self.send_msg(&BitcoinMsg {
+                payload: BitcoinPayload::Addr(Addr { addr_list }),
+            })?;
+        }
+        Ok(())
+    }
+
+    // Registers a handler that runs on every received message with this
+    // command, before the built-in handling, in registration order. The
+    // handler can reply through the connection it is given, and stop
+    // further handling by returning Handled::Consumed. An error aborts the
+    // processing of the message and is returned to the caller.
+    //
+    // Handlers only see messages after the handshake.
+    pub fn on_message(
+        &mut self,
+        command: Command,
+        handler: impl FnMut(&mut Connection<T>, &BitcoinMsg) -> Result<Handled> + Send + 'static,
+    ) {
+        self.handlers.push((command, Box::new(handler)));
+    }
+
+    // Mangles every outbound message with this command from now on. Faults
+    // for the same command add up, e.g. truncated and delayed.
+    #[cfg(feature = "faults")]
+    pub fn inject_fault(&mut self, command: Command, fault: Fault) {
+        self.faults.add(command, fault);
+    }
+
+    #[cfg(feature = "faults")]
+    pub fn clear_faults(&mut self, command: &Command) {
+        self.faults.clear(command);
+    }
+
+    #[cfg(feature = "faults")]
+    fn send_held(&mut self, after_send: bool) -> Result<()> {
+        for (command, blob) in self.faults.take_due(after_send) {
+            trace!(size = blob.len(), "sending held back message");
+            self.enqueue(&command, blob)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    #[cfg(feature = "metrics")]
+    fn record(&self, f: impl FnOnce(&Metrics)) {
+        if let Some(metrics) = &self.metrics {
+            f(metrics);
+        }
+    }
+
+    fn emit(&mut self, event: Event) {
+        if let Event::Disconnected(_) = event {
+            if self.connected {
+                self.connected = false;
+                #[cfg(feature = "metrics")]
+                self.record(|m| m.peer_disconnected());
+            }
+        }
+
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    // Bytes sent but not yet taken by the transport. Only non-blocking
+    // transports ever leave any behind.
+    pub fn queued_bytes(&self) -> usize {
+        self.outbound.len()
+    }
+
+    // Caps queued_bytes(). Past it low priority messages are dropped and
+    // sending normal priority ones fails; see Priority.
+    pub fn set_max_queued_bytes(&mut self, max: usize) {
+        self.outbound.set_max_len(max);
+    }
+
+    // Writes queued messages until the transport would block. Sending and
+    // reading do this too; event loops call it when the transport becomes
+    // writable.
+    pub fn flush_outbound(&mut self) -> Result<()> {
+        self.outbound.write_to(&mut self.transport)?;
+        Ok(())
+    }
+
+    fn enqueue(&mut self, command: &Command, blob: Vec<u8>) -> Result<bool> {
+        let priority = Priority::of(command);
+        if !self.outbound.push(priority, blob) {
+            if priority == Priority::Low {
+                debug!(
+                    queued = self.outbound.len(),
+                    "outbound queue full, dropping message"
+                );
+                return Ok(false);
+            }
+            return Err(Error::with_msg(
+                ErrorKind::IoErr(io::ErrorKind::WouldBlock.into()),
+                format!(
+                    "Outbound queue full with {} bytes, the peer is not reading",
+                    self.outbound.len()
+                ),
+            ));
+        }
+
+        self.flush_outbound()?;
+        Ok(true)
+    }
+
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.transport.set_read_timeout(timeout)?;
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    pub fn handshake(&mut self) -> Result<()> {
+        let _span = self.span.clone().entered();
+        debug!("starting handshake");
+
+        self.read_deadline = Some(Instant::now() + self.handshake_timeout);
+        let exchanged = self.exchange_versions();
+        self.read_deadline = None;
+        self.transport.set_read_timeout(self.read_timeout)?;
+        let version = match exchanged {
+            Ok(version) => version,
+            Err(e) => {
+                #[cfg(feature = "metrics")]
+                self.record(|m| m.handshake_failed());
+                return Err(e);
+            }
+        };
+
+        info!(
+            user_agent = %version.user_agent,
+            proto_ver = version.proto_ver,
+            time_offset = self.time_offset,
+            "handshake complete"
+        );
+
+        self.peer_version = Some(version.clone());
+        self.connected = true;
+        self.connected_at = Some(Instant::now());
+        #[cfg(feature = "metrics")]
+        self.record(|m| m.peer_connected());
+
+        let addr = self.transport.peer_addr()?;
+        self.emit(Event::PeerConnected { addr, version });
+
+        Ok(())
+    }
+
+    fn exchange_versions(&mut self) -> Result<Version> {
+        let nonce = self.rng.next_u64();
+        self.local_nonce = Some(nonce);
+        let version = Version::builder()
+            .local(NetAddr {
+                services: Default::default(),
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8333),
+            })
+            .remote(NetAddr {
+                services: Default::default(),
+                addr: self.transport.peer_addr()?,
+            })
+            .user_agent(USER_AGENT)
+            .nonce(nonce)
+            .relay(self.relay)
+            .build()
+            .map_err(|e| Error::with_msg(ErrorKind::ProtocolErr, e))?;
+        let msg = BitcoinMsg {
+            payload: BitcoinPayload::Version(version),
+        };
+
+        let sent = Instant::now();
+        self.send_msg(&msg)?;
+
+        let version = if let BitcoinPayload::Version(version) = self.read_msg()?.payload {
+            version
+        } else {
+            return Err(Error::with_msg(
+                ErrorKind::ProtocolErr,
+                "Peer did not start the handshake with a version message",
+            ));
+        };
+        if version.nonce == nonce {
+            return Err(Error::with_msg(
+                ErrorKind::ProtocolErr,
+                "Peer sent back our own version nonce, connected to ourselves?",
+            ));
+        }
+
+        self.record_latency(sent.elapsed());
+        self.time_offset = Some(version.time as i64 - unix_time());
+
+        // Feature negotiation messages like wtxidrelay and sendaddrv2 are
+        // sent between version and verack, so skip anything until verack,
+        // keeping note of it for fingerprinting.
+        self.handshake_commands.clear();
+        loop {
+            match self.read_msg()?.payload {
+                BitcoinPayload::VerAck => break,
+                payload => self.handshake_commands.push(payload.command()),
+            }
+        }
+
+        self.send_msg(&BitcoinMsg::verack())?;
+
+        Ok(version)
+    }
+
+    pub fn disconnect(mut self) {
+        let _span = self.span.clone().entered();
+        info!("disconnecting");
+        self.emit(Event::Disconnected(DisconnectReason::Local));
+    }
+
+    pub fn process_next_msg(&mut self) -> Result<()> {
+        let _span = self.span.clone().entered();
+        self.flush_outbound()?;
+        self.advertise_if_due()?;
+        self.trickle_if_due()?;
+        if self.decode_pool.is_none() {
+            let msg = self.read_or_disconnect(Connection::read_msg)?;
+            return self.dispatch(msg);
+        }
+
+        self.dispatch_decoded()?;
+        let (header, mut frame) = self.read_or_disconnect(Connection::read_frame)?;
+        let command = header.command;
+        // Pings and pongs are never held up behind a large payload. Other
+        // messages wait their turn so they are dispatched in order.
+        let keepalive = matches!(command, Command::Ping | Command::Pong);
+        if !keepalive && (!self.decoding.is_empty() || header.size as usize >= DEFER_MIN_SIZE) {
+            // A copy of the frame is only kept if it is to be dumped
+            // should the payload not decode.
+            let dump = self.dump_dir.is_some().then(|| frame.clone());
+            frame.drain(..HEADER_SIZE);
+            let pool = self.decode_pool.as_ref().unwrap();
+            let decoding = pool.decode(LazyPayload::new(command.clone(), frame));
+            self.decoding.push_back((command, dump, decoding));
+            return Ok(());
+        }
+
+        let payload = self.decode_frame(&command, frame)?;
+        self.dispatch(BitcoinMsg { payload })
+    }
+
+    // Payloads are decoded on the pool's threads from then on, apart
+    // from small ones, and dispatched as they are ready by
+    // process_next_msg and dispatch_decoded. Reads, including request(),
+    // still decode in place.
+    pub fn set_decode_pool(&mut self, pool: Option<DecodePool>) {
+        self.decode_pool = pool;
+    }
+
+    // Dispatches the messages the decode pool has finished, in the order
+    // they were received, stopping at the first still being decoded.
+    // Returns how many were dispatched.
+    pub fn dispatch_decoded(&mut self) -> Result<usize> {
+        let mut dispatched = 0;
+        while let Some((command, _, decoding)) = self.decoding.front() {
+            let decoded = match decoding.try_recv() {
+                Ok(decoded) => Ok(decoded),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => Err(format!("decoding {command} panicked")),
+            };
+            let (command, dump, _) = self.decoding.pop_front().unwrap();
+
+            let error = match &decoded {
+                Ok(Err(e)) => Some(e.to_string()),
+                Ok(Ok(_)) => None,
+                Err(error) => Some(error.clone()),
+            };
+            if let (Some(error), Some(frame)) = (error, dump) {
+                self.dump_frame(&command, &error, &frame);
+            }
+
+            let payload = match decoded {
+                Ok(decoded) => self.check_decoded(&command, decoded)?,
+                Err(error) => {
+                    warn!(%error, "dropping undecodable payload");
+                    #[cfg(feature = "metrics")]
+                    self.record(|m| m.decode_error());
+                    return Err(Error::with_msg(ErrorKind::ProtocolErr, error));
+                }
+            };
+            self.dispatch(BitcoinMsg { payload })?;
+            dispatched += 1;
+        }
+        Ok(dispatched)
+    }
+
+    // Payloads handed to the decode pool and not dispatched yet.
+    pub fn pending_decodes(&self) -> usize {
+        self.decoding.len()
+    }
+
+    fn check_decoded(
+        &mut self,
+        command: &Command,
+        decoded: result::Result<BitcoinPayload, DecodeError>,
+    ) -> Result<BitcoinPayload> {
+        decoded.map_err(|e| {
+            let error = format!("Could not decode {command} message: {e}");
+            warn!(%error, "dropping undecodable payload");
+            #[cfg(feature = "metrics")]
+            self.record(|m| m.decode_error());
+            Error::with_msg(ErrorKind::ProtocolErr, error)
+        })
+    }
+
+    // Sends `msg` and blocks until the peer answers it, dispatching any
+    // unrelated messages that arrive in the meantime as usual.
+    pub fn request(&mut self, msg: BitcoinMsg, timeout: Duration) -> Result<BitcoinMsg> {
+        let _span = self.span.clone().entered();
+        if !expects_response(&msg.payload) {
+            return Err(Error::with_msg(
+                ErrorKind::ProtocolErr,
+                format!("{} has no response to wait for", msg.payload.command()),
+            ));
+        }
+
+        self.send_msg(&msg)?;
+
+        let peer_ip = self.transport.peer_addr().ok().map(|addr| addr.ip());
+        let deadline = Instant::now() + timeout;
+        let ret = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                debug!(command = %msg.payload.command(), "request timed out");
+                break Err(Error::with_msg(
+                    ErrorKind::Timeout,
+                    format!("No response to {} after {timeout:?}", msg.payload.command()),
+                ));
+            }
+
+            if let Err(e) = self.transport.set_read_timeout(Some(remaining)) {
+                break Err(e.into());
+            }
+
+            match self.read_or_disconnect(Connection::read_msg) {
+                Ok(reply) if is_response(&msg.payload, &reply.payload, peer_ip) => {
+                    break self.dispatch(reply.clone()).map(|_| reply);
+                }
+                Ok(other) => {
+                    if let Err(e) = self.dispatch(other) {
+                        break Err(e);
+                    }
+                }
+                Err(Error {
+                    kind: ErrorKind::IoErr(e),
+                    ..
+                }) if matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) => {}
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.transport.set_read_timeout(self.read_timeout)?;
+        ret
+    }
+
+    fn read_or_disconnect<R>(&mut self, read: fn(&mut Self) -> Result<R>) -> Result<R> {
+        let ret = read(self);
+
+        if let Err(Error {
+            kind: ErrorKind::IoErr(e),
+            ..
+        }) = &ret
+        {
+            match e.kind() {
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {}
+                // Reported when the framing broke.
+                io::ErrorKind::NotConnected if self.broken => {}
+                io::ErrorKind::UnexpectedEof => {
+                    info!("peer closed the connection");
+                    self.emit(Event::Disconnected(DisconnectReason::ClosedByPeer))
+                }
+                _ => {
+                    warn!(error = %e, "connection lost");
+                    self.emit(Event::Disconnected(DisconnectReason::Error(e.to_string())))
+                }
+            }
+        }
+
+        ret
+    }
+
+    fn dispatch(&mut self, msg: BitcoinMsg) -> Result<()> {
+        let _span = debug_span!("recv", command = %msg.payload.command()).entered();
+        trace!("dispatching message");
+
+        if self.run_handlers(&msg)? == Handled::Consumed {
+            trace!("message consumed by a handler");
+            return Ok(());
+        }
+
+        let event = match msg.payload {
+            BitcoinPayload::Inv(mut inv) => {
+                // A peer that was told not to relay transactions shouldn't
+                // announce any, and whatever it does announce is ignored.
+                if !self.relay {
+                    let len = inv.inventory.len();
+                    inv.inventory.retain(|element| !element.kind.is_tx());
+                    if inv.inventory.len() != len {
+                        debug!(
+                            dropped = len - inv.inventory.len(),
+                            "ignoring tx announcements with relay disabled"
+                        );
+                    }
+                    if inv.inventory.is_empty() {
+                        return Ok(());
+                    }
+                }
+                Event::InvReceived(inv)
+            }
+            BitcoinPayload::Addr(addr) => {
+                if addr.addr_list.len() > MAX_ADDR {
+                    return Err(Error::with_msg(
+                        ErrorKind::ProtocolErr,
+                        format!(
+                            "Peer sent {} addresses in one message, at most {MAX_ADDR} are allowed",
+                            addr.addr_list.len()
+                        ),
+                    ));
+                }
+                Event::AddrReceived(addr)
+            }
+            // Like Core, only the first getaddr on a connection is answered,
+            // so a peer can't scrape the address book by asking repeatedly.
+            BitcoinPayload::GetAddr if self.getaddr_received => {
+                debug!("ignoring repeated getaddr");
+                return Ok(());
+            }
+            BitcoinPayload::GetAddr => {
+                self.getaddr_received = true;
+                Event::MessageReceived(BitcoinMsg::getaddr())
+            }
+            BitcoinPayload::Ping(nonce) => {
+                self.send_msg(&BitcoinMsg::pong(nonce))?;
+                Event::PingReceived(nonce)
+            }
+            BitcoinPayload::Pong(nonce) => {
+                if let Some((sent_nonce, sent)) = self.ping_sent {
+                    if sent_nonce == nonce {
+                        self.record_latency(sent.elapsed());
+                        self.ping_sent = None;
+                    }
+                }
+                Event::PongReceived(nonce)
+            }
+            _ => Event::MessageReceived(msg),
+        };
+        self.emit(event);
+
+        Ok(())
+    }
+
+    fn run_handlers(&mut self, msg: &BitcoinMsg) -> Result<Handled> {
+        let command = msg.payload.command();
+        if !self.handlers.iter().any(|(c, _)| *c == command) {
+            return Ok(Handled::Continue);
+        }
+
+        // Handlers get the connection itself, so they are taken out while
+        // they run. Any registered meanwhile go after the existing ones.
+        let mut handlers = mem::take(&mut self.handlers);
+        let mut ret = Ok(Handled::Continue);
+        for (_, handler) in handlers.iter_mut().filter(|(c, _)| *c == command) {
+            ret = handler(self, msg);
+            if !matches!(ret, Ok(Handled::Continue)) {
+                break;
+            }
+        }
+        handlers.append(&mut self.handlers);
+        self.handlers = handlers;
+
+        ret
+    }
+
+    pub fn send_msg(&mut self, msg: &BitcoinMsg) -> Result<()> {
+        let _peer = self.span.clone().entered();
+        let _msg = debug_span!("send", command = %msg.payload.command()).entered();
+
+        if let BitcoinPayload::Addr(addr) = &msg.payload {
+            if addr.addr_list.len() > MAX_ADDR {
+                return Err(Error::with_msg(
+                    ErrorKind::ProtocolErr,
+                    format!(
+                        "{} addresses don't fit in one message, at most {MAX_ADDR} are allowed",
+                        addr.addr_list.len()
+                    ),
+                ));
+            }
+        }
+
+        if let BitcoinPayload::Inv(inv) = &msg.payload {
+            if !self.peer_relay() && inv.inventory.iter().any(|element| element.kind.is_tx()) {
+                return Err(Error::with_msg(
+                    ErrorKind::ProtocolErr,
+                    "Peer asked not to be sent transactions",
+                ));
+            }
+        }
+
+        let command = msg.payload.command();
+        let blob = msg.encode_with(self.magic, &mut self.checksum);
+        #[cfg(feature = "faults")]
+        let Some(blob) = self.faults.apply(&command, blob) else {
+            debug!("holding message back");
+            return Ok(());
+        };
+        #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+        let size = blob.len();
+        trace!(size, "sending message");
+        if !self.enqueue(&command, blob)? {
+            return Ok(());
+        }
+        #[cfg(feature = "faults")]
+        self.send_held(true)?;
+
+        if let BitcoinPayload::Ping(nonce) = msg.payload {
+            self.ping_sent = Some((nonce, Instant::now()));
+        }
+
+        #[cfg(feature = "metrics")]
+        self.record(|m| m.record_message(Direction::Sent, &command, size));
+
+        Ok(())
+    }
+
+    // Sends `payload` as is, framed as a `command` message with the right
+    // magic, length and checksum. Meant for probing how peers react to
+    // malformed or experimental messages; nothing checks the payload.
+    pub fn send_raw(&mut self, command: &str, payload: &[u8]) -> Result<()> {
+        if command.len() > 12 || !command.is_ascii() {
+            return Err(Error::with_msg(
+                ErrorKind::ProtocolErr,
+                format!("\"{command}\" is not a valid command, it must be at most 12 ASCII bytes"),
+            ));
+        }
+
+        self.send_msg(&BitcoinMsg {
+            payload: BitcoinPayload::Unknown {
+                command: Command::from(command),
+                payload: payload.to_vec(),
+            },
+        })
+    }
+
+    pub fn read_msg(&mut self) -> Result<BitcoinMsg> {
+        self.read_next(Connection::next_buffered_msg)
+    }
+
+    // Like read_msg, but the payload is only split off its frame, after
+    // checking the magic and checksum, and decoded if and when the caller
+    // asks for it.
+    pub fn read_lazy(&mut self) -> Result<LazyPayload> {
+        self.read_next(Connection::next_buffered_lazy)
+    }
+
+    // Hands the buffer of a payload read with read_lazy back for reading
+    // the next ones into, once the caller is done with it.
+    pub fn recycle(&mut self, payload: LazyPayload) {
+        self.buffers.give(payload.into_bytes());
+    }
+
+    fn read_frame(&mut self) -> Result<(BitcoinHeader, Vec<u8>)> {
+        self.read_next(Connection::next_buffered_frame)
+    }
+
+    fn read_next<R>(&mut self, next: fn(&mut Self) -> Result<Option<R>>) -> Result<R> {
+        let _span = self.span.clone().entered();
+        if self.broken {
+            return Err(Error::new(ErrorKind::IoErr(
+                io::ErrorKind::NotConnected.into(),
+            )));
+        }
+
+        loop {
+            if let Some(ret) = next(self)? {
+                return Ok(ret);
+            }
+
+            #[cfg(feature = "faults")]
+            self.send_held(false)?;
+            self.flush_outbound()?;
+            self.wait_no_later_than_deadline()?;
+
+            // Read straight into the buffer, as much as the socket has, so
+            // a burst of small messages costs one syscall instead of one
+            // per message or per 4 KiB.
+            let len = self.read_buf.len();
+            self.read_buf.resize(len + READ_CHUNK_SIZE, 0);
+            let read = self.transport.read(&mut self.read_buf[len..]);
+            self.read_buf.truncate(len + *read.as_ref().unwrap_or(&0));
+            if read.is_err() {
+                self.wait_no_later_than_deadline()?;
+            }
+            if read? == 0 {
+                return Err(Error::new(ErrorKind::IoErr(
+                    io::ErrorKind::UnexpectedEof.into(),
+                )));
+            }
+        }
+    }
+
+    // With a read deadline set, makes the next read wait no longer than
+    // what is left of it, or fails once it has passed.
+    fn wait_no_later_than_deadline(&mut self) -> Result<()> {
+        let Some(deadline) = self.read_deadline else {
+            return Ok(());
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::with_msg(
+                ErrorKind::Timeout,
+                format!(
+                    "Peer did not complete the handshake in {:?}",
+                    self.handshake_timeout
+                ),
+            ));
+        }
+        let timeout = self.read_timeout.map_or(remaining, |t| t.min(remaining));
+        self.transport.set_read_timeout(Some(timeout))?;
+        Ok(())
+    }
+
+    // Drops the bytes already decoded. Done only once they are most of the
+    // buffer, so the rest isn't moved down after every message.
+    fn consume(&mut self, len: usize) {
+        self.read_pos += len;
+        if self.read_pos == self.read_buf.len() {
+            self.read_buf.clear();
+            self.read_pos = 0;
+        } else if self.read_pos > self.read_buf.len() / 2 {
+            self.read_buf.drain(..self.read_pos);
+            self.read_pos = 0;
+        }
+    }
+
+    fn next_buffered_msg(&mut self) -> Result<Option<BitcoinMsg>> {
+        let Some((header, frame)) = self.next_buffered_frame()? else {
+            return Ok(None);
+        };
+        let payload = self.decode_frame(&header.command, frame)?;
+        Ok(Some(BitcoinMsg { payload }))
+    }
+
+    // The frame is already consumed, so a payload that doesn't decode to
+    // its full size costs only this message and not the next.
+    fn decode_frame(&mut self, command: &Command, mut frame: Vec<u8>) -> Result<BitcoinPayload> {
+        let raw_header: [u8; HEADER_SIZE] = frame[..HEADER_SIZE].try_into().unwrap();
+        frame.drain(..HEADER_SIZE);
+
+        let mut reader = ByteReader::new(frame);
+        let decoded = BitcoinPayload::decode_from(command, &mut reader);
+        let bytes = reader.into_inner();
+        if let Err(e) = &decoded {
+            let frame = [&raw_header[..], &bytes].concat();
+            self.dump_frame(command, &e.to_string(), &frame);
+        } else {
+            self.buffers.give(bytes);
+        }
+        self.check_decoded(command, decoded)
+    }
+
+    fn next_buffered_lazy(&mut self) -> Result<Option<LazyPayload>> {
+        let Some((header, mut frame)) = self.next_buffered_frame()? else {
+            return Ok(None);
+        };
+        frame.drain(..HEADER_SIZE);
+        Ok(Some(LazyPayload::new(header.command, frame)))
+    }
+
+    // Takes the next frame off the buffer once all of it is there, with
+    // its magic and checksum verified. The header is checked as soon as
+    // it arrives, so that a peer can't have a frame too large to accept
+    // buffered, and kept until the rest of the frame arrives.
+    fn next_buffered_frame(&mut self) -> Result<Option<(BitcoinHeader, Vec<u8>)>> {
+        let buffered = self.read_buf.len() - self.read_pos;
+        let header = match self.pending_header.take() {
+            Some(header) => header,
+            None if buffered < HEADER_SIZE => return Ok(None),
+            None => {
+                let raw = &self.read_buf[self.read_pos..self.read_pos + HEADER_SIZE];
+                // Every header is HEADER_SIZE bytes, so this can't run out.
+                let header = BitcoinHeader::from_blob(&mut ByteReader::new(raw.to_vec())).unwrap();
+                // Checked here rather than left to BitcoinMsg::decode so
+                // that a bad frame is reported as an error instead of a
+                // panic.
+                if header.magic != self.magic {
+                    let error = format!("Unexpected network magic {}", header.magic);
+                    return Err(self.break_framing(error));
+                }
+                if header.size as usize > MAX_PROTOCOL_MESSAGE_LENGTH {
+                    let error = format!(
+                        "{} message of {} bytes, at most {MAX_PROTOCOL_MESSAGE_LENGTH} are allowed",
+                        header.command, header.size
+                    );
+                    return Err(self.break_framing(error));
+                }
+                header
+            }
+        };
+
+        let msg_size = HEADER_SIZE + header.size as usize;
+        if buffered < msg_size {
+            self.pending_header = Some(header);
+            return Ok(None);
+        }
+
+        trace!(command = %header.command, size = header.size, "received message");
+
+        let mut msg = self.buffers.take();
+        msg.extend_from_slice(&self.read_buf[self.read_pos..self.read_pos + msg_size]);
+        self.consume(msg_size);
+
+        #[cfg(feature = "metrics")]
+        self.record(|m| m.record_message(Direction::Received, &header.command, msg_size));
+
+        if self.verify_checksums && !self.checksum_matches(&msg, &header) {
+            self.buffers.give(msg);
+            let error = format!("Checksum mismatch in {} message", header.command);
+            return Err(self.invalid_frame(error));
+        }
+
+        Ok(Some((header, msg)))
+    }
+
+    // A peer on another network, or sending frames larger than can be
+    // accepted, is disconnected as Core does. What it sent is dropped
+    // rather than read again, and later reads fail.
+    fn break_framing(&mut self, error: String) -> Error {
+        warn!(%error, "disconnecting peer sending invalid frames");
+        #[cfg(feature = "metrics")]
+        self.record(|m| m.decode_error());
+        self.broken = true;
+        self.read_buf.clear();
+        self.read_pos = 0;
+        self.emit(Event::Disconnected(DisconnectReason::Error(error.clone())));
+        Error::with_msg(ErrorKind::ProtocolErr, error)
+    }
+
+    fn invalid_frame(&self, error: String) -> Error {
+        warn!(%error, "dropping invalid frame");
+        #[cfg(feature = "metrics")]
+        self.record(|m| m.decode_error());
+        Error::with_msg(ErrorKind::ProtocolErr, error)
+    }
+
+    fn dump_frame(&self, command: &Command, error: &str, frame: &[u8]) {
+        let Some(dir) = &self.dump_dir else {
+            return;
+        };
+        let peer = self.transport.peer_addr().ok();
+        #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+        match dump::dump_frame(dir, peer, command, error, frame) {
+            Ok(path) => {
+                info!(path = %path.display(), "dumped undecodable frame");
+            }
+            Err(e) => {
+                warn!(error = %e, "could not dump undecodable frame");
+            }
+        }
+    }
+
+    fn checksum_matches(&mut self, msg: &[u8], header: &BitcoinHeader) -> bool {
+        self.checksum.update(&msg[HEADER_SIZE..]);
+        self.checksum.finalize_reset() == header.check_sum
+    }
+}
+
+fn expects_response(request: &BitcoinPayload) -> bool {
+    matches!(
+        request,
+        BitcoinPayload::Ping(_)
+            | BitcoinPayload::GetAddr
+            | BitcoinPayload::GetHeaders(_)
+            | BitcoinPayload::GetData(_)
+    )
+}
+
+fn is_response(request: &BitcoinPayload, reply: &BitcoinPayload, peer_ip: Option<IpAddr>) -> bool {
+    match (request, reply) {
+        (BitcoinPayload::Ping(nonce), BitcoinPayload::Pong(reply_nonce)) => nonce == reply_nonce,
+        // Core advertises its own address unprompted after the handshake,
+        // which may well arrive before the answer.
+        (BitcoinPayload::GetAddr, BitcoinPayload::Addr(reply)) => match &reply.addr_list[..] {
+            [element] => {
+                Some(element.addr.addr.ip().to_canonical()) != peer_ip.map(|ip| ip.to_canonical())
+            }
+            _ => true,
+        },
+        // The headers follow the locator hash the peer found first, or
+        // there are none if it has nothing past it.
+        (BitcoinPayload::GetHeaders(request), BitcoinPayload::Headers(reply)) => reply
+            .headers
+            .first()
+            .is_none_or(|entry| request.locator.contains(&entry.header.prev_block)),
+        (BitcoinPayload::GetData(request), BitcoinPayload::NotFound(reply)) => {
+            reply.inventory.iter().any(|element| {
+                request
+                    .inventory
+                    .iter()
+                    .any(|requested| requested.hash == element.hash)
+            })
+        }
+        // Blocks are asked for by their header's hash, transactions by
+        // txid or, for wtx, by the hash of all of them.
+        (BitcoinPayload::GetData(request), BitcoinPayload::Unknown { command, payload }) => {
+            let block = (*command == *"block").then(|| payload.get(..80).map(sha256d));
+            let tx = (*command == *"tx").then(|| (txid(payload), sha256d(payload)));
+            request
+                .inventory
+                .iter()
+                .any(|element| match (&element.kind, block, tx) {
+                    (InventoryKind::Block | InventoryKind::WitnessBlock, Some(hash), _) => {
+                        hash == Some(element.hash)
+                    }
+                    (InventoryKind::Tx | InventoryKind::WitnessTx, _, Some((txid, _))) => {
+                        txid == Some(element.hash)
+                    }
+                    (InventoryKind::WTx, _, Some((_, wtxid))) => wtxid == element.hash,
+                    _ => false,
+                })
+        }
+        _ => false,
+    }
+}
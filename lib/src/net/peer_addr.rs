@@ -0,0 +1,141 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+
+// A peer the way a user gives it: an IP address or a host name, with a
+// port or without one. Any of
+//
+//     203.0.113.5          203.0.113.5:8333
+//     2001:db8::1          [2001:db8::1]:8333    [2001:db8::1]
+//     seed.example.org     seed.example.org:8333
+//
+// A missing port is filled in with the default port of the network the
+// peer is on, e.g. Network::default_port().
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerAddr {
+    Ip(SocketAddr),
+    Host { name: String, port: u16 },
+}
+
+impl PeerAddr {
+    pub fn parse(s: &str, default_port: u16) -> io::Result<PeerAddr> {
+        let invalid = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid {what} in \"{s}\""),
+            )
+        };
+        let parse_port = |port: &str| port.parse::<u16>().map_err(|_| invalid("port"));
+
+        if let Some(rest) = s.strip_prefix('[') {
+            let (ip, port) = rest.split_once(']').ok_or_else(|| invalid("address"))?;
+            let ip: Ipv6Addr = ip.parse().map_err(|_| invalid("IPv6 address"))?;
+            let port = match port {
+                "" => default_port,
+                _ => parse_port(port.strip_prefix(':').ok_or_else(|| invalid("port"))?)?,
+            };
+            return Ok(PeerAddr::Ip(SocketAddr::new(ip.into(), port)));
+        }
+
+        // Bare IPv6 addresses have colons of their own, so they can't
+        // have a port.
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(PeerAddr::Ip(SocketAddr::new(ip, default_port)));
+        }
+
+        let (host, port) = match s.rsplit_once(':') {
+            Some((host, port)) => (host, parse_port(port)?),
+            None => (s, default_port),
+        };
+        if let Ok(ip) = host.parse::<Ipv4Addr>() {
+            return Ok(PeerAddr::Ip(SocketAddr::new(ip.into(), port)));
+        }
+        if host.is_empty() || host.contains(|c: char| "[]:/".contains(c) || c.is_whitespace()) {
+            return Err(invalid("address"));
+        }
+
+        Ok(PeerAddr::Host {
+            name: host.to_string(),
+            port,
+        })
+    }
+
+    // The addresses to try connecting to, in order. Looking up a host
+    // name blocks.
+    pub fn resolve(&self) -> io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<SocketAddr> = match self {
+            PeerAddr::Ip(addr) => return Ok(vec![*addr]),
+            PeerAddr::Host { name, port } => (name.as_str(), *port).to_socket_addrs()?.collect(),
+        };
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no addresses found for {self}"),
+            ));
+        }
+        Ok(addrs)
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Ip(addr) => write!(f, "{addr}"),
+            PeerAddr::Host { name, port } => write!(f, "{name}:{port}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> PeerAddr {
+        PeerAddr::Ip(s.parse().unwrap())
+    }
+
+    #[test]
+    fn parses_ips_with_and_without_port() {
+        let parse = |s| PeerAddr::parse(s, 18333).unwrap();
+
+        assert_eq!(parse("203.0.113.5"), ip("203.0.113.5:18333"));
+        assert_eq!(parse("203.0.113.5:8333"), ip("203.0.113.5:8333"));
+        assert_eq!(parse("2001:db8::1"), ip("[2001:db8::1]:18333"));
+        assert_eq!(parse("[2001:db8::1]"), ip("[2001:db8::1]:18333"));
+        assert_eq!(parse("[2001:db8::1]:8333"), ip("[2001:db8::1]:8333"));
+    }
+
+    #[test]
+    fn parses_host_names() {
+        assert_eq!(
+            PeerAddr::parse("seed.example.org", 8333).unwrap(),
+            PeerAddr::Host {
+                name: "seed.example.org".into(),
+                port: 8333
+            }
+        );
+        assert_eq!(
+            PeerAddr::parse("seed.example.org:18444", 8333)
+                .unwrap()
+                .to_string(),
+            "seed.example.org:18444"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        for s in [
+            "",
+            ":8333",
+            "203.0.113.5:",
+            "203.0.113.5:99999",
+            "seed.example.org:x",
+            "[203.0.113.5]",
+            "[2001:db8::1",
+            "[2001:db8::1]8333",
+            "a b",
+        ] {
+            assert!(PeerAddr::parse(s, 8333).is_err(), "{s:?}");
+        }
+    }
+}
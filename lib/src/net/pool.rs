@@ -0,0 +1,30 @@
+// Most buffers kept, enough for a burst of messages read back to back.
+const MAX_POOLED: usize = 8;
+// Buffers that grew past this, for a block say, are let go rather than
+// kept around for the rare next one.
+const MAX_POOLED_CAPACITY: usize = 1 << 20;
+
+// Byte buffers kept for reuse, so that reading a message doesn't have to
+// allocate for its frame every time.
+#[derive(Debug, Default)]
+pub(super) struct BufferPool {
+    free: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    // An empty buffer, with the capacity of an earlier one if any is free.
+    pub(super) fn take(&mut self) -> Vec<u8> {
+        self.free.pop().unwrap_or_default()
+    }
+
+    pub(super) fn give(&mut self, mut buf: Vec<u8>) {
+        if buf.capacity() == 0
+            || buf.capacity() > MAX_POOLED_CAPACITY
+            || self.free.len() == MAX_POOLED
+        {
+            return;
+        }
+        buf.clear();
+        self.free.push(buf);
+    }
+}
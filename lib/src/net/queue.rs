@@ -0,0 +1,120 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use crate::types::Command;
+
+// How many bytes may wait to be written to one peer by default. Core
+// stops reading from a peer whose send buffer passes 5 MB.
+pub const DEFAULT_MAX_QUEUED_BYTES: usize = 4 * 1024 * 1024;
+
+// The order queued messages are written in, highest first. Messages of
+// the same priority keep the order they were sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    // The handshake and keepalives, which must not wait behind bulk data.
+    High,
+    Normal,
+    // Gossip that can be dropped when the peer doesn't keep up.
+    Low,
+}
+
+impl Priority {
+    pub fn of(command: &Command) -> Priority {
+        match command {
+            Command::Version | Command::VerAck | Command::Ping | Command::Pong => Priority::High,
+            Command::Addr | Command::Inv => Priority::Low,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+// Encoded messages waiting for the transport to take them, so that a
+// peer that reads slowly doesn't stall whoever is sending to it.
+#[derive(Debug)]
+pub(super) struct OutboundQueue {
+    queues: [VecDeque<Vec<u8>>; 3],
+    // The message being written and how much of it is written already.
+    // It is finished before anything else, even of higher priority, or
+    // the stream would be corrupt.
+    partial: Option<(Vec<u8>, usize)>,
+    len: usize,
+    max_len: usize,
+}
+
+impl OutboundQueue {
+    pub(super) fn new() -> OutboundQueue {
+        OutboundQueue {
+            queues: Default::default(),
+            partial: None,
+            len: 0,
+            max_len: DEFAULT_MAX_QUEUED_BYTES,
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(super) fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    // Queues `blob` unless that would go over the size limit. To make
+    // room, low priority messages are dropped, newest first, for ones of
+    // higher priority. High priority messages are always queued.
+    pub(super) fn push(&mut self, priority: Priority, blob: Vec<u8>) -> bool {
+        let droppable: usize = match priority {
+            Priority::Low => 0,
+            _ => self.queues[Priority::Low as usize]
+                .iter()
+                .map(Vec::len)
+                .sum(),
+        };
+        if self.len - droppable + blob.len() > self.max_len && priority != Priority::High {
+            return false;
+        }
+
+        while self.len + blob.len() > self.max_len && priority < Priority::Low {
+            let Some(dropped) = self.queues[Priority::Low as usize].pop_back() else {
+                break;
+            };
+            self.len -= dropped.len();
+        }
+
+        self.len += blob.len();
+        self.queues[priority as usize].push_back(blob);
+        true
+    }
+
+    // Writes as much as `writer` takes without blocking. Returns once the
+    // queue is empty or the writer would block.
+    pub(super) fn write_to(&mut self, writer: &mut impl Write) -> io::Result<()> {
+        loop {
+            let (blob, mut written) = match self.partial.take() {
+                Some(partial) => partial,
+                None => match self.queues.iter_mut().find_map(|queue| queue.pop_front()) {
+                    Some(blob) => (blob, 0),
+                    None => return Ok(()),
+                },
+            };
+
+            while written < blob.len() {
+                match writer.write(&blob[written..]) {
+                    Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Ok(n) => {
+                        written += n;
+                        self.len -= n;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        self.partial = Some((blob, written));
+                        return match e.kind() {
+                            io::ErrorKind::WouldBlock => Ok(()),
+                            _ => Err(e),
+                        };
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use super::{unix_time, Event};
+use crate::crypto::sha256d;
+use crate::netgroup::AddressType;
+use crate::p2p::messages::BitcoinPayload;
+
+pub const RECORD_HEADER: &str = "timestamp,peer,event,details";
+
+// Appends what peers announce to a CSV file for offline analysis, one
+// row per inventory item, address or block:
+//
+//     timestamp,peer,event,details
+//     1700000000,203.0.113.1:8333,inv,Tx 1f2e...
+//     1700000003,203.0.113.1:8333,addr,198.51.100.7:8333 ipv4 1699999000
+//     1700000009,203.0.113.1:8333,block,00000000...a3 1234567
+//
+// Addresses carry their type and timestamp, blocks their hash and size.
+// Other events are not recorded.
+pub struct EventRecorder<W: Write = BufWriter<File>> {
+    writer: W,
+}
+
+impl EventRecorder {
+    // Appends to `path`, writing the header first if the file is new or
+    // empty.
+    pub fn append(path: impl AsRef<Path>) -> io::Result<EventRecorder> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let empty = file.metadata()?.len() == 0;
+
+        let mut recorder = EventRecorder::new(BufWriter::new(file));
+        if empty {
+            writeln!(recorder.writer, "{RECORD_HEADER}")?;
+        }
+        Ok(recorder)
+    }
+}
+
+impl<W: Write> EventRecorder<W> {
+    pub fn new(writer: W) -> EventRecorder<W> {
+        EventRecorder { writer }
+    }
+
+    pub fn record(&mut self, peer: SocketAddr, event: &Event) -> io::Result<()> {
+        let now = unix_time();
+
+        match event {
+            Event::InvReceived(inv) => {
+                for element in &inv.inventory {
+                    let details = format!("{:?} {}", element.kind, element.hash);
+                    self.row(now, peer, "inv", &details)?;
+                }
+            }
+            Event::AddrReceived(addr) => {
+                for element in &addr.addr_list {
+                    let addr = element.addr.addr;
+                    let details = format!(
+                        "{addr} {} {}",
+                        AddressType::of(&addr.ip()),
+                        element.timestamp
+                    );
+                    self.row(now, peer, "addr", &details)?;
+                }
+            }
+            Event::MessageReceived(msg) => match &msg.payload {
+                BitcoinPayload::Unknown { command, payload }
+                    if command == "block" && payload.len() >= 80 =>
+                {
+                    let details = format!("{} {}", sha256d(&payload[..80]), payload.len());
+                    self.row(now, peer, "block", &details)?;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn row(
+        &mut self,
+        timestamp: i64,
+        peer: SocketAddr,
+        event: &str,
+        details: &str,
+    ) -> io::Result<()> {
+        // Addresses, hashes and inventory kinds never contain commas or
+        // quotes, so nothing needs escaping.
+        writeln!(self.writer, "{timestamp},{peer},{event},{details}")
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use rand::{Rng, RngCore};
+
+// Same bounds as Bitcoin Core: a handful of peers must agree before the
+// clock is adjusted at all, and never by more than 70 minutes.
+const MAX_SAMPLES: usize = 200;
+const MIN_SAMPLES: usize = 5;
+const MAX_ADJUSTMENT: i64 = 70 * 60;
+
+pub fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+// As the CLI shows durations, e.g. "3h12m5s".
+pub fn format_duration(secs: u64) -> String {
+    format!("{}h{}m{}s", secs / 3600, (secs % 3600) / 60, secs % 60)
+}
+
+// How long before `now` the unix time `timestamp` is. Timestamps come
+// from peers, which can claim any time, so ones in the future are
+// written as "in 0h5m0s" rather than wrapping around or panicking.
+pub fn format_age(timestamp: i64, now: i64) -> String {
+    let secs = now.abs_diff(timestamp);
+    if timestamp > now {
+        format!("in {}", format_duration(secs))
+    } else {
+        format_duration(secs)
+    }
+}
+
+// A random delay averaging `mean`, exponentially distributed like Core's
+// timers for trickled messages, so their timing gives nothing away.
+pub(super) fn random_delay(rng: &mut dyn RngCore, mean: Duration) -> Duration {
+    let uniform: f64 = rng.gen();
+    mean.mul_f64(-(1.0 - uniform).ln())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AdjustedTime {
+    samples: VecDeque<(IpAddr, i64)>,
+}
+
+impl AdjustedTime {
+    pub fn new() -> AdjustedTime {
+        AdjustedTime::default()
+    }
+
+    // Only one sample is kept per address, so a peer reconnecting over and
+    // over can't drag the median on its own.
+    pub fn add_sample(&mut self, ip: IpAddr, offset: i64) {
+        self.samples.retain(|(sample_ip, _)| *sample_ip != ip);
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((ip, offset));
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn median_offset(&self) -> Option<i64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut offsets: Vec<i64> = self.samples.iter().map(|(_, offset)| *offset).collect();
+        offsets.sort_unstable();
+
+        let mid = offsets.len() / 2;
+        if offsets.len().is_multiple_of(2) {
+            Some((offsets[mid - 1] + offsets[mid]) / 2)
+        } else {
+            Some(offsets[mid])
+        }
+    }
+
+    pub fn offset(&self) -> i64 {
+        match self.median_offset() {
+            Some(median) if self.samples.len() >= MIN_SAMPLES && median.abs() <= MAX_ADJUSTMENT => {
+                median
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn now(&self) -> i64 {
+        unix_time() + self.offset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_ages_in_the_past_and_future() {
+        assert_eq!(format_age(1_000, 4_725), "1h2m5s");
+        assert_eq!(format_age(1_000, 1_000), "0h0m0s");
+        assert_eq!(format_age(1_300, 1_000), "in 0h5m0s");
+        assert_eq!(format_age(u32::MAX as i64, 0), "in 1193046h28m15s");
+    }
+}
@@ -0,0 +1,72 @@
+use std::mem;
+use std::time::{Duration, Instant};
+
+use rand::seq::SliceRandom;
+use rand::RngCore;
+
+use super::time::random_delay;
+
+// Core's averages between announcements to a peer: transactions every
+// 5 seconds to inbound peers and every 2 to outbound ones, addresses
+// every 30 seconds.
+pub const INBOUND_INV_INTERVAL: Duration = Duration::from_secs(5);
+pub const OUTBOUND_INV_INTERVAL: Duration = Duration::from_secs(2);
+pub const ADDR_INTERVAL: Duration = Duration::from_secs(30);
+
+// Announcements held back and sent in batches at random intervals, as
+// Core does, so that when they go out doesn't tell the peer when we
+// learnt of them.
+#[derive(Debug)]
+pub(super) struct Trickle<E> {
+    queued: Vec<E>,
+    mean: Duration,
+    next: Instant,
+}
+
+impl<E> Trickle<E> {
+    pub(super) fn new(mean: Duration, now: Instant) -> Trickle<E> {
+        Trickle {
+            queued: vec![],
+            mean,
+            next: now,
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    // Whatever is queued after a quiet spell, with the timer long run
+    // out, still waits for a fresh one rather than going out at once.
+    pub(super) fn push(&mut self, element: E, now: Instant, rng: &mut dyn RngCore) {
+        if self.queued.is_empty() && now >= self.next {
+            self.next = now + random_delay(rng, self.mean);
+        }
+        self.queued.push(element);
+    }
+
+    // The next batch is due at a time drawn anew for the new mean.
+    pub(super) fn set_mean(&mut self, mean: Duration, now: Instant, rng: &mut dyn RngCore) {
+        self.mean = mean;
+        self.next = now + random_delay(rng, mean);
+    }
+
+    // Up to `max` of the queued elements if the timer went off, in random
+    // order so that it doesn't give away the order they were queued in.
+    // The rest wait for the next time.
+    pub(super) fn take_if_due(
+        &mut self,
+        max: usize,
+        now: Instant,
+        rng: &mut dyn RngCore,
+    ) -> Option<Vec<E>> {
+        if self.queued.is_empty() || now < self.next {
+            return None;
+        }
+
+        self.next = now + random_delay(rng, self.mean);
+        self.queued.shuffle(rng);
+        let rest = self.queued.split_off(self.queued.len().min(max));
+        Some(mem::replace(&mut self.queued, rest))
+    }
+}
@@ -0,0 +1,169 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// What kind of network an address belongs to, for deciding how much to
+// trust a set of peers to be independent. Tor v3, I2P and CJDNS addresses
+// only come in addrv2 messages (BIP155), see from_bip155().
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressType {
+    Ipv4,
+    Ipv6,
+    TorV3,
+    I2p,
+    Cjdns,
+    // Loopback and link-local.
+    Local,
+    // RFC1918, shared (RFC6598) and unique local (RFC4193) ranges.
+    Private,
+    // Unspecified, documentation, multicast and reserved ranges.
+    Unroutable,
+}
+
+impl AddressType {
+    pub fn of(ip: &IpAddr) -> AddressType {
+        match ip {
+            IpAddr::V4(ip) => classify_v4(ip),
+            IpAddr::V6(ip) => classify_v6(ip),
+        }
+    }
+
+    // The network ids addrv2 messages use.
+    pub fn from_bip155(network_id: u8) -> Option<AddressType> {
+        match network_id {
+            1 => Some(AddressType::Ipv4),
+            2 => Some(AddressType::Ipv6),
+            4 => Some(AddressType::TorV3),
+            5 => Some(AddressType::I2p),
+            6 => Some(AddressType::Cjdns),
+            _ => None,
+        }
+    }
+
+    pub fn is_routable(&self) -> bool {
+        !matches!(
+            self,
+            AddressType::Local | AddressType::Private | AddressType::Unroutable
+        )
+    }
+
+    // Core's names where it has one, as in getpeerinfo.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AddressType::Ipv4 => "ipv4",
+            AddressType::Ipv6 => "ipv6",
+            AddressType::TorV3 => "onion",
+            AddressType::I2p => "i2p",
+            AddressType::Cjdns => "cjdns",
+            AddressType::Local => "local",
+            AddressType::Private => "private",
+            AddressType::Unroutable => "unroutable",
+        }
+    }
+}
+
+impl fmt::Display for AddressType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn classify_v4(ip: &Ipv4Addr) -> AddressType {
+    let [a, b, ..] = ip.octets();
+
+    if ip.is_loopback() || ip.is_link_local() {
+        AddressType::Local
+    } else if ip.is_private() || (a == 100 && (64..128).contains(&b)) {
+        AddressType::Private
+    } else if ip.is_unspecified()
+        || a == 0
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a >= 240
+    {
+        AddressType::Unroutable
+    } else {
+        AddressType::Ipv4
+    }
+}
+
+fn classify_v6(ip: &Ipv6Addr) -> AddressType {
+    if let Some(ip) = embedded_v4(ip) {
+        return classify_v4(&ip);
+    }
+
+    let segments = ip.segments();
+    if ip.is_loopback() || segments[0] & 0xffc0 == 0xfe80 {
+        AddressType::Local
+    } else if segments[0] & 0xfe00 == 0xfc00 {
+        AddressType::Private
+    } else if ip.is_unspecified()
+        || ip.is_multicast()
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+    {
+        AddressType::Unroutable
+    } else {
+        AddressType::Ipv6
+    }
+}
+
+// The IPv4 address behind an IPv4-mapped, 6to4 or Teredo address, so
+// those group with the IPv4 network they really come from.
+fn embedded_v4(ip: &Ipv6Addr) -> Option<Ipv4Addr> {
+    let octets = ip.octets();
+    let segments = ip.segments();
+
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        Some(ip)
+    } else if segments[0] == 0x2002 {
+        Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5]))
+    } else if segments[0] == 0x2001 && segments[1] == 0 {
+        // Teredo stores the address inverted.
+        let [a, b, c, d] = [octets[12], octets[13], octets[14], octets[15]];
+        Some(Ipv4Addr::new(!a, !b, !c, !d))
+    } else {
+        None
+    }
+}
+
+// Addresses in the same group are likely run by the same operator: the
+// same IPv4 /16 or IPv6 /32, as Core groups them without an ASN map.
+// Peers should be spread across as many groups as possible.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NetGroup(pub Vec<u8>);
+
+impl NetGroup {
+    pub fn of(ip: &IpAddr) -> NetGroup {
+        let class = AddressType::of(ip);
+        if !class.is_routable() {
+            // Each of these is one group of its own.
+            return NetGroup(vec![class as u8]);
+        }
+
+        let v4_group = |ip: Ipv4Addr| {
+            let [a, b, ..] = ip.octets();
+            NetGroup(vec![AddressType::Ipv4 as u8, a, b])
+        };
+        match ip {
+            IpAddr::V4(ip) => v4_group(*ip),
+            IpAddr::V6(ip) => match embedded_v4(ip) {
+                Some(v4) => v4_group(v4),
+                None => {
+                    let mut group = vec![AddressType::Ipv6 as u8];
+                    group.extend(&ip.octets()[..4]);
+                    NetGroup(group)
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Display for NetGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in &self.0 {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
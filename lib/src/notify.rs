@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::crypto::sha256d;
+use crate::net::Event;
+use crate::p2p::messages::BitcoinPayload;
+use crate::types::InventoryKind;
+
+// Subscribers that don't keep up are dropped rather than stalling the
+// connection that publishes.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Topic {
+    HashTx,
+    HashBlock,
+    RawTx,
+    RawBlock,
+}
+
+impl Topic {
+    pub const ALL: [Topic; 4] = [
+        Topic::HashTx,
+        Topic::HashBlock,
+        Topic::RawTx,
+        Topic::RawBlock,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Topic::HashTx => "hashtx",
+            Topic::HashBlock => "hashblock",
+            Topic::RawTx => "rawtx",
+            Topic::RawBlock => "rawblock",
+        }
+    }
+}
+
+impl FromStr for Topic {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Topic::ALL
+            .into_iter()
+            .find(|topic| topic.as_str() == s)
+            .ok_or(())
+    }
+}
+
+struct Subscriber {
+    stream: TcpStream,
+    topics: Vec<Topic>,
+}
+
+#[derive(Default)]
+struct State {
+    subscribers: Vec<Subscriber>,
+    sequences: BTreeMap<Topic, u32>,
+}
+
+// A small pub/sub server modelled on bitcoind's ZMQ notifications, over
+// plain TCP. A subscriber connects and sends one line with the topics it
+// wants, separated by spaces (an empty line subscribes to everything). It
+// then receives one line per notification:
+//
+//     <topic> <sequence> <hex body>
+//
+// Sequence numbers count up per topic, so gaps reveal dropped messages.
+// Hashes are sent in the usual byte-reversed display order.
+#[derive(Default)]
+pub struct Notifier {
+    state: Mutex<State>,
+}
+
+impl Notifier {
+    pub fn new() -> Arc<Notifier> {
+        Arc::new(Notifier::default())
+    }
+
+    pub fn serve(self: &Arc<Self>, addr: SocketAddr) -> io::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(addr)?;
+        let notifier = self.clone();
+
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                // Waiting for the subscription line happens off the accept
+                // loop, so a silent client can't hold up others.
+                let notifier = notifier.clone();
+                thread::spawn(move || notifier.add_subscriber(stream));
+            }
+        }))
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.state.lock().unwrap().subscribers.len()
+    }
+
+    fn add_subscriber(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(SUBSCRIBE_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+
+        let topics: Vec<Topic> = if line.trim().is_empty() {
+            Topic::ALL.to_vec()
+        } else {
+            line.split_whitespace()
+                .filter_map(|s| s.parse().ok())
+                .collect()
+        };
+
+        self.state
+            .lock()
+            .unwrap()
+            .subscribers
+            .push(Subscriber { stream, topics });
+        Ok(())
+    }
+
+    pub fn publish(&self, topic: Topic, body: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+
+        let sequence = state.sequences.entry(topic).or_default();
+        let mut line = format!("{} {sequence} ", topic.as_str());
+        *sequence = sequence.wrapping_add(1);
+
+        for b in body {
+            write!(line, "{b:02x}").unwrap();
+        }
+        line.push('\n');
+
+        state.subscribers.retain_mut(|subscriber| {
+            !subscriber.topics.contains(&topic)
+                || subscriber.stream.write_all(line.as_bytes()).is_ok()
+        });
+    }
+
+    // Publishes what a connection event reveals: announced transactions
+    // and blocks by hash, and the raw contents of tx and block messages.
+    pub fn publish_event(&self, event: &Event) {
+        match event {
+            Event::InvReceived(inv) => {
+                for element in &inv.inventory {
+                    let topic = match element.kind {
+                        InventoryKind::Tx | InventoryKind::WitnessTx => Topic::HashTx,
+                        InventoryKind::Block
+                        | InventoryKind::WitnessBlock
+                        | InventoryKind::CmpctBlock => Topic::HashBlock,
+                        _ => continue,
+                    };
+                    self.publish(topic, &reversed(&element.hash.0));
+                }
+            }
+            Event::MessageReceived(msg) => match &msg.payload {
+                BitcoinPayload::Unknown { command, payload } if command == "tx" => {
+                    self.publish(Topic::RawTx, payload);
+                }
+                BitcoinPayload::Unknown { command, payload } if command == "block" => {
+                    self.publish(Topic::RawBlock, payload);
+                    if payload.len() >= 80 {
+                        self.publish(Topic::HashBlock, &reversed(&sha256d(&payload[..80]).0));
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+fn reversed(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().rev().copied().collect()
+}
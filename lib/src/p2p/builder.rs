@@ -0,0 +1,209 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+use crate::p2p::messages::{Addr, AddrElement, Inv, Version};
+use crate::types::{InventoryElement, NetAddr, Services};
+
+pub const PROTOCOL_VERSION: u32 = 70014;
+
+// Limits enforced by Bitcoin Core; peers disconnect or ban on anything
+// larger, so it's better to refuse to build such a message at all.
+pub const MAX_USER_AGENT_LEN: usize = 256;
+pub const MAX_ADDR: usize = 1000;
+pub const MAX_INV: usize = 50000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    UserAgentTooLong(usize),
+    TooManyAddresses(usize),
+    TooManyInventoryElements(usize),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::UserAgentTooLong(len) => write!(
+                f,
+                "user agent is {len} bytes, at most {MAX_USER_AGENT_LEN} are allowed"
+            ),
+            BuildError::TooManyAddresses(count) => {
+                write!(f, "{count} addresses, at most {MAX_ADDR} are allowed")
+            }
+            BuildError::TooManyInventoryElements(count) => {
+                write!(
+                    f,
+                    "{count} inventory elements, at most {MAX_INV} are allowed"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildError {}
+
+fn unspecified_addr() -> NetAddr {
+    NetAddr {
+        services: Services::default(),
+        addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VersionBuilder {
+    version: Version,
+}
+
+impl Version {
+    pub fn builder() -> VersionBuilder {
+        #[cfg(feature = "std")]
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        #[cfg(not(feature = "std"))]
+        let time = 0;
+
+        VersionBuilder {
+            version: Version {
+                proto_ver: PROTOCOL_VERSION,
+                services: Services::default(),
+                time,
+                remote: unspecified_addr(),
+                local: unspecified_addr(),
+                nonce: 0,
+                user_agent: String::new(),
+                last_block: 0,
+                relay: true,
+            },
+        }
+    }
+}
+
+impl VersionBuilder {
+    pub fn proto_ver(mut self, proto_ver: u32) -> VersionBuilder {
+        self.version.proto_ver = proto_ver;
+        self
+    }
+
+    // Also applied to the local address, which advertises the same services.
+    pub fn services(mut self, services: Services) -> VersionBuilder {
+        self.version.local.services = services.clone();
+        self.version.services = services;
+        self
+    }
+
+    pub fn time(mut self, time: u64) -> VersionBuilder {
+        self.version.time = time;
+        self
+    }
+
+    pub fn remote(mut self, remote: NetAddr) -> VersionBuilder {
+        self.version.remote = remote;
+        self
+    }
+
+    pub fn local(mut self, local: NetAddr) -> VersionBuilder {
+        self.version.local = local;
+        self
+    }
+
+    pub fn nonce(mut self, nonce: u64) -> VersionBuilder {
+        self.version.nonce = nonce;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl ToString) -> VersionBuilder {
+        self.version.user_agent = user_agent.to_string();
+        self
+    }
+
+    pub fn last_block(mut self, last_block: u32) -> VersionBuilder {
+        self.version.last_block = last_block;
+        self
+    }
+
+    pub fn relay(mut self, relay: bool) -> VersionBuilder {
+        self.version.relay = relay;
+        self
+    }
+
+    pub fn build(self) -> Result<Version, BuildError> {
+        let len = self.version.user_agent.len();
+        if len > MAX_USER_AGENT_LEN {
+            return Err(BuildError::UserAgentTooLong(len));
+        }
+
+        Ok(self.version)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AddrBuilder {
+    addr_list: Vec<AddrElement>,
+}
+
+impl Addr {
+    pub fn builder() -> AddrBuilder {
+        AddrBuilder::default()
+    }
+}
+
+impl AddrBuilder {
+    pub fn addr(mut self, timestamp: u32, addr: NetAddr) -> AddrBuilder {
+        self.addr_list.push(AddrElement { timestamp, addr });
+        self
+    }
+
+    pub fn addrs(mut self, addrs: impl IntoIterator<Item = AddrElement>) -> AddrBuilder {
+        self.addr_list.extend(addrs);
+        self
+    }
+
+    pub fn build(self) -> Result<Addr, BuildError> {
+        if self.addr_list.len() > MAX_ADDR {
+            return Err(BuildError::TooManyAddresses(self.addr_list.len()));
+        }
+
+        Ok(Addr {
+            addr_list: self.addr_list,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InvBuilder {
+    inventory: Vec<InventoryElement>,
+}
+
+impl Inv {
+    pub fn builder() -> InvBuilder {
+        InvBuilder::default()
+    }
+}
+
+impl InvBuilder {
+    pub fn element(mut self, element: InventoryElement) -> InvBuilder {
+        self.inventory.push(element);
+        self
+    }
+
+    pub fn elements(mut self, elements: impl IntoIterator<Item = InventoryElement>) -> InvBuilder {
+        self.inventory.extend(elements);
+        self
+    }
+
+    pub fn build(self) -> Result<Inv, BuildError> {
+        if self.inventory.len() > MAX_INV {
+            return Err(BuildError::TooManyInventoryElements(self.inventory.len()));
+        }
+
+        Ok(Inv {
+            inventory: self.inventory,
+        })
+    }
+}
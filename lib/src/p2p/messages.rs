@@ -0,0 +1,825 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+use crate::codec::{BitcoinType, ByteReader, DecodeError};
+use crate::crypto::{get_check_sum, sha256d, Checksum};
+use crate::p2p::builder::PROTOCOL_VERSION;
+use crate::trace::trace;
+use crate::types::{Command, Hash, InventoryElement, Magic, NetAddr, Services};
+
+pub const HEADER_SIZE: usize = 24;
+// Largest payload Core accepts, it disconnects peers sending more.
+pub const MAX_PROTOCOL_MESSAGE_LENGTH: usize = 4_000_000;
+// Most headers a peer sends in one headers message.
+pub const MAX_HEADERS_RESULTS: usize = 2000;
+
+#[derive(Debug, Clone, BitcoinType)]
+#[bitcoin(command = "version")]
+pub struct Version {
+    pub proto_ver: u32,
+    pub services: Services,
+    pub time: u64,
+    pub remote: NetAddr,
+    pub local: NetAddr,
+    pub nonce: u64,
+    #[bitcoin(with = "user_agent")]
+    pub user_agent: String,
+    pub last_block: u32,
+    pub relay: bool,
+}
+
+// The user agent is put in front of people, so the control characters
+// a peer could use to send escape sequences to a terminal are dropped,
+// and it is cut to MAX_USER_AGENT_LEN bytes.
+mod user_agent {
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    use crate::codec::{BitcoinType, ByteReader, DecodeError};
+    use crate::p2p::builder::MAX_USER_AGENT_LEN;
+
+    pub fn encode(user_agent: &str) -> Vec<u8> {
+        let mut ret = user_agent.len().to_blob();
+        ret.extend(user_agent.bytes());
+        ret
+    }
+
+    pub fn decode(blob: &mut ByteReader) -> Result<String, DecodeError> {
+        let user_agent = String::from_blob(blob)?;
+        let mut user_agent: String = user_agent.chars().filter(|c| !c.is_control()).collect();
+
+        let mut len = user_agent.len().min(MAX_USER_AGENT_LEN);
+        while !user_agent.is_char_boundary(len) {
+            len -= 1;
+        }
+        user_agent.truncate(len);
+        Ok(user_agent)
+    }
+}
+
+#[derive(Debug, Clone, BitcoinType)]
+#[bitcoin(command = "sendcmpct")]
+pub struct SendCmpct {
+    pub flag: bool,
+    pub integer: u64,
+}
+
+#[derive(Debug, Clone, BitcoinType)]
+#[bitcoin(command = "feefilter")]
+pub struct FeeFilter {
+    pub feerate: u64,
+}
+
+#[derive(Debug, Clone, BitcoinType)]
+#[bitcoin(command = "inv")]
+pub struct Inv {
+    pub inventory: Vec<InventoryElement>,
+}
+
+#[derive(Debug, Clone, BitcoinType)]
+pub struct AddrElement {
+    pub timestamp: u32,
+    pub addr: NetAddr,
+}
+
+#[derive(Debug, Clone, BitcoinType)]
+#[bitcoin(command = "addr")]
+pub struct Addr {
+    pub addr_list: Vec<AddrElement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, BitcoinType)]
+pub struct BlockHeader {
+    pub version: i32,
+    pub prev_block: Hash,
+    pub merkle_root: Hash,
+    pub time: u32,
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+impl BlockHeader {
+    pub fn hash(&self) -> Hash {
+        sha256d(&self.to_blob())
+    }
+}
+
+// On the wire each header in a headers message is followed by a
+// transaction count, always 0 as no transactions come with it. Decoding
+// a bare BlockHeader per entry would leave every following header off by
+// that one byte.
+#[derive(Debug, Clone, PartialEq, Eq, BitcoinType)]
+pub struct HeadersEntry {
+    pub header: BlockHeader,
+    pub tx_count: usize,
+}
+
+#[derive(Debug, Clone, BitcoinType)]
+#[bitcoin(command = "headers")]
+pub struct Headers {
+    pub headers: Vec<HeadersEntry>,
+}
+
+// Asks for the headers after the first hash in `locator` the peer has,
+// up to `stop` or MAX_HEADERS_RESULTS of them, all zeros for no stop.
+#[derive(Debug, Clone, PartialEq, Eq, BitcoinType)]
+#[bitcoin(command = "getheaders")]
+pub struct GetHeaders {
+    pub version: u32,
+    pub locator: Vec<Hash>,
+    pub stop: Hash,
+}
+
+// The block and tx messages that answer it aren't decoded, and come as
+// BitcoinPayload::Unknown with their raw contents.
+#[derive(Debug, Clone, BitcoinType)]
+#[bitcoin(command = "getdata")]
+pub struct GetData {
+    pub inventory: Vec<InventoryElement>,
+}
+
+// What the peer didn't have of a getdata.
+#[derive(Debug, Clone, BitcoinType)]
+#[bitcoin(command = "notfound")]
+pub struct NotFound {
+    pub inventory: Vec<InventoryElement>,
+}
+
+#[derive(Debug, Clone, BitcoinType)]
+pub struct BitcoinHeader {
+    pub magic: Magic,
+    pub command: Command,
+    pub size: u32,
+    pub check_sum: [u8; 4],
+}
+
+// The payload of a message, with the command that identifies it. Deriving
+// BitcoinType with #[bitcoin(command = "...")] implements this.
+pub trait Payload: BitcoinType {
+    const COMMAND: &'static str;
+}
+
+// Generates BitcoinPayload and how each variant is named, encoded and
+// decoded from one table, so adding a message only takes a line there.
+// A variant carries a Payload struct, whose command is taken from it,
+// or another type or nothing, with the command given after `=`.
+macro_rules! payloads {
+    ($($variant:ident $(($payload:ty))? $(= $command:literal)?,)*) => {
+        #[derive(Debug, Clone)]
+        pub enum BitcoinPayload {
+            $($variant $(($payload))?,)*
+            Unknown { command: Command, payload: Vec<u8> },
+        }
+
+        impl BitcoinPayload {
+            pub fn command(&self) -> Command {
+                match self {
+                    $(BitcoinPayload::$variant { .. } => {
+                        Command::from(payload_command!($($payload)? $(= $command)?))
+                    })*
+                    BitcoinPayload::Unknown { command, .. } => command.clone(),
+                }
+            }
+
+            fn encode_into(&self, blob: &mut Vec<u8>) {
+                match self {
+                    $(payload_pattern!(p, $variant $(, $payload)?) => {
+                        encode_payload!(blob, p $(, $payload)?)
+                    })*
+                    BitcoinPayload::Unknown { payload, .. } => blob.extend(payload),
+                }
+            }
+
+            fn encoded_size_hint(&self) -> usize {
+                match self {
+                    $(payload_pattern!(p, $variant $(, $payload)?) => {
+                        payload_size_hint!(p $(, $payload)?)
+                    })*
+                    BitcoinPayload::Unknown { payload, .. } => payload.len(),
+                }
+            }
+
+            // Decodes a payload that takes up all of `blob`. The payload
+            // is decoded on its own, so that a #[bitcoin(rest)] field
+            // ends where it does and any bytes left over are noticed.
+            pub fn decode_from(
+                command: &Command,
+                blob: &mut ByteReader,
+            ) -> Result<Self, DecodeError> {
+                // An unknown command can read as a known one up to its
+                // first NUL, but is still not that one.
+                let known = !matches!(command, Command::Unknown(_));
+                Ok(match command.as_str() {
+                    $(name if known && name == payload_command!($($payload)? $(= $command)?) => {
+                        decode_payload!(blob, $variant $(, $payload)?)
+                    })*
+                    _ => BitcoinPayload::Unknown {
+                        command: command.clone(),
+                        payload: blob.take_rest(),
+                    },
+                })
+            }
+        }
+    };
+}
+
+macro_rules! payload_command {
+    ($payload:ty) => {
+        <$payload as Payload>::COMMAND
+    };
+    ($payload:ty = $command:literal) => {
+        $command
+    };
+    (= $command:literal) => {
+        $command
+    };
+}
+
+macro_rules! payload_pattern {
+    ($p:ident, $variant:ident) => {
+        BitcoinPayload::$variant
+    };
+    ($p:ident, $variant:ident, $payload:ty) => {
+        BitcoinPayload::$variant($p)
+    };
+}
+
+macro_rules! encode_payload {
+    ($blob:ident, $p:ident) => {{}};
+    ($blob:ident, $p:ident, $payload:ty) => {
+        $blob.extend($p.to_blob())
+    };
+}
+
+macro_rules! payload_size_hint {
+    ($p:ident) => {
+        0
+    };
+    ($p:ident, $payload:ty) => {
+        $p.encoded_size_hint()
+    };
+}
+
+macro_rules! decode_payload {
+    ($blob:ident, $variant:ident) => {{
+        $blob.expect_end()?;
+        BitcoinPayload::$variant
+    }};
+    ($blob:ident, $variant:ident, $payload:ty) => {{
+        let payload = <$payload>::from_blob($blob)?;
+        $blob.expect_end()?;
+        BitcoinPayload::$variant(payload)
+    }};
+}
+
+payloads! {
+    Version(Version),
+    VerAck = "verack",
+    SendHeaders = "sendheaders",
+    SendCmpct(SendCmpct),
+    Ping(u64) = "ping",
+    Pong(u64) = "pong",
+    FeeFilter(FeeFilter),
+    Inv(Inv),
+    GetAddr = "getaddr",
+    Addr(Addr),
+    GetHeaders(GetHeaders),
+    Headers(Headers),
+    GetData(GetData),
+    NotFound(NotFound),
+}
+
+#[derive(Debug, Clone)]
+pub struct BitcoinMsg {
+    pub payload: BitcoinPayload,
+}
+
+impl BitcoinType for BitcoinMsg {
+    fn to_blob(&self) -> Vec<u8> {
+        self.encode(Magic::MAINNET)
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        BitcoinMsg::try_decode(blob, Magic::MAINNET)
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        HEADER_SIZE + self.payload.encoded_size_hint()
+    }
+}
+
+impl BitcoinMsg {
+    pub fn encode(&self, magic: Magic) -> Vec<u8> {
+        self.encode_with(magic, &mut Checksum::new())
+    }
+
+    // Serializes the payload straight after the header and checksums it in
+    // place, reusing `checksum` so that senders of many messages don't
+    // have to set up a hasher for each one.
+    pub fn encode_with(&self, magic: Magic, checksum: &mut Checksum) -> Vec<u8> {
+        let mut blob = Vec::with_capacity(self.encoded_size_hint());
+        blob.extend(magic.to_blob());
+
+        blob.extend(self.payload.command().to_blob());
+
+        // Size and checksum, filled in once the payload is written.
+        blob.extend([0; 8]);
+
+        self.payload.encode_into(&mut blob);
+
+        let size = (blob.len() - HEADER_SIZE) as u32;
+        checksum.update(&blob[HEADER_SIZE..]);
+
+        blob[16..20].copy_from_slice(&size.to_le_bytes());
+        blob[20..24].copy_from_slice(&checksum.finalize_reset());
+
+        blob
+    }
+
+    pub fn decode(blob: &mut ByteReader, magic: Magic) -> Self {
+        match BitcoinMsg::try_decode(blob, magic) {
+            Ok(msg) => msg,
+            Err(e) => panic!("Message is corrupted: {e}"),
+        }
+    }
+
+    // Like decode, but a frame cut short, for another network, with a
+    // wrong checksum or with a payload that doesn't decode is an error.
+    fn try_decode(blob: &mut ByteReader, magic: Magic) -> Result<Self, DecodeError> {
+        let header =
+            BitcoinHeader::from_blob(&mut ByteReader::new(blob.peek(HEADER_SIZE)?.to_vec()))?;
+        if header.magic != magic {
+            return Err(DecodeError::WrongMagic {
+                expected: magic,
+                got: header.magic,
+            });
+        }
+
+        let bulk = &blob.peek(HEADER_SIZE + header.size as usize)?[HEADER_SIZE..];
+
+        if get_check_sum(bulk) != header.check_sum {
+            return Err(DecodeError::ChecksumMismatch);
+        }
+
+        BitcoinMsg::try_decode_unchecked(blob)
+    }
+
+    // Decodes without looking at the magic or checksum, for callers that
+    // already verified them or chose not to.
+    pub fn decode_unchecked(blob: &mut ByteReader) -> Self {
+        match BitcoinMsg::try_decode_unchecked(blob) {
+            Ok(msg) => msg,
+            Err(e) => panic!("Message is corrupted: {e}"),
+        }
+    }
+
+    // Like decode_unchecked, but a payload that doesn't take up exactly
+    // the size in its header is an error. The whole frame is consumed
+    // either way.
+    pub fn try_decode_unchecked(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        let payload = LazyPayload::split_unchecked(blob)?.decode()?;
+        Ok(BitcoinMsg { payload })
+    }
+
+    pub fn getaddr() -> BitcoinMsg {
+        BitcoinMsg {
+            payload: BitcoinPayload::GetAddr,
+        }
+    }
+
+    pub fn ping(nonce: u64) -> BitcoinMsg {
+        BitcoinMsg {
+            payload: BitcoinPayload::Ping(nonce),
+        }
+    }
+
+    pub fn pong(nonce: u64) -> BitcoinMsg {
+        BitcoinMsg {
+            payload: BitcoinPayload::Pong(nonce),
+        }
+    }
+
+    pub fn verack() -> BitcoinMsg {
+        BitcoinMsg {
+            payload: BitcoinPayload::VerAck,
+        }
+    }
+
+    pub fn getheaders(locator: Vec<Hash>, stop: Hash) -> BitcoinMsg {
+        BitcoinMsg {
+            payload: BitcoinPayload::GetHeaders(GetHeaders {
+                version: PROTOCOL_VERSION,
+                locator,
+                stop,
+            }),
+        }
+    }
+
+    pub fn getdata(inventory: Vec<InventoryElement>) -> BitcoinMsg {
+        BitcoinMsg {
+            payload: BitcoinPayload::GetData(GetData { inventory }),
+        }
+    }
+
+    pub fn headers(headers: Vec<BlockHeader>) -> BitcoinMsg {
+        BitcoinMsg {
+            payload: BitcoinPayload::Headers(Headers {
+                headers: headers
+                    .into_iter()
+                    .map(|header| HeadersEntry {
+                        header,
+                        tx_count: 0,
+                    })
+                    .collect(),
+            }),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[deprecated(note = "use Version::builder(), which also validates the fields")]
+    pub fn version(
+        local: NetAddr,
+        remote: NetAddr,
+        user_agent: String,
+        nonce: u64,
+        last_block: u32,
+        relay: bool,
+    ) -> BitcoinMsg {
+        BitcoinMsg {
+            payload: BitcoinPayload::Version(Version {
+                proto_ver: PROTOCOL_VERSION,
+                time: SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+                services: local.services.clone(),
+                remote,
+                local,
+                nonce,
+                user_agent,
+                last_block,
+                relay,
+            }),
+        }
+    }
+}
+
+// A payload cut out of its frame but not decoded, for callers that only
+// look at some messages and don't want to pay for decoding the rest,
+// like the 50000 entries an inv can have. Nothing is checked until it
+// is decoded.
+#[derive(Debug, Clone)]
+pub struct LazyPayload {
+    command: Command,
+    bytes: Vec<u8>,
+}
+
+impl LazyPayload {
+    pub fn new(command: Command, bytes: Vec<u8>) -> LazyPayload {
+        LazyPayload { command, bytes }
+    }
+
+    // Takes a whole frame off `blob`, without looking at the magic or
+    // checksum.
+    pub fn split_unchecked(blob: &mut ByteReader) -> Result<LazyPayload, DecodeError> {
+        let header = BitcoinHeader::from_blob(blob)?;
+        let bytes = blob.take(header.size as usize)?.to_vec();
+        Ok(LazyPayload::new(header.command, bytes))
+    }
+
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn decode(self) -> Result<BitcoinPayload, DecodeError> {
+        trace!(command = %self.command, size = self.bytes.len(), "decoding payload");
+        BitcoinPayload::decode_from(&self.command, &mut ByteReader::new(self.bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::InventoryKind;
+
+    // Mainnet blocks 1 and 2 as they come in a headers message, each
+    // followed by its zero transaction count.
+    const BLOCKS_1_2: &str = "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d61900\
+                              00000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e8\
+                              57233e0e61bc6649ffff001d01e3629900010000004860eb18bf1b1620e37e94\
+                              90fc8a427514416fd75159ab86688e9a8300000000d5fdcc541e25de1c7a5add\
+                              edf24858b8bb665c9f36ef744ee42c316022c90f9bb0bc6649ffff001d08d2bd\
+                              6100";
+
+    fn parse_hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn headers_msg(entries: &[u8], count: usize) -> Vec<u8> {
+        let mut payload = count.to_blob();
+        payload.extend(entries);
+        BitcoinMsg {
+            payload: BitcoinPayload::Unknown {
+                command: "headers".into(),
+                payload,
+            },
+        }
+        .encode(Magic::MAINNET)
+    }
+
+    fn decode_headers(blob: Vec<u8>) -> Vec<HeadersEntry> {
+        let mut reader = ByteReader::new(blob);
+        let msg = BitcoinMsg::decode(&mut reader, Magic::MAINNET);
+        assert_eq!(reader.remaining(), 0);
+
+        match msg.payload {
+            BitcoinPayload::Headers(headers) => headers.headers,
+            payload => panic!("expected headers, got {payload:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_frames_through_from_blob() {
+        let ping = BitcoinMsg::ping(7);
+        let frame = ping.encode(Magic::TESTNET);
+        assert!(matches!(
+            BitcoinMsg::from_blob(&mut ByteReader::new(frame)),
+            Err(DecodeError::WrongMagic { expected, got })
+                if expected == Magic::MAINNET && got == Magic::TESTNET
+        ));
+
+        let mut frame = ping.encode(Magic::MAINNET);
+        frame[HEADER_SIZE] ^= 1;
+        assert!(matches!(
+            BitcoinMsg::from_blob(&mut ByteReader::new(frame)),
+            Err(DecodeError::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn keeps_unknown_inventory_types() {
+        let mut payload = 2usize.to_blob();
+        for code in [0x5u32, 0x7] {
+            payload.extend(code.to_blob());
+            payload.extend([0xab; 32]);
+        }
+        let blob = BitcoinMsg {
+            payload: BitcoinPayload::Unknown {
+                command: "inv".into(),
+                payload,
+            },
+        }
+        .encode(Magic::MAINNET);
+
+        let msg = BitcoinMsg::decode(&mut ByteReader::new(blob.clone()), Magic::MAINNET);
+        let BitcoinPayload::Inv(inv) = &msg.payload else {
+            panic!("expected inv, got {:?}", msg.payload);
+        };
+        assert!(matches!(inv.inventory[0].kind, InventoryKind::WTx));
+        assert!(matches!(inv.inventory[1].kind, InventoryKind::Unknown(0x7)));
+        assert_eq!(msg.encode(Magic::MAINNET), blob);
+    }
+
+    #[test]
+    fn keeps_unknown_commands_byte_for_byte() {
+        let commands: [[u8; 12]; 3] = [
+            *b"mystery\0\0\0\0\0",
+            *b"inv\0junk\0\0\0\0",
+            [0xff, 0xfe, b'x', 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        ];
+        for command in commands {
+            let blob = BitcoinMsg {
+                payload: BitcoinPayload::Unknown {
+                    command: Command::Unknown(command),
+                    payload: vec![1, 2, 3],
+                },
+            }
+            .encode(Magic::MAINNET);
+            assert_eq!(&blob[4..16], &command);
+
+            let msg = BitcoinMsg::decode(&mut ByteReader::new(blob.clone()), Magic::MAINNET);
+            assert_eq!(msg.payload.command(), Command::Unknown(command));
+            assert_eq!(msg.encode(Magic::MAINNET), blob);
+        }
+
+        assert_eq!("sendheaders".parse(), Ok(Command::SendHeaders));
+        assert_eq!("mystery".parse(), Ok(Command::from("mystery")));
+        assert!("waytoolongcommand".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn registers_payload_commands() {
+        assert_eq!(FeeFilter::COMMAND, "feefilter");
+
+        let msg = BitcoinMsg {
+            payload: BitcoinPayload::FeeFilter(FeeFilter { feerate: 1000 }),
+        };
+        let blob = msg.encode(Magic::MAINNET);
+        assert_eq!(&blob[4..13], b"feefilter");
+
+        let msg = BitcoinMsg::decode(&mut ByteReader::new(blob), Magic::MAINNET);
+        assert_eq!(msg.payload.command(), Command::FeeFilter);
+        assert!(matches!(
+            msg.payload,
+            BitcoinPayload::FeeFilter(FeeFilter { feerate: 1000 })
+        ));
+    }
+
+    // Shaped like BIP61's reject, whose data is whatever follows the
+    // reason.
+    #[derive(Debug, PartialEq, BitcoinType)]
+    struct Reject {
+        message: String,
+        code: u8,
+        reason: String,
+        #[bitcoin(rest)]
+        data: Vec<u8>,
+    }
+
+    #[test]
+    fn rest_field_has_no_length_prefix() {
+        let reject = Reject {
+            message: "tx".into(),
+            code: 0x10,
+            reason: "bad-txns".into(),
+            data: vec![0xab; 32],
+        };
+        let blob = reject.to_blob();
+        assert_eq!(blob.len(), 3 + 1 + 9 + 32);
+        assert_eq!(reject.encoded_size_hint(), blob.len());
+        assert_eq!(&blob[13..], &[0xab; 32]);
+
+        assert_eq!(Reject::from_blob(&mut ByteReader::new(blob)), Ok(reject));
+    }
+
+    mod big_endian {
+        use crate::codec::{ByteReader, DecodeError};
+
+        pub fn encode(port: &u16) -> Vec<u8> {
+            port.to_be_bytes().to_vec()
+        }
+
+        pub fn decode(blob: &mut ByteReader) -> Result<u16, DecodeError> {
+            Ok(u16::from_be_bytes(blob.take(2)?.try_into().unwrap()))
+        }
+    }
+
+    #[derive(Debug, PartialEq, BitcoinType)]
+    struct Endpoint {
+        services: u64,
+        #[bitcoin(with = "big_endian")]
+        port: u16,
+    }
+
+    #[test]
+    fn with_field_uses_module_hooks() {
+        let endpoint = Endpoint {
+            services: 1,
+            port: 8333,
+        };
+        let blob = endpoint.to_blob();
+        assert_eq!(&blob[8..], &[0x20, 0x8d]);
+
+        assert_eq!(
+            Endpoint::from_blob(&mut ByteReader::new(blob)),
+            Ok(endpoint)
+        );
+    }
+
+    #[derive(Debug, PartialEq, BitcoinType)]
+    #[bitcoin(tag = "u8")]
+    enum Filter {
+        #[bitcoin(tag = 0)]
+        Clear,
+        #[bitcoin(tag = 1)]
+        Load(Vec<u8>, u32),
+        #[bitcoin(tag = 2)]
+        Add {
+            #[bitcoin(rest)]
+            data: Vec<u8>,
+        },
+        #[bitcoin(other)]
+        Unknown(u8),
+    }
+
+    #[test]
+    fn enum_is_tag_then_fields() {
+        let filters = [
+            (Filter::Clear, vec![0]),
+            (Filter::Load(vec![0xab], 7), vec![1, 1, 0xab, 7, 0, 0, 0]),
+            (
+                Filter::Add {
+                    data: vec![0xcd; 2],
+                },
+                vec![2, 0xcd, 0xcd],
+            ),
+            (Filter::Unknown(9), vec![9]),
+        ];
+
+        for (filter, blob) in filters {
+            assert_eq!(filter.encoded_size_hint(), blob.len());
+            assert_eq!(filter.to_blob(), blob);
+            assert_eq!(Filter::from_blob(&mut ByteReader::new(blob)), Ok(filter));
+        }
+    }
+
+    #[test]
+    fn decodes_mainnet_headers() {
+        let headers = decode_headers(headers_msg(&parse_hex(BLOCKS_1_2), 2));
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(
+            headers[0].header.hash().to_string(),
+            "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048"
+        );
+        assert_eq!(
+            headers[1].header.hash().to_string(),
+            "000000006a625f06636b8bb6ac7b960a8d03705d1ace08b1a19da3fdcc99ddbd"
+        );
+        assert_eq!(headers[1].header.prev_block, headers[0].header.hash());
+        assert_eq!(headers[1].header.time, 1231469744);
+        assert!(headers.iter().all(|entry| entry.tx_count == 0));
+    }
+
+    #[test]
+    fn decodes_full_headers_batch() {
+        let entries = parse_hex(BLOCKS_1_2).repeat(MAX_HEADERS_RESULTS / 2);
+        let headers = decode_headers(headers_msg(&entries, MAX_HEADERS_RESULTS));
+
+        assert_eq!(headers.len(), MAX_HEADERS_RESULTS);
+        for pair in headers.chunks(2) {
+            assert_eq!(pair[1].header.prev_block, pair[0].header.hash());
+        }
+    }
+
+    #[test]
+    fn encodes_headers_round_trip() {
+        let headers = decode_headers(headers_msg(&parse_hex(BLOCKS_1_2), 2));
+        let msg = BitcoinMsg::headers(headers.iter().map(|entry| entry.header.clone()).collect());
+
+        assert_eq!(
+            msg.encode(Magic::MAINNET),
+            headers_msg(&parse_hex(BLOCKS_1_2), 2)
+        );
+    }
+
+    #[test]
+    fn size_hints_are_exact() {
+        let headers = decode_headers(headers_msg(&parse_hex(BLOCKS_1_2), 2));
+        let addr = AddrElement {
+            timestamp: 1700000000,
+            addr: NetAddr {
+                services: Services::default(),
+                addr: "203.0.113.5:8333".parse().unwrap(),
+            },
+        };
+        let msgs = [
+            BitcoinMsg::headers(headers.into_iter().map(|entry| entry.header).collect()),
+            BitcoinMsg {
+                payload: BitcoinPayload::Addr(Addr {
+                    addr_list: vec![addr; 1000],
+                }),
+            },
+            BitcoinMsg {
+                payload: BitcoinPayload::VerAck,
+            },
+            BitcoinMsg::ping(7),
+        ];
+
+        for msg in msgs {
+            assert_eq!(msg.encoded_size_hint(), msg.encode(Magic::MAINNET).len());
+        }
+    }
+
+    #[test]
+    fn sanitizes_user_agents() {
+        let version = |user_agent: String| {
+            let mut version = Version::builder().build().unwrap();
+            version.user_agent = user_agent;
+            Version::from_blob(&mut ByteReader::new(version.to_blob()))
+                .unwrap()
+                .user_agent
+        };
+
+        assert_eq!(version("/Satoshi:27.0.0/".into()), "/Satoshi:27.0.0/");
+        assert_eq!(
+            version("/evil\x1b[2J\x1b]0;pwned\x07/".into()),
+            "/evil[2J]0;pwned/"
+        );
+        assert_eq!(version("é".repeat(200)), "é".repeat(128));
+    }
+}
@@ -0,0 +1,76 @@
+use alloc::string::String;
+
+use crate::types::{Hash, Magic, Network};
+
+// Everything that sets one Bitcoin network apart from another. The
+// known networks have theirs in Network::params(); for a private chain
+// or a Bitcoin-derived test network, fill one in by hand, usually
+// starting from the regtest parameters:
+//
+//     let params = Params {
+//         magic: Magic([0xde, 0xad, 0xbe, 0xef]),
+//         default_port: 19444,
+//         ..Network::Regtest.params()
+//     };
+//
+// Connections only need the magic, through Connection::set_magic().
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Params {
+    pub magic: Magic,
+    pub default_port: u16,
+    pub genesis_hash: Hash,
+    // Version bytes of base58 addresses and WIF keys.
+    pub pubkey_address_prefix: u8,
+    pub script_address_prefix: u8,
+    pub secret_key_prefix: u8,
+    pub bech32_hrp: String,
+    // The easiest target allowed, in compact form.
+    pub pow_limit_bits: u32,
+    pub pow_target_spacing: u32,
+    pub pow_target_timespan: u32,
+    // Testnet rule: a block may use pow_limit_bits if it comes more than
+    // twice the target spacing after the previous one.
+    pub pow_allow_min_difficulty_blocks: bool,
+    pub pow_no_retargeting: bool,
+}
+
+impl Network {
+    pub fn params(&self) -> Params {
+        let (pubkey_address_prefix, script_address_prefix, secret_key_prefix) = match self {
+            Network::Mainnet => (0x00, 0x05, 0x80),
+            _ => (0x6f, 0xc4, 0xef),
+        };
+
+        Params {
+            magic: self.magic(),
+            default_port: self.default_port(),
+            genesis_hash: self.genesis_hash(),
+            pubkey_address_prefix,
+            script_address_prefix,
+            secret_key_prefix,
+            bech32_hrp: String::from(match self {
+                Network::Mainnet => "bc",
+                Network::Testnet | Network::Testnet4 | Network::Signet => "tb",
+                Network::Regtest => "bcrt",
+            }),
+            pow_limit_bits: match self {
+                Network::Mainnet | Network::Testnet | Network::Testnet4 => 0x1d00ffff,
+                Network::Signet => 0x1e0377ae,
+                Network::Regtest => 0x207fffff,
+            },
+            pow_target_spacing: 10 * 60,
+            pow_target_timespan: 14 * 24 * 60 * 60,
+            pow_allow_min_difficulty_blocks: matches!(
+                self,
+                Network::Testnet | Network::Testnet4 | Network::Regtest
+            ),
+            pow_no_retargeting: *self == Network::Regtest,
+        }
+    }
+}
+
+impl From<Network> for Params {
+    fn from(network: Network) -> Params {
+        network.params()
+    }
+}
@@ -0,0 +1,220 @@
+use alloc::vec::Vec;
+
+use crate::crypto::sha256d;
+use crate::params::Params;
+use crate::types::{Hash, Magic};
+
+pub const HEADER_SIZE: usize = 80;
+
+// The target a compact "bits" value stands for, as a big endian 256 bit
+// number. None for negative or overflowing encodings, which no valid
+// header has.
+pub fn target_from_bits(bits: u32) -> Option<[u8; 32]> {
+    let exponent = (bits >> 24) as i32;
+    let mantissa = bits & 0x007fffff;
+    if bits & 0x00800000 != 0 && mantissa != 0 {
+        return None;
+    }
+
+    let mut target = [0; 32];
+    for (i, byte) in mantissa.to_be_bytes()[1..].iter().enumerate() {
+        // Position counted in bytes from the least significant end.
+        let position = exponent - 1 - i as i32;
+        if *byte == 0 || position < 0 {
+            continue;
+        }
+        if position >= 32 {
+            return None;
+        }
+        target[31 - position as usize] = *byte;
+    }
+
+    Some(target)
+}
+
+// Whether a block hash, in the internal byte order, is at or below the
+// target of `bits`.
+pub fn meets_target(hash: &Hash, bits: u32) -> bool {
+    let Some(target) = target_from_bits(bits) else {
+        return false;
+    };
+
+    hash.0.iter().rev().le(target.iter())
+}
+
+pub fn header_bits(header: &[u8; HEADER_SIZE]) -> u32 {
+    u32::from_le_bytes(header[72..76].try_into().unwrap())
+}
+
+// Grinds `header` until its hash meets its own target and returns the
+// hash, for producing blocks in tests. The nonce is counted up from its
+// current value; when it runs out the timestamp is bumped by a second
+// and the nonce starts over, since rolling the extra nonce would mean
+// rebuilding the coinbase and merkle root.
+//
+// Only for test networks at their easiest difficulty: None on mainnet or
+// if the header's bits are not the pow_limit_bits of `params`. Regtest
+// takes a couple of tries, testnet billions.
+pub fn mine(header: &mut [u8; HEADER_SIZE], params: &Params) -> Option<Hash> {
+    let bits = header_bits(header);
+    if params.magic == Magic::MAINNET || bits != params.pow_limit_bits {
+        return None;
+    }
+    target_from_bits(bits)?;
+
+    loop {
+        let hash = sha256d(header);
+        if meets_target(&hash, bits) {
+            return Some(hash);
+        }
+
+        let nonce = u32::from_le_bytes(header[76..80].try_into().unwrap());
+        match nonce.checked_add(1) {
+            Some(nonce) => header[76..80].copy_from_slice(&nonce.to_le_bytes()),
+            None => {
+                let time = u32::from_le_bytes(header[68..72].try_into().unwrap());
+                header[68..72].copy_from_slice(&time.wrapping_add(1).to_le_bytes());
+                header[76..80].copy_from_slice(&0u32.to_le_bytes());
+            }
+        }
+    }
+}
+
+pub fn header_time(header: &[u8; HEADER_SIZE]) -> u32 {
+    u32::from_le_bytes(header[68..72].try_into().unwrap())
+}
+
+// How many blocks the median time past is taken over.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+// How far ahead of network-adjusted time a header may be.
+pub const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 60;
+
+// The median timestamp of the last MEDIAN_TIME_SPAN blocks, given the
+// timestamps of the chain oldest first. Near the start of the chain
+// fewer are used, like Core does. None for an empty chain.
+pub fn median_time_past(times: &[u32]) -> Option<u32> {
+    if times.is_empty() {
+        return None;
+    }
+    let mut last: Vec<u32> = times[times.len().saturating_sub(MEDIAN_TIME_SPAN)..].to_vec();
+    last.sort_unstable();
+    Some(last[last.len() / 2])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampError {
+    // Not after the median time past of the previous blocks.
+    TooOld,
+    // More than MAX_FUTURE_BLOCK_TIME ahead of network-adjusted time.
+    TooNew,
+}
+
+// Checks a header's timestamp against the median time past of the chain
+// it extends and the network-adjusted time `now`, such as
+// net::AdjustedTime::now().
+pub fn check_timestamp(time: u32, median_time_past: u32, now: i64) -> Result<(), TimestampError> {
+    if time <= median_time_past {
+        Err(TimestampError::TooOld)
+    } else if time as i64 > now + MAX_FUTURE_BLOCK_TIME {
+        Err(TimestampError::TooNew)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Network;
+
+    fn target(hex: &str) -> [u8; 32] {
+        let mut target = [0; 32];
+        for (i, byte) in target.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        target
+    }
+
+    #[test]
+    fn decodes_compact_bits() {
+        assert_eq!(
+            target_from_bits(0x1d00ffff),
+            Some(target(
+                "00000000ffff0000000000000000000000000000000000000000000000000000"
+            ))
+        );
+        assert_eq!(
+            target_from_bits(0x207fffff),
+            Some(target(
+                "7fffff0000000000000000000000000000000000000000000000000000000000"
+            ))
+        );
+        // Mantissa bytes below the exponent fall off the end.
+        assert_eq!(target_from_bits(0x01003456), Some([0; 32]));
+        assert_eq!(target_from_bits(0x01123456).unwrap()[31], 0x12);
+        // Sign bit set.
+        assert_eq!(target_from_bits(0x04923456), None);
+        assert_eq!(target_from_bits(0xff123456), None);
+    }
+
+    #[test]
+    fn checks_hashes_against_target() {
+        let genesis: Hash = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+            .parse()
+            .unwrap();
+        assert!(meets_target(&genesis, 0x1d00ffff));
+        assert!(!meets_target(&genesis, 0x1b0404cb));
+
+        // The target itself passes, one above it doesn't.
+        let mut at_target = target_from_bits(0x1d00ffff).unwrap();
+        at_target.reverse();
+        assert!(meets_target(&Hash(at_target), 0x1d00ffff));
+        at_target[0] = 1;
+        assert!(!meets_target(&Hash(at_target), 0x1d00ffff));
+
+        assert!(!meets_target(&Hash([0; 32]), 0x04923456));
+    }
+
+    #[test]
+    fn mines_regtest_headers() {
+        let params = Network::Regtest.params();
+        let mut header = [0; HEADER_SIZE];
+        header[68..72].copy_from_slice(&1_700_000_000u32.to_le_bytes());
+        header[72..76].copy_from_slice(&params.pow_limit_bits.to_le_bytes());
+
+        let hash = mine(&mut header, &params).unwrap();
+        assert_eq!(hash, sha256d(&header));
+        assert!(meets_target(&hash, header_bits(&header)));
+
+        assert_eq!(mine(&mut header, &Network::Mainnet.params()), None);
+        header[72..76].copy_from_slice(&0x1d00ffffu32.to_le_bytes());
+        assert_eq!(mine(&mut header, &params), None);
+    }
+
+    #[test]
+    fn median_time_past_of_short_chains() {
+        assert_eq!(median_time_past(&[]), None);
+        assert_eq!(median_time_past(&[7]), Some(7));
+        assert_eq!(median_time_past(&[30, 10]), Some(30));
+        assert_eq!(median_time_past(&[5, 1, 3, 2, 4]), Some(3));
+
+        // Only the last MEDIAN_TIME_SPAN count once there are more.
+        let times: Vec<u32> = (0..20).rev().collect();
+        assert_eq!(median_time_past(&times), Some(5));
+    }
+
+    #[test]
+    fn checks_timestamp_boundaries() {
+        let now = 1_700_000_000;
+        let mtp = (now - 3600) as u32;
+        assert_eq!(check_timestamp(mtp, mtp, now), Err(TimestampError::TooOld));
+        assert_eq!(check_timestamp(mtp + 1, mtp, now), Ok(()));
+
+        let latest = (now + MAX_FUTURE_BLOCK_TIME) as u32;
+        assert_eq!(check_timestamp(latest, mtp, now), Ok(()));
+        assert_eq!(
+            check_timestamp(latest + 1, mtp, now),
+            Err(TimestampError::TooNew)
+        );
+    }
+}
@@ -0,0 +1,13 @@
+pub use crate::codec::{BitcoinType, ByteReader};
+pub use crate::types::{
+    Command, Hash, InventoryElement, InventoryKind, Magic, NetAddr, Network, Services,
+};
+
+#[cfg(feature = "p2p")]
+pub use crate::p2p::messages::{
+    Addr, AddrElement, BitcoinHeader, BitcoinMsg, BitcoinPayload, FeeFilter, Inv, LazyPayload,
+    SendCmpct, Version,
+};
+
+#[cfg(feature = "net")]
+pub use crate::net::{Connection, Transport};
@@ -0,0 +1,335 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub use serde_json::{json, Value};
+
+use crate::net::{Connection, Transport};
+use crate::netgroup::AddressType;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_BODY_SIZE: usize = 32 * 1024 * 1024;
+
+// Error codes as used by Bitcoin Core.
+pub const RPC_MISC_ERROR: i64 = -1;
+pub const RPC_CLIENT_NOT_CONNECTED: i64 = -9;
+pub const RPC_DESERIALIZATION_ERROR: i64 = -22;
+pub const RPC_CLIENT_NODE_ALREADY_ADDED: i64 = -23;
+pub const RPC_CLIENT_INVALID_IP_OR_SUBNET: i64 = -30;
+pub const RPC_INVALID_REQUEST: i64 = -32600;
+pub const RPC_METHOD_NOT_FOUND: i64 = -32601;
+pub const RPC_INVALID_PARAMS: i64 = -32602;
+pub const RPC_PARSE_ERROR: i64 = -32700;
+
+#[derive(Debug, Clone)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl ToString) -> RpcError {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn method_not_found(method: &str) -> RpcError {
+        RpcError::new(RPC_METHOD_NOT_FOUND, format!("Method not found: {method}"))
+    }
+
+    pub fn invalid_params(message: impl ToString) -> RpcError {
+        RpcError::new(RPC_INVALID_PARAMS, message)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "code": self.code, "message": self.message })
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+pub trait Handler: Send + Sync + 'static {
+    fn call(&self, method: &str, params: &[Value]) -> Result<Value, RpcError>;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&str, &[Value]) -> Result<Value, RpcError> + Send + Sync + 'static,
+{
+    fn call(&self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        self(method, params)
+    }
+}
+
+// Serves JSON-RPC over HTTP POST the way bitcoind does, so bitcoin-cli and
+// other tooling can talk to it. With `auth` set, requests need matching
+// basic auth credentials. Batches are supported. One thread is used per
+// connection, which is plenty for local tooling.
+pub fn serve(
+    addr: SocketAddr,
+    auth: Option<(&str, &str)>,
+    handler: impl Handler,
+) -> io::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)?;
+    let auth = auth.map(|(user, password)| base64(format!("{user}:{password}").as_bytes()));
+    let handler = Arc::new(handler);
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let handler = handler.clone();
+            let auth = auth.clone();
+            thread::spawn(move || handle_connection(stream, auth.as_deref(), &*handler));
+        }
+    }))
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    auth: Option<&str>,
+    handler: &dyn Handler,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut content_length = None;
+    let mut authorized = auth.is_none();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.trim().parse::<usize>().ok(),
+            "authorization" => {
+                authorized |= auth.is_some_and(|auth| value.trim() == format!("Basic {auth}"));
+            }
+            _ => {}
+        }
+    }
+
+    let mut stream = &stream;
+    if !authorized {
+        return write_response(&mut stream, "401 Unauthorized", "");
+    }
+    if !request_line.starts_with("POST") {
+        return write_response(&mut stream, "405 Method Not Allowed", "");
+    }
+    let Some(content_length) = content_length.filter(|len| *len <= MAX_BODY_SIZE) else {
+        return write_response(&mut stream, "411 Length Required", "");
+    };
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (status, response) = match serde_json::from_slice::<Value>(&body) {
+        Ok(Value::Array(batch)) => (
+            "200 OK",
+            Value::Array(
+                batch
+                    .iter()
+                    .map(|request| dispatch(request, handler))
+                    .collect(),
+            ),
+        ),
+        Ok(request) => {
+            let response = dispatch(&request, handler);
+            // Like bitcoind, a failed single request also gets an HTTP error
+            // status, which some clients rely on.
+            let status = match response["error"]["code"].as_i64() {
+                None => "200 OK",
+                Some(RPC_METHOD_NOT_FOUND) => "404 Not Found",
+                Some(RPC_INVALID_REQUEST) => "400 Bad Request",
+                Some(_) => "500 Internal Server Error",
+            };
+            (status, response)
+        }
+        Err(e) => (
+            "500 Internal Server Error",
+            json!({
+                "result": null,
+                "error": RpcError::new(RPC_PARSE_ERROR, e).to_json(),
+                "id": null,
+            }),
+        ),
+    };
+
+    write_response(&mut stream, status, &response.to_string())
+}
+
+fn dispatch(request: &Value, handler: &dyn Handler) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+
+    let result = match (
+        request.get("method").and_then(Value::as_str),
+        request.get("params"),
+    ) {
+        (None, _) => Err(RpcError::new(
+            RPC_INVALID_REQUEST,
+            "Method must be a string",
+        )),
+        (Some(method), None | Some(Value::Null)) => handler.call(method, &[]),
+        (Some(method), Some(Value::Array(params))) => handler.call(method, params),
+        (Some(_), Some(_)) => Err(RpcError::new(
+            RPC_INVALID_REQUEST,
+            "Params must be an array",
+        )),
+    };
+
+    match result {
+        Ok(result) => json!({ "result": result, "error": null, "id": id }),
+        Err(e) => json!({ "result": null, "error": e.to_json(), "id": id }),
+    }
+}
+
+fn write_response(stream: &mut impl Write, status: &str, body: &str) -> io::Result<()> {
+    write_response_with(stream, status, "", body)
+}
+
+fn write_response_with(
+    stream: &mut impl Write,
+    status: &str,
+    headers: &str,
+    body: &str,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         {headers}\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut ret = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+    ret
+}
+
+pub trait RestHandler: Send + Sync + 'static {
+    fn get(&self, path: &str) -> Option<Value>;
+}
+
+impl<F> RestHandler for F
+where
+    F: Fn(&str) -> Option<Value> + Send + Sync + 'static,
+{
+    fn get(&self, path: &str) -> Option<Value> {
+        self(path)
+    }
+}
+
+// Serves read-only JSON documents over HTTP GET, for dashboards and
+// monitoring scripts. Paths the handler has nothing for get a 404. There
+// is no authentication and any origin is allowed, so only bind this to
+// addresses that are meant to see the data.
+pub fn serve_rest(addr: SocketAddr, handler: impl RestHandler) -> io::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)?;
+    let handler = Arc::new(handler);
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let handler = handler.clone();
+            thread::spawn(move || handle_rest_connection(stream, &*handler));
+        }
+    }))
+}
+
+fn handle_rest_connection(stream: TcpStream, handler: &dyn RestHandler) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut stream = &stream;
+    let mut parts = request_line.split_whitespace();
+    if parts.next() != Some("GET") {
+        return write_response(&mut stream, "405 Method Not Allowed", "");
+    }
+    // The query string is of no use to any handler yet.
+    let path = parts.next().unwrap_or("/");
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+
+    match handler.get(path) {
+        Some(body) => write_response_with(
+            &mut stream,
+            "200 OK",
+            "Access-Control-Allow-Origin: *\r\n",
+            &body.to_string(),
+        ),
+        None => write_response(&mut stream, "404 Not Found", ""),
+    }
+}
+
+// A getpeerinfo entry for `conn`, with the fields this library knows about.
+pub fn peer_info<T: Transport>(id: u64, conn: &Connection<T>) -> Value {
+    let mut info = json!({
+        "id": id,
+        "addr": conn.peer_addr().map(|addr| addr.to_string()).ok(),
+        "inbound": false,
+        "relaytxes": conn.peer_relay(),
+        "timeoffset": conn.time_offset().unwrap_or(0),
+    });
+
+    if let Ok(addr) = conn.peer_addr() {
+        info["network"] = json!(AddressType::of(&addr.ip()).as_str());
+    }
+    if let Some(version) = conn.peer_version() {
+        info["services"] = json!(format!("{:016x}", version.services.bits()));
+        info["version"] = json!(version.proto_ver);
+        info["subver"] = json!(version.user_agent);
+        info["startingheight"] = json!(version.last_block);
+    }
+    if let Some(latency) = conn.latency() {
+        info["pingtime"] = json!(latency.as_secs_f64());
+    }
+
+    info
+}
@@ -0,0 +1,218 @@
+use core::hash::{BuildHasher, Hasher};
+
+use crate::types::Hash;
+
+// SipHash-2-4 with 64-bit output, as used for BIP152 short transaction IDs.
+// Also usable as a keyed hasher for maps whose keys come from peers.
+#[derive(Debug, Clone)]
+pub struct SipHasher24 {
+    v: [u64; 4],
+    tail: u64,
+    ntail: usize,
+    length: usize,
+}
+
+impl SipHasher24 {
+    pub fn new(k0: u64, k1: u64) -> SipHasher24 {
+        SipHasher24 {
+            v: [
+                k0 ^ 0x736f6d6570736575,
+                k1 ^ 0x646f72616e646f6d,
+                k0 ^ 0x6c7967656e657261,
+                k1 ^ 0x7465646279746573,
+            ],
+            tail: 0,
+            ntail: 0,
+            length: 0,
+        }
+    }
+
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13);
+        v[1] ^= v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16);
+        v[3] ^= v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21);
+        v[3] ^= v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17);
+        v[1] ^= v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+
+    fn compress(v: &mut [u64; 4], m: u64) {
+        v[3] ^= m;
+        SipHasher24::round(v);
+        SipHasher24::round(v);
+        v[0] ^= m;
+    }
+}
+
+impl Hasher for SipHasher24 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.length += bytes.len();
+
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            if self.ntail == 0 && bytes.len() >= 8 {
+                let (word, rest) = bytes.split_at(8);
+                SipHasher24::compress(&mut self.v, u64::from_le_bytes(word.try_into().unwrap()));
+                bytes = rest;
+                continue;
+            }
+
+            self.tail |= (bytes[0] as u64) << (8 * self.ntail);
+            self.ntail += 1;
+            bytes = &bytes[1..];
+
+            if self.ntail == 8 {
+                SipHasher24::compress(&mut self.v, self.tail);
+                self.tail = 0;
+                self.ntail = 0;
+            }
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        let mut v = self.v;
+
+        SipHasher24::compress(&mut v, ((self.length as u64) << 56) | self.tail);
+
+        v[2] ^= 0xff;
+        for _ in 0..4 {
+            SipHasher24::round(&mut v);
+        }
+
+        v[0] ^ v[1] ^ v[2] ^ v[3]
+    }
+}
+
+pub fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut hasher = SipHasher24::new(k0, k1);
+    hasher.write(data);
+    hasher.finish()
+}
+
+// BIP152 short ID: the keyed SipHash of a (w)txid truncated to 6 bytes.
+pub fn short_id(k0: u64, k1: u64, txid: &Hash) -> u64 {
+    siphash24(k0, k1, &txid.0) & 0xffff_ffff_ffff
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BuildSipHasher24 {
+    k0: u64,
+    k1: u64,
+}
+
+impl BuildSipHasher24 {
+    pub fn new(k0: u64, k1: u64) -> BuildSipHasher24 {
+        BuildSipHasher24 { k0, k1 }
+    }
+}
+
+impl BuildHasher for BuildSipHasher24 {
+    type Hasher = SipHasher24;
+
+    fn build_hasher(&self) -> SipHasher24 {
+        SipHasher24::new(self.k0, self.k1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The reference vectors from the SipHash paper: keys 00..0f and
+    // inputs 00..(n-1) for n up to 63.
+    #[test]
+    fn reference_vectors() {
+        let vectors: [u64; 64] = [
+            0x726fdb47dd0e0e31,
+            0x74f839c593dc67fd,
+            0x0d6c8009d9a94f5a,
+            0x85676696d7fb7e2d,
+            0xcf2794e0277187b7,
+            0x18765564cd99a68d,
+            0xcbc9466e58fee3ce,
+            0xab0200f58b01d137,
+            0x93f5f5799a932462,
+            0x9e0082df0ba9e4b0,
+            0x7a5dbbc594ddb9f3,
+            0xf4b32f46226bada7,
+            0x751e8fbc860ee5fb,
+            0x14ea5627c0843d90,
+            0xf723ca908e7af2ee,
+            0xa129ca6149be45e5,
+            0x3f2acc7f57c29bdb,
+            0x699ae9f52cbe4794,
+            0x4bc1b3f0968dd39c,
+            0xbb6dc91da77961bd,
+            0xbed65cf21aa2ee98,
+            0xd0f2cbb02e3b67c7,
+            0x93536795e3a33e88,
+            0xa80c038ccd5ccec8,
+            0xb8ad50c6f649af94,
+            0xbce192de8a85b8ea,
+            0x17d835b85bbb15f3,
+            0x2f2e6163076bcfad,
+            0xde4daaaca71dc9a5,
+            0xa6a2506687956571,
+            0xad87a3535c49ef28,
+            0x32d892fad841c342,
+            0x7127512f72f27cce,
+            0xa7f32346f95978e3,
+            0x12e0b01abb051238,
+            0x15e034d40fa197ae,
+            0x314dffbe0815a3b4,
+            0x027990f029623981,
+            0xcadcd4e59ef40c4d,
+            0x9abfd8766a33735c,
+            0x0e3ea96b5304a7d0,
+            0xad0c42d6fc585992,
+            0x187306c89bc215a9,
+            0xd4a60abcf3792b95,
+            0xf935451de4f21df2,
+            0xa9538f0419755787,
+            0xdb9acddff56ca510,
+            0xd06c98cd5c0975eb,
+            0xe612a3cb9ecba951,
+            0xc766e62cfcadaf96,
+            0xee64435a9752fe72,
+            0xa192d576b245165a,
+            0x0a8787bf8ecb74b2,
+            0x81b3e73d20b49b6f,
+            0x7fa8220ba3b2ecea,
+            0x245731c13ca42499,
+            0xb78dbfaf3a8d83bd,
+            0xea1ad565322a1a0b,
+            0x60e61c23a3795013,
+            0x6606d7e446282b93,
+            0x6ca4ecb15c5f91e1,
+            0x9f626da15c9625f3,
+            0xe51b38608ef25f57,
+            0x958a324ceb064572,
+        ];
+
+        let k0 = u64::from_le_bytes([0, 1, 2, 3, 4, 5, 6, 7]);
+        let k1 = u64::from_le_bytes([8, 9, 10, 11, 12, 13, 14, 15]);
+        let input: Vec<u8> = (0..64).collect();
+        let mut incremental = SipHasher24::new(k0, k1);
+        for (n, expected) in vectors.into_iter().enumerate() {
+            assert_eq!(
+                siphash24(k0, k1, &input[..n]),
+                expected,
+                "input of {n} bytes"
+            );
+            assert_eq!(
+                incremental.finish(),
+                expected,
+                "input of {n} bytes, written bytewise"
+            );
+            incremental.write(&input[n..n + 1]);
+        }
+    }
+}
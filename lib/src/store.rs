@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::codec::{BitcoinType, ByteReader, DecodeError};
+use crate::crypto::sha256d;
+use crate::types::{Hash, Magic};
+
+const DEFAULT_MAX_FILE_SIZE: u64 = 128 * 1024 * 1024;
+const BLOCK_HEADER_SIZE: usize = 80;
+const INDEX_FILE: &str = "index.dat";
+const INDEX_ENTRY_SIZE: usize = 32 + 4 + 4 + 8 + 4;
+
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    height: u32,
+    file: u32,
+    offset: u64,
+    len: u32,
+}
+
+impl Location {
+    fn to_blob(self, hash: &Hash) -> Vec<u8> {
+        let mut ret = hash.to_blob();
+        ret.extend(self.height.to_blob());
+        ret.extend(self.file.to_blob());
+        ret.extend(self.offset.to_blob());
+        ret.extend(self.len.to_blob());
+        ret
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<(Hash, Location), DecodeError> {
+        let hash = Hash::from_blob(blob)?;
+        let location = Location {
+            height: u32::from_blob(blob)?,
+            file: u32::from_blob(blob)?,
+            offset: u64::from_blob(blob)?,
+            len: u32::from_blob(blob)?,
+        };
+        Ok((hash, location))
+    }
+}
+
+// Raw blocks in numbered blkNNNNN.dat files, framed like Bitcoin Core's
+// (magic, little endian size, block), plus an append-only index of where
+// each block hash lives. Blocks are stored as given; the only thing read
+// from them is the 80 byte header, to compute the block hash.
+pub struct BlockStore {
+    dir: PathBuf,
+    magic: Magic,
+    max_file_size: u64,
+    prune_depth: Option<u32>,
+    index: HashMap<Hash, Location>,
+    // Highest block height stored in each file, for pruning.
+    files: BTreeMap<u32, u32>,
+    current_file: u32,
+    tip_height: Option<u32>,
+}
+
+impl BlockStore {
+    pub fn open(dir: impl AsRef<Path>, magic: Magic) -> io::Result<BlockStore> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut store = BlockStore {
+            dir,
+            magic,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            prune_depth: None,
+            index: HashMap::new(),
+            files: BTreeMap::new(),
+            current_file: 0,
+            tip_height: None,
+        };
+        store.load_index()?;
+
+        Ok(store)
+    }
+
+    pub fn set_max_file_size(&mut self, max_file_size: u64) {
+        self.max_file_size = max_file_size;
+    }
+
+    // Keep only the most recent `depth` blocks, pruning older ones as new
+    // blocks are stored. Pruning works on whole files, so somewhat more
+    // than `depth` blocks remain on disk.
+    pub fn set_prune_depth(&mut self, depth: Option<u32>) {
+        self.prune_depth = depth;
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn tip_height(&self) -> Option<u32> {
+        self.tip_height
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.index.contains_key(hash)
+    }
+
+    pub fn height(&self, hash: &Hash) -> Option<u32> {
+        self.index.get(hash).map(|location| location.height)
+    }
+
+    pub fn put_block(&mut self, height: u32, block: &[u8]) -> io::Result<Hash> {
+        if block.len() < BLOCK_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} bytes is too short for a block", block.len()),
+            ));
+        }
+
+        let hash = sha256d(&block[..BLOCK_HEADER_SIZE]);
+        if self.index.contains_key(&hash) {
+            return Ok(hash);
+        }
+
+        // Where the record goes is taken from the file rather than the
+        // index, which doesn't know of blocks written before a crash or of
+        // records a failed write left half done.
+        let record_size = 8 + block.len() as u64;
+        let mut file = self.open_block_file(self.current_file)?;
+        let mut size = file.metadata()?.len();
+        if size > 0 && size + record_size > self.max_file_size {
+            self.current_file += 1;
+            file = self.open_block_file(self.current_file)?;
+            size = file.metadata()?.len();
+        }
+
+        file.write_all(&self.magic.to_blob())?;
+        file.write_all(&(block.len() as u32).to_blob())?;
+        file.write_all(block)?;
+        file.sync_data()?;
+
+        let location = Location {
+            height,
+            file: self.current_file,
+            offset: size + 8,
+            len: block.len() as u32,
+        };
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(INDEX_FILE))?
+            .write_all(&location.to_blob(&hash))?;
+
+        self.insert(hash, location);
+
+        if let (Some(depth), Some(tip)) = (self.prune_depth, self.tip_height) {
+            self.prune(tip.saturating_sub(depth))?;
+        }
+
+        Ok(hash)
+    }
+
+    pub fn get_block(&self, hash: &Hash) -> io::Result<Option<Vec<u8>>> {
+        let Some(location) = self.index.get(hash) else {
+            return Ok(None);
+        };
+
+        let mut file = File::open(self.block_file(location.file))?;
+        file.seek(SeekFrom::Start(location.offset))?;
+
+        let mut block = vec![0; location.len as usize];
+        file.read_exact(&mut block)?;
+
+        Ok(Some(block))
+    }
+
+    // Deletes every file that only holds blocks below `height` and returns
+    // how many blocks went with them. The file being written to is kept.
+    pub fn prune(&mut self, height: u32) -> io::Result<usize> {
+        let pruned: Vec<u32> = self
+            .files
+            .iter()
+            .filter(|(file, max_height)| **file != self.current_file && **max_height < height)
+            .map(|(file, _)| *file)
+            .collect();
+        if pruned.is_empty() {
+            return Ok(0);
+        }
+
+        let before = self.index.len();
+        self.index
+            .retain(|_, location| !pruned.contains(&location.file));
+        for file in &pruned {
+            self.files.remove(file);
+        }
+
+        // Rewrite the index before deleting anything, so a crash in between
+        // leaves stray files rather than index entries pointing nowhere.
+        self.write_index()?;
+        for file in pruned {
+            fs::remove_file(self.block_file(file))?;
+        }
+
+        Ok(before - self.index.len())
+    }
+
+    fn insert(&mut self, hash: Hash, location: Location) {
+        let max_height = self.files.entry(location.file).or_insert(location.height);
+        *max_height = (*max_height).max(location.height);
+        self.tip_height = Some(
+            self.tip_height
+                .map_or(location.height, |tip| tip.max(location.height)),
+        );
+        self.index.insert(hash, location);
+    }
+
+    fn block_file(&self, file: u32) -> PathBuf {
+        self.dir.join(format!("blk{file:05}.dat"))
+    }
+
+    fn open_block_file(&self, file: u32) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.block_file(file))
+    }
+
+    fn load_index(&mut self) -> io::Result<()> {
+        let path = self.dir.join(INDEX_FILE);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        // A torn final entry from a crash mid-append is cut off so later
+        // appends stay aligned; its block is simply stored again.
+        let complete = bytes.len() - bytes.len() % INDEX_ENTRY_SIZE;
+        if complete != bytes.len() {
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(complete as u64)?;
+        }
+        let mut reader = ByteReader::new(bytes[..complete].to_vec());
+        for _ in 0..complete / INDEX_ENTRY_SIZE {
+            let (hash, location) = Location::from_blob(&mut reader)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if self.block_file(location.file).exists() {
+                self.insert(hash, location);
+            }
+        }
+
+        if let Some(&last) = self.files.keys().next_back() {
+            self.current_file = last;
+        }
+
+        Ok(())
+    }
+
+    fn write_index(&self) -> io::Result<()> {
+        let mut entries: Vec<_> = self.index.iter().collect();
+        entries.sort_by_key(|(_, location)| (location.file, location.offset));
+
+        let mut bytes = Vec::with_capacity(entries.len() * INDEX_ENTRY_SIZE);
+        for (hash, location) in entries {
+            bytes.extend(location.to_blob(hash));
+        }
+
+        let path = self.dir.join(INDEX_FILE);
+        let tmp = path.with_extension("dat.tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("btc-store-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // A block of `len` bytes whose header, and so hash, depends on `n`.
+    fn block(n: u8, len: usize) -> Vec<u8> {
+        let mut block = vec![n; len];
+        block[0] = 0xb1;
+        block
+    }
+
+    // A block written to a file without its index entry, as a crash
+    // between the two would leave it.
+    fn orphan(dir: &Path, file: u32, block: &[u8]) {
+        let mut record = Magic::REGTEST.to_blob();
+        record.extend((block.len() as u32).to_blob());
+        record.extend(block);
+        fs::create_dir_all(dir).unwrap();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(format!("blk{file:05}.dat")))
+            .unwrap()
+            .write_all(&record)
+            .unwrap();
+    }
+
+    #[test]
+    fn stores_blocks_across_reopens() {
+        let dir = TempDir::new("reopen");
+        let mut store = BlockStore::open(&dir.0, Magic::REGTEST).unwrap();
+        let hashes: Vec<Hash> = (0..3)
+            .map(|height| store.put_block(height, &block(height as u8, 100)).unwrap())
+            .collect();
+        assert_eq!(store.put_block(0, &block(0, 100)).unwrap(), hashes[0]);
+        assert!(store.put_block(3, &[0; 79]).is_err());
+        assert_eq!(store.len(), 3);
+
+        let mut store = BlockStore::open(&dir.0, Magic::REGTEST).unwrap();
+        assert_eq!(store.tip_height(), Some(2));
+        let hash = store.put_block(3, &block(3, 120)).unwrap();
+        assert_eq!(store.get_block(&hash).unwrap(), Some(block(3, 120)));
+        for (height, hash) in hashes.iter().enumerate() {
+            assert_eq!(store.height(hash), Some(height as u32));
+            assert_eq!(
+                store.get_block(hash).unwrap(),
+                Some(block(height as u8, 100))
+            );
+        }
+        assert_eq!(store.get_block(&Hash([0; 32])).unwrap(), None);
+    }
+
+    #[test]
+    fn rolls_over_and_prunes_files() {
+        let dir = TempDir::new("prune");
+        let mut store = BlockStore::open(&dir.0, Magic::REGTEST).unwrap();
+        // Two 108 byte records to a file.
+        store.set_max_file_size(250);
+        let hashes: Vec<Hash> = (0..6)
+            .map(|height| store.put_block(height, &block(height as u8, 100)).unwrap())
+            .collect();
+        for file in 0..3 {
+            let path = dir.0.join(format!("blk{file:05}.dat"));
+            assert_eq!(fs::metadata(path).unwrap().len(), 216);
+        }
+
+        // Heights 0 and 1 are in the first file, 2 in the second.
+        assert_eq!(store.prune(3).unwrap(), 2);
+        assert!(!dir.0.join("blk00000.dat").exists());
+        assert_eq!(store.get_block(&hashes[0]).unwrap(), None);
+        assert_eq!(store.get_block(&hashes[2]).unwrap(), Some(block(2, 100)));
+
+        store.set_prune_depth(Some(1));
+        store.put_block(6, &block(6, 100)).unwrap();
+        let store = BlockStore::open(&dir.0, Magic::REGTEST).unwrap();
+        assert_eq!(store.len(), 3);
+        assert!(!store.contains(&hashes[3]));
+        assert!(store.contains(&hashes[4]));
+    }
+
+    #[test]
+    fn drops_torn_index_entries() {
+        let dir = TempDir::new("torn");
+        let mut store = BlockStore::open(&dir.0, Magic::REGTEST).unwrap();
+        let first = store.put_block(0, &block(0, 100)).unwrap();
+        let second = store.put_block(1, &block(1, 100)).unwrap();
+        let index = dir.0.join(INDEX_FILE);
+        let mut bytes = fs::read(&index).unwrap();
+        bytes.truncate(INDEX_ENTRY_SIZE + 10);
+        fs::write(&index, bytes).unwrap();
+
+        let mut store = BlockStore::open(&dir.0, Magic::REGTEST).unwrap();
+        assert_eq!(fs::metadata(&index).unwrap().len(), INDEX_ENTRY_SIZE as u64);
+        assert!(!store.contains(&second));
+        assert_eq!(store.put_block(1, &block(1, 100)).unwrap(), second);
+        store.put_block(2, &block(2, 100)).unwrap();
+
+        let store = BlockStore::open(&dir.0, Magic::REGTEST).unwrap();
+        assert_eq!(store.get_block(&first).unwrap(), Some(block(0, 100)));
+        assert_eq!(store.get_block(&second).unwrap(), Some(block(1, 100)));
+        assert_eq!(store.len(), 3);
+    }
+
+    #[test]
+    fn stores_past_orphaned_blocks() {
+        let dir = TempDir::new("orphan");
+        // Before any block is indexed, in the first file and in the next
+        // one once the first is full.
+        orphan(&dir.0, 0, &block(9, 100));
+        let mut store = BlockStore::open(&dir.0, Magic::REGTEST).unwrap();
+        store.set_max_file_size(250);
+        let first = store.put_block(0, &block(0, 100)).unwrap();
+        orphan(&dir.0, 1, &block(8, 100));
+        let second = store.put_block(1, &block(1, 100)).unwrap();
+        assert_eq!(store.get_block(&first).unwrap(), Some(block(0, 100)));
+        assert_eq!(store.get_block(&second).unwrap(), Some(block(1, 100)));
+
+        // After indexed blocks, in a store opened again.
+        orphan(&dir.0, 1, &block(7, 20));
+        let mut store = BlockStore::open(&dir.0, Magic::REGTEST).unwrap();
+        let third = store.put_block(2, &block(2, 90)).unwrap();
+        for (hash, block) in [
+            (first, block(0, 100)),
+            (second, block(1, 100)),
+            (third, block(2, 90)),
+        ] {
+            assert_eq!(store.get_block(&hash).unwrap(), Some(block));
+        }
+    }
+}
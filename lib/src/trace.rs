@@ -0,0 +1,41 @@
+// Thin stand-ins for the `tracing` macros and spans so instrumented code
+// compiles to nothing when the `tracing` feature is disabled. Not every
+// feature combination uses every macro.
+#![allow(dead_code, unused_imports, unused_macros)]
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{debug, debug_span, info, info_span, trace, warn, Span};
+
+#[cfg(not(feature = "tracing"))]
+mod disabled {
+    macro_rules! event {
+        ($($args:tt)*) => {};
+    }
+
+    macro_rules! span {
+        ($($args:tt)*) => {
+            $crate::trace::Span
+        };
+    }
+
+    pub(crate) use event as debug;
+    pub(crate) use event as info;
+    pub(crate) use event as trace;
+    pub(crate) use event as warn;
+    pub(crate) use span as debug_span;
+    pub(crate) use span as info_span;
+
+    #[derive(Debug, Clone)]
+    pub(crate) struct Span;
+
+    pub(crate) struct Entered;
+
+    impl Span {
+        pub(crate) fn entered(self) -> Entered {
+            Entered
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use disabled::*;
@@ -0,0 +1,575 @@
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{IpAddr, Ipv6Addr, SocketAddr};
+use core::str::FromStr;
+
+use crate::codec::{BitcoinType, ByteReader, DecodeError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    InvalidLength(usize),
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidLength(len) => write!(f, "invalid length {len}"),
+            ParseError::InvalidCharacter(c) => write!(f, "invalid character '{c}'"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+fn fmt_hex<'a>(f: &mut fmt::Formatter<'_>, bytes: impl Iterator<Item = &'a u8>) -> fmt::Result {
+    for b in bytes {
+        write!(f, "{b:02x}")?;
+    }
+    Ok(())
+}
+
+fn parse_hex<const N: usize>(s: &str) -> Result<[u8; N], ParseError> {
+    if s.len() != N * 2 {
+        return Err(ParseError::InvalidLength(s.len()));
+    }
+
+    let mut ret = [0; N];
+    for (i, c) in s.chars().enumerate() {
+        let digit = c.to_digit(16).ok_or(ParseError::InvalidCharacter(c))? as u8;
+        ret[i / 2] |= digit << if i % 2 == 0 { 4 } else { 0 };
+    }
+    Ok(ret)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Magic(pub [u8; 4]);
+
+impl Magic {
+    pub const MAINNET: Magic = Magic([0xf9, 0xbe, 0xb4, 0xd9]);
+    pub const TESTNET: Magic = Magic([0x0b, 0x11, 0x09, 0x07]);
+    pub const TESTNET4: Magic = Magic([0x1c, 0x16, 0x3f, 0x28]);
+    pub const SIGNET: Magic = Magic([0x0a, 0x03, 0xcf, 0x40]);
+    pub const REGTEST: Magic = Magic([0xfa, 0xbf, 0xb5, 0xda]);
+}
+
+// The networks this library knows the parameters of. Signet means the
+// default signet; custom signets have their own magic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Network {
+    Mainnet,
+    // Testnet3.
+    Testnet,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+impl Network {
+    pub const ALL: [Network; 5] = [
+        Network::Mainnet,
+        Network::Testnet,
+        Network::Testnet4,
+        Network::Signet,
+        Network::Regtest,
+    ];
+
+    pub fn magic(&self) -> Magic {
+        match self {
+            Network::Mainnet => Magic::MAINNET,
+            Network::Testnet => Magic::TESTNET,
+            Network::Testnet4 => Magic::TESTNET4,
+            Network::Signet => Magic::SIGNET,
+            Network::Regtest => Magic::REGTEST,
+        }
+    }
+
+    pub fn from_magic(magic: Magic) -> Option<Network> {
+        Network::ALL
+            .into_iter()
+            .find(|network| network.magic() == magic)
+    }
+
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Network::Mainnet => 8333,
+            Network::Testnet => 18333,
+            Network::Testnet4 => 48333,
+            Network::Signet => 38333,
+            Network::Regtest => 18444,
+        }
+    }
+
+    pub fn genesis_hash(&self) -> Hash {
+        let hash = match self {
+            Network::Mainnet => "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+            Network::Testnet => "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+            Network::Testnet4 => "00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043",
+            Network::Signet => "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6",
+            Network::Regtest => "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+        };
+        hash.parse().unwrap()
+    }
+
+    // The DNS seeds Bitcoin Core queries for peer addresses.
+    pub fn dns_seeds(&self) -> &'static [&'static str] {
+        match self {
+            Network::Mainnet => &[
+                "seed.bitcoin.sipa.be",
+                "dnsseed.bluematt.me",
+                "seed.bitcoinstats.com",
+                "seed.bitcoin.jonasschnelli.ch",
+                "seed.btc.petertodd.net",
+                "seed.bitcoin.sprovoost.nl",
+                "dnsseed.emzy.de",
+                "seed.bitcoin.wiz.biz",
+            ],
+            Network::Testnet => &[
+                "testnet-seed.bitcoin.jonasschnelli.ch",
+                "seed.tbtc.petertodd.net",
+                "seed.testnet.bitcoin.sprovoost.nl",
+                "testnet-seed.bluematt.me",
+            ],
+            Network::Testnet4 => &[
+                "seed.testnet4.bitcoin.sprovoost.nl",
+                "seed.testnet4.wiz.biz",
+            ],
+            Network::Signet => &["seed.signet.bitcoin.sprovoost.nl"],
+            Network::Regtest => &[],
+        }
+    }
+
+    // The chain names Bitcoin Core uses.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "main",
+            Network::Testnet => "test",
+            Network::Testnet4 => "testnet4",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Network {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            _ => Network::ALL
+                .into_iter()
+                .find(|network| network.as_str() == s)
+                .ok_or(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Command {
+    Version,
+    VerAck,
+    SendHeaders,
+    SendCmpct,
+    Ping,
+    Pong,
+    FeeFilter,
+    Inv,
+    GetAddr,
+    Addr,
+    GetHeaders,
+    Headers,
+    GetData,
+    NotFound,
+    // The 12 bytes as they came, so that a message with a command this
+    // library doesn't know, or one that isn't valid UTF-8 or has bytes
+    // after its NUL padding, encodes back to the same frame.
+    Unknown([u8; 12]),
+}
+
+impl Command {
+    // Unknown commands that aren't UTF-8 are shown lossily, and without
+    // their padding.
+    pub fn as_str(&self) -> Cow<'_, str> {
+        use Command::*;
+
+        Cow::Borrowed(match self {
+            Version => "version",
+            VerAck => "verack",
+            SendHeaders => "sendheaders",
+            SendCmpct => "sendcmpct",
+            Ping => "ping",
+            Pong => "pong",
+            FeeFilter => "feefilter",
+            Inv => "inv",
+            GetAddr => "getaddr",
+            Addr => "addr",
+            GetHeaders => "getheaders",
+            Headers => "headers",
+            GetData => "getdata",
+            NotFound => "notfound",
+            Unknown(bytes) => {
+                let len = bytes.iter().position(|&b| b == 0).unwrap_or(12);
+                return String::from_utf8_lossy(&bytes[..len]);
+            }
+        })
+    }
+}
+
+impl PartialEq<str> for Command {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl From<&str> for Command {
+    fn from(s: &str) -> Self {
+        use Command::*;
+
+        match s {
+            "version" => Version,
+            "verack" => VerAck,
+            "sendheaders" => SendHeaders,
+            "sendcmpct" => SendCmpct,
+            "ping" => Ping,
+            "pong" => Pong,
+            "feefilter" => FeeFilter,
+            "inv" => Inv,
+            "getaddr" => GetAddr,
+            "addr" => Addr,
+            "getheaders" => GetHeaders,
+            "headers" => Headers,
+            "getdata" => GetData,
+            "notfound" => NotFound,
+            // Longer names are cut to the 12 bytes a header has room for.
+            _ => {
+                let mut bytes = [0; 12];
+                let len = s.len().min(12);
+                bytes[..len].copy_from_slice(&s.as_bytes()[..len]);
+                Unknown(bytes)
+            }
+        }
+    }
+}
+
+// Only accepts what fits in a header and is printable ASCII, as
+// commands sent by this library should be.
+impl FromStr for Command {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() > 12 {
+            return Err(ParseError::InvalidLength(s.len()));
+        }
+
+        if let Some(c) = s.chars().find(|c| !c.is_ascii_graphic()) {
+            return Err(ParseError::InvalidCharacter(c));
+        }
+
+        Ok(Command::from(s))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Hash(pub [u8; 32]);
+
+impl BitcoinType for Magic {
+    fn to_blob(&self) -> Vec<u8> {
+        self.0.to_blob()
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        Ok(Magic(<[u8; 4]>::from_blob(blob)?))
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        4
+    }
+}
+
+impl BitcoinType for Command {
+    fn to_blob(&self) -> Vec<u8> {
+        if let Command::Unknown(bytes) = self {
+            return bytes.to_vec();
+        }
+        let mut ret = self.as_str().as_bytes().to_vec();
+        ret.resize(12, 0);
+        ret
+    }
+
+    // Only a name padded with NULs can be a known command, anything else
+    // is kept as it came.
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        let bytes = <[u8; 12]>::from_blob(blob)?;
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(12);
+        Ok(match core::str::from_utf8(&bytes[..len]) {
+            Ok(name) if bytes[len..].iter().all(|&b| b == 0) => Command::from(name),
+            _ => Command::Unknown(bytes),
+        })
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        12
+    }
+}
+
+impl BitcoinType for Hash {
+    fn to_blob(&self) -> Vec<u8> {
+        self.0.to_blob()
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        Ok(Hash(<[u8; 32]>::from_blob(blob)?))
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        32
+    }
+}
+
+impl fmt::Display for Magic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_hex(f, self.0.iter())
+    }
+}
+
+impl fmt::Debug for Magic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Magic({self})")
+    }
+}
+
+impl FromStr for Magic {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Magic(parse_hex(s)?))
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// Hashes are displayed byte-reversed, matching how block explorers and
+// bitcoind print txids and block hashes.
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_hex(f, self.0.iter().rev())
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hash({self})")
+    }
+}
+
+impl FromStr for Hash {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut hash = parse_hex::<32>(s)?;
+        hash.reverse();
+        Ok(Hash(hash))
+    }
+}
+
+#[derive(Debug, Clone, BitcoinType)]
+#[bitcoin(tag = "u32")]
+pub enum InventoryKind {
+    #[bitcoin(tag = 0x0)]
+    Error,
+    #[bitcoin(tag = 0x1)]
+    Tx,
+    #[bitcoin(tag = 0x2)]
+    Block,
+    #[bitcoin(tag = 0x3)]
+    FilteredBlock,
+    #[bitcoin(tag = 0x4)]
+    CmpctBlock,
+    // Announces a transaction by wtxid (BIP339).
+    #[bitcoin(tag = 0x5)]
+    WTx,
+    #[bitcoin(tag = 0x40000001)]
+    WitnessTx,
+    #[bitcoin(tag = 0x40000002)]
+    WitnessBlock,
+    #[bitcoin(tag = 0x40000003)]
+    FilteredWitnessBlock,
+    // Types this library doesn't know yet, such as ones added by future
+    // BIPs, kept so they can be ignored instead of failing the message.
+    #[bitcoin(other)]
+    Unknown(u32),
+}
+
+impl InventoryKind {
+    pub fn is_tx(&self) -> bool {
+        matches!(
+            self,
+            InventoryKind::Tx | InventoryKind::WTx | InventoryKind::WitnessTx
+        )
+    }
+}
+
+impl fmt::Display for InventoryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use InventoryKind::*;
+
+        match self {
+            Error => write!(f, "error"),
+            Tx => write!(f, "tx"),
+            Block => write!(f, "block"),
+            FilteredBlock => write!(f, "filtered block"),
+            CmpctBlock => write!(f, "compact block"),
+            WTx => write!(f, "wtx"),
+            WitnessTx => write!(f, "witness tx"),
+            WitnessBlock => write!(f, "witness block"),
+            FilteredWitnessBlock => write!(f, "filtered witness block"),
+            Unknown(code) => write!(f, "unknown type 0x{code:x}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, BitcoinType)]
+pub struct InventoryElement {
+    pub kind: InventoryKind,
+    pub hash: Hash,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Services {
+    pub network: bool,
+    pub getutxo: bool,
+    pub bloom: bool,
+    pub witness: bool,
+    pub xthin: bool,
+    pub compact_filters: bool,
+    pub network_limited: bool,
+    // The set bits without a field of their own, such as NODE_P2P_V2 at
+    // bit 11, kept so that they survive decoding and encoding again.
+    pub other: u64,
+}
+
+impl Services {
+    // Bits 0 to 4, 6 and 10, the ones with fields.
+    const NAMED: u64 = 0x45f;
+
+    pub fn from_bits(bits: u64) -> Services {
+        Services {
+            network: bits & 1 == 1,
+            getutxo: (bits >> 1) & 1 == 1,
+            bloom: (bits >> 2) & 1 == 1,
+            witness: (bits >> 3) & 1 == 1,
+            xthin: (bits >> 4) & 1 == 1,
+            compact_filters: (bits >> 6) & 1 == 1,
+            network_limited: (bits >> 10) & 1 == 1,
+            other: bits & !Services::NAMED,
+        }
+    }
+
+    // The bitfield as sent on the wire.
+    pub fn bits(&self) -> u64 {
+        self.network as u64
+            | (self.getutxo as u64) << 1
+            | (self.bloom as u64) << 2
+            | (self.witness as u64) << 3
+            | (self.xthin as u64) << 4
+            | (self.compact_filters as u64) << 6
+            | (self.network_limited as u64) << 10
+            | self.other
+    }
+}
+
+// The set flags, comma separated, or "none". Bits without a name are
+// written as bit11 and so on.
+impl fmt::Display for Services {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.network, "network"),
+            (self.getutxo, "getutxo"),
+            (self.bloom, "bloom"),
+            (self.witness, "witness"),
+            (self.xthin, "xthin"),
+            (self.compact_filters, "compact_filters"),
+            (self.network_limited, "network_limited"),
+        ];
+        let names: Vec<String> = flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| String::from(*name))
+            .chain(
+                (0..64)
+                    .filter(|bit| (self.other >> bit) & 1 == 1)
+                    .map(|bit| alloc::format!("bit{bit}")),
+            )
+            .collect();
+
+        if names.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&names.join(","))
+        }
+    }
+}
+
+impl BitcoinType for Services {
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        Ok(Services::from_bits(u64::from_blob(blob)?))
+    }
+
+    fn to_blob(&self) -> Vec<u8> {
+        self.bits().to_blob()
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        8
+    }
+}
+
+impl BitcoinType for SocketAddr {
+    fn to_blob(&self) -> Vec<u8> {
+        let mut res = match self.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped().octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+
+        res.extend(self.port().to_be_bytes().to_vec());
+        res
+    }
+
+    fn from_blob(blob: &mut ByteReader) -> Result<Self, DecodeError> {
+        let ip = Ipv6Addr::from(<[u8; 16]>::from_blob(blob)?);
+        let ip = if let Some(ipv4) = ip.to_ipv4_mapped() {
+            IpAddr::V4(ipv4)
+        } else {
+            IpAddr::V6(ip)
+        };
+
+        let port = u16::from_be_bytes(blob.take(2)?.try_into().unwrap());
+        Ok(SocketAddr::new(ip, port))
+    }
+
+    fn encoded_size_hint(&self) -> usize {
+        18
+    }
+}
+
+#[derive(Debug, Clone, BitcoinType)]
+pub struct NetAddr {
+    pub services: Services,
+    pub addr: SocketAddr,
+}
@@ -0,0 +1,171 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use btc_lib::p2p::messages::BlockHeader;
+use btc_lib::prelude::*;
+
+// Every message the library can build, from fixed inputs, and the bytes
+// they have to encode to in tests/snapshots/<name>.hex. An encoding that
+// changes fails here instead of on the network. When a change is meant,
+// rerun with UPDATE_SNAPSHOTS=1 and commit the new files after checking
+// the diff.
+fn messages() -> Vec<(&'static str, BitcoinMsg)> {
+    let services = Services {
+        network: true,
+        witness: true,
+        network_limited: true,
+        ..Default::default()
+    };
+    let addr = |addr: &str| NetAddr {
+        services: services.clone(),
+        addr: addr.parse::<SocketAddr>().unwrap(),
+    };
+    let hash = |s: &str| s.parse::<Hash>().unwrap();
+    let genesis = BlockHeader {
+        version: 1,
+        prev_block: Hash([0; 32]),
+        merkle_root: hash("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"),
+        time: 1231006505,
+        bits: 0x1d00ffff,
+        nonce: 2083236893,
+    };
+
+    let version = Version::builder()
+        .proto_ver(70016)
+        .services(services.clone())
+        .time(1700000000)
+        .remote(addr("203.0.113.7:8333"))
+        .local(addr("[2001:db8::1]:8333"))
+        .nonce(0x0123456789abcdef)
+        .user_agent("/btc:0.1.0/")
+        .last_block(800000)
+        .relay(false)
+        .build()
+        .unwrap();
+    let inv = Inv::builder()
+        .element(InventoryElement {
+            kind: InventoryKind::Block,
+            hash: genesis.hash(),
+        })
+        .element(InventoryElement {
+            kind: InventoryKind::WitnessTx,
+            hash: genesis.merkle_root,
+        })
+        .build()
+        .unwrap();
+    let addr = Addr::builder()
+        .addr(1700000000, addr("198.51.100.1:8333"))
+        .addr(1700000001, addr("[2001:db8::2]:18333"))
+        .build()
+        .unwrap();
+
+    vec![
+        (
+            "addr",
+            BitcoinMsg {
+                payload: BitcoinPayload::Addr(addr),
+            },
+        ),
+        (
+            "feefilter",
+            BitcoinMsg {
+                payload: BitcoinPayload::FeeFilter(FeeFilter { feerate: 1000 }),
+            },
+        ),
+        ("getaddr", BitcoinMsg::getaddr()),
+        ("headers", BitcoinMsg::headers(vec![genesis])),
+        (
+            "inv",
+            BitcoinMsg {
+                payload: BitcoinPayload::Inv(inv),
+            },
+        ),
+        ("ping", BitcoinMsg::ping(0x0123456789abcdef)),
+        ("pong", BitcoinMsg::pong(0x0123456789abcdef)),
+        (
+            "sendcmpct",
+            BitcoinMsg {
+                payload: BitcoinPayload::SendCmpct(SendCmpct {
+                    flag: true,
+                    integer: 2,
+                }),
+            },
+        ),
+        (
+            "sendheaders",
+            BitcoinMsg {
+                payload: BitcoinPayload::SendHeaders,
+            },
+        ),
+        ("verack", BitcoinMsg::verack()),
+        (
+            "version",
+            BitcoinMsg {
+                payload: BitcoinPayload::Version(version),
+            },
+        ),
+    ]
+}
+
+#[test]
+fn wire_snapshots() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let messages = messages();
+
+    let mut failures = vec![];
+    for (name, msg) in &messages {
+        let path = dir.join(format!("{name}.hex"));
+        let encoded = msg.encode(Magic::MAINNET);
+        let snapshot = to_snapshot(&encoded);
+
+        if update {
+            fs::write(&path, &snapshot).unwrap();
+            continue;
+        }
+
+        let expected = fs::read_to_string(&path).unwrap_or_default();
+        if snapshot != expected {
+            failures.push(format!(
+                "{name}: encoding changed\nsnapshot:\n{expected}encoded:\n{snapshot}"
+            ));
+            continue;
+        }
+
+        // And what was encoded has to decode to the same thing.
+        let decoded = BitcoinMsg::decode(&mut ByteReader::new(encoded.clone()), Magic::MAINNET);
+        if decoded.encode(Magic::MAINNET) != encoded {
+            failures.push(format!("{name}: does not survive decoding"));
+        }
+    }
+
+    // Snapshots of messages that are gone would pass unnoticed otherwise.
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_stem().unwrap().to_string_lossy();
+        if !messages.iter().any(|(known, _)| *known == name) {
+            failures.push(format!("{}: no message for it", path.display()));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}\nrerun with UPDATE_SNAPSHOTS=1 if the change is meant",
+        failures.join("\n")
+    );
+}
+
+// The header on the first line, then the payload 32 bytes per line, so
+// diffs point at what changed.
+fn to_snapshot(encoded: &[u8]) -> String {
+    let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{b:02x}")).collect() };
+
+    let (header, payload) = encoded.split_at(24);
+    let mut ret = hex(header) + "\n";
+    for line in payload.chunks(32) {
+        ret += &hex(line);
+        ret += "\n";
+    }
+    ret
+}